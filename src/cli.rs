@@ -0,0 +1,170 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// 默认的视窗宽度
+const DEFAULT_WIDTH: f32 = 1280.0;
+
+/// 默认的视窗高度
+const DEFAULT_HEIGHT: f32 = 480.0;
+
+/// 命令行用法说明
+pub const USAGE: &str = "用法：toy-browser [页面地址] [--width 宽度] [--height 高度] [--output 输出png路径] [--selector 选择器] [--layout-json 输出json路径] [--verbose] [--help]
+
+  页面地址         http(s)地址或本地路径，缺省时加载本地的示例页面
+  --width, -w      视窗宽度，默认为1280
+  --height, -H     视窗高度，默认为480；无窗口渲染时为0表示取整个文档的高度
+  --output, -o     不打开窗口，直接将页面渲染为png图片保存到该路径；路径以.svg结尾时保存为SVG
+  --selector, -s   配合--output使用，只渲染第一个命中该选择器的元素
+  --layout-json    不打开窗口，将布局树以JSON格式保存到该路径
+  --verbose, -v    输出布局过程的调试日志
+  --help, -h       输出本用法说明";
+
+/// 命令行参数解析后的配置
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+  /// 页面地址，`None`表示加载本地的示例页面
+  pub url: Option<String>,
+  /// 视窗宽度
+  pub width: f32,
+  /// 视窗高度
+  pub height: f32,
+  /// 无窗口渲染时图片的保存路径，`None`表示打开窗口
+  pub output: Option<PathBuf>,
+  /// 无窗口渲染时只渲染命中的元素，`None`表示渲染整个页面
+  pub selector: Option<String>,
+  /// 布局树JSON的保存路径，用于调试布局
+  pub layout_json: Option<PathBuf>,
+  /// 是否输出调试日志
+  pub verbose: bool,
+  /// 是否只输出用法说明
+  pub help: bool
+}
+
+/// 命令行参数错误
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArgError {
+  /// 选项缺少值，如`--width`后面没有数值
+  MissingValue(String),
+  /// 选项的值不合法
+  InvalidValue(String, String),
+  /// 无法识别的选项
+  UnknownOption(String),
+  /// 多余的位置参数
+  UnexpectedArgument(String)
+}
+
+impl fmt::Display for ArgError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ArgError::MissingValue(option) => write!(f, "选项{option}缺少值"),
+      ArgError::InvalidValue(option, val) => write!(f, "选项{option}的值不合法：{val}"),
+      ArgError::UnknownOption(option) => write!(f, "无法识别的选项：{option}"),
+      ArgError::UnexpectedArgument(arg) => write!(f, "多余的参数：{arg}")
+    }
+  }
+}
+
+impl std::error::Error for ArgError {}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config {
+      url: None,
+      width: DEFAULT_WIDTH,
+      height: DEFAULT_HEIGHT,
+      output: None,
+      selector: None,
+      layout_json: None,
+      verbose: false,
+      help: false
+    }
+  }
+}
+
+/// 解析视窗尺寸，需要是非负的有限数值
+fn parse_size(option: &str, val: String) -> Result<f32, ArgError> {
+  match val.parse::<f32>() {
+    Ok(size) if size.is_finite() && size >= 0.0 => Ok(size),
+    _ => Err(ArgError::InvalidValue(option.to_string(), val))
+  }
+}
+
+/// 解析命令行参数（不包含程序名本身），选项的值可以用空格或`=`分隔，如`--width 800`、`--width=800`；`--verbose`、`--help`不需要值
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Config, ArgError> {
+  let mut config = Config::default();
+  let mut args = args.into_iter();
+  while let Some(arg) = args.next() {
+    if !arg.starts_with('-') || arg == "-" {
+      if config.url.is_some() {
+        return Err(ArgError::UnexpectedArgument(arg));
+      }
+      config.url = Some(arg);
+      continue;
+    }
+    let (option, inline_val) = match arg.split_once('=') {
+      Some((option, val)) => (option.to_string(), Some(val.to_string())),
+      None => (arg, None)
+    };
+    let mut value = || inline_val.clone()
+      .or_else(|| args.next())
+      .ok_or_else(|| ArgError::MissingValue(option.clone()));
+    match option.as_str() {
+      "--width" | "-w" => config.width = parse_size(&option, value()?)?,
+      "--height" | "-H" => config.height = parse_size(&option, value()?)?,
+      "--output" | "-o" => config.output = Some(PathBuf::from(value()?)),
+      "--selector" | "-s" => config.selector = Some(value()?),
+      "--layout-json" => config.layout_json = Some(PathBuf::from(value()?)),
+      "--verbose" | "-v" => config.verbose = true,
+      "--help" | "-h" => config.help = true,
+      _ => return Err(ArgError::UnknownOption(option))
+    }
+  }
+  Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse(args: &[&str]) -> Result<Config, ArgError> {
+    parse_args(args.iter().map(|arg| arg.to_string()))
+  }
+
+  #[test]
+  fn parse_viewport_and_output() {
+    let config = parse(&["page.html", "--width", "800", "-H=600", "-o", "out.png"]).unwrap();
+    assert_eq!(config, Config {
+      url: Some(String::from("page.html")),
+      width: 800.0,
+      height: 600.0,
+      output: Some(PathBuf::from("out.png")),
+      ..Config::default()
+    });
+  }
+
+  #[test]
+  fn parse_defaults_and_flags() {
+    assert_eq!(parse(&[]).unwrap(), Config::default());
+    let config = parse(&["--height=0", "-v", "--selector", ".card", "https://example.com/"]).unwrap();
+    assert_eq!(config.url.as_deref(), Some("https://example.com/"));
+    assert_eq!(config.width, DEFAULT_WIDTH);
+    assert_eq!(config.height, 0.0);
+    assert_eq!(config.selector.as_deref(), Some(".card"));
+    assert!(config.verbose);
+  }
+
+  #[test]
+  fn parse_invalid_args() {
+    assert_eq!(parse(&["--width"]), Err(ArgError::MissingValue(String::from("--width"))));
+    assert_eq!(parse(&["-w", "wide"]), Err(ArgError::InvalidValue(String::from("-w"), String::from("wide"))));
+    assert_eq!(parse(&["--height=-1"]), Err(ArgError::InvalidValue(String::from("--height"), String::from("-1"))));
+    assert_eq!(parse(&["--depth", "2"]), Err(ArgError::UnknownOption(String::from("--depth"))));
+    assert_eq!(parse(&["a.html", "b.html"]), Err(ArgError::UnexpectedArgument(String::from("b.html"))));
+  }
+
+  #[test]
+  fn parse_help() {
+    assert!(parse(&["-h"]).unwrap().help);
+    assert!(parse(&["page.html", "--help"]).unwrap().help);
+  }
+}
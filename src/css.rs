@@ -1,12 +1,21 @@
+use std::collections::HashSet;
+use std::fmt;
 use std::ops::Index;
 
 use ggez::graphics;
+use url::Url;
+
+use crate::fetch;
 
 struct Parser {
   /// 源码字符串
   input: String,
   /// 当前位置（字符位移）
   pos: usize,
+  /// 样式表地址，`@import`的相对地址基于此解析
+  base_url: Option<Url>,
+  /// 已经导入过的样式表地址，避免循环导入
+  imported: HashSet<Url>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -17,12 +26,27 @@ pub struct CSSColor {
   pub a: u8,
 }
 
+/// 根节点默认的字号（像素），也是`rem`及缺少字号上下文的`em`的换算基准
+pub const DEFAULT_FONT_SIZE: f32 = 16.0;
+
+/// 网格轨道数量的上限（与浏览器一致），`repeat()`的重复次数再大也不会超过
+pub const MAX_GRID_TRACKS: usize = 10000;
+
+/// 网格线编号及`span`的上限（与浏览器一致），超出的值会被截断，避免隐式的行轨道无限增长
+pub const MAX_GRID_LINE: i32 = 10000;
+
 /// `CSS`值的单位
 #[derive(Debug, Clone, PartialEq)]
 pub enum CSSUnit {
   Px,
   Em,
-  Rem
+  Rem,
+  /// 百分比
+  Percent,
+  /// 视窗宽度的1%
+  Vw,
+  /// 视窗高度的1%
+  Vh
 }
 
 /// 值类型，增加`Clone trait`可以使自定义值也能拷贝
@@ -31,19 +55,39 @@ pub enum CSSValue {
   Color(CSSColor),
   Keyword(String),
   Length(f32, CSSUnit),
+  /// 无单位的数值，如`line-height: 1.5`
+  Number(f32),
+  /// `var()`引用：自定义属性名及可选的后备值
+  Var(String, Option<Box<CSSValue>>),
+  /// `inherit`：取父级的值
+  Inherit,
+  /// `initial`：取属性的初始值
+  Initial,
+  /// `unset`：可继承的属性等同于`inherit`，否则等同于`initial`
+  Unset,
   Unknown(String)
 }
 
 impl CSSValue {
   /// 将长度单位转为像素长度
   pub fn to_px(&self) -> f32 {
-    if let CSSValue::Length(length, unit) = self {
-      match unit {
+    match self {
+      CSSValue::Length(length, unit) => match unit {
         CSSUnit::Px => *length,
-        _ => *length * 14.0
-      }
-    } else {
-      0.0
+        CSSUnit::Percent | CSSUnit::Vw | CSSUnit::Vh => 0.0, // 缺少参照值时百分比及视窗单位无法计算
+        _ => *length * DEFAULT_FONT_SIZE
+      },
+      CSSValue::Number(num) => *num, // 无单位数值按像素处理
+      _ => 0.0
+    }
+  }
+
+  /// 将长度单位转为像素长度，`em`相对于`font_size`计算，百分比相对于`base`计算
+  pub fn to_px_with(&self, font_size: f32, base: f32) -> f32 {
+    match self {
+      CSSValue::Length(length, CSSUnit::Em) => *length * font_size,
+      CSSValue::Length(length, CSSUnit::Percent) => *length / 100.0 * base,
+      _ => self.to_px()
     }
   }
 }
@@ -73,31 +117,174 @@ impl CSSColor {
   }
 }
 
+/// `filter`中的滤镜函数
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+  /// `grayscale(amount)`，0~1，1为完全灰度
+  Grayscale(f32),
+  /// `brightness(amount)`，1为原始亮度
+  Brightness(f32),
+  /// `blur(radius)`，模糊半径（像素）
+  Blur(f32)
+}
+
+/// `grid-template-columns`中单个轨道的尺寸
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrackSize {
+  /// 固定尺寸（像素）
+  Fixed(f32),
+  /// `fr`单位，按比例分配剩余空间
+  Fraction(f32),
+  /// `auto`，取内容的尺寸
+  Auto
+}
+
+/// `grid-column`/`grid-row`中的网格线
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GridLine {
+  /// 自动放置
+  Auto,
+  /// 网格线编号，从1开始；负数从最后一条网格线倒数
+  Line(i32),
+  /// `span n`，跨越的轨道数
+  Span(usize)
+}
+
+impl Filter {
+  /// 对单个颜色应用滤镜，模糊与颜色无关，原样返回
+  pub fn apply(&self, color: CSSColor) -> CSSColor {
+    let map = |f: &dyn Fn(f32, f32) -> f32| {
+      let luma = 0.2126 * color.r as f32 + 0.7152 * color.g as f32 + 0.0722 * color.b as f32;
+      let channel = |val: u8| f(val as f32, luma).round().clamp(0.0, 255.0) as u8;
+      CSSColor { r: channel(color.r), g: channel(color.g), b: channel(color.b), a: color.a }
+    };
+    match *self {
+      Filter::Grayscale(amount) => map(&|val, luma| val + (luma - val) * amount),
+      Filter::Brightness(amount) => map(&|val, _| val * amount),
+      Filter::Blur(_) => color
+    }
+  }
+}
+
 /// `CSS`键值对
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CSSPropValue {
   pub prop: String,
   pub value: CSSValue,
+  /// 是否带有`!important`
+  pub important: bool,
+}
+
+/// 属性选择器的匹配方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AttrOperator {
+  /// `[attr=val]`，值完全相等
+  Eq,
+  /// `[attr^=val]`，值以`val`开头
+  StartsWith,
+  /// `[attr$=val]`，值以`val`结尾
+  EndsWith,
+  /// `[attr*=val]`，值包含`val`
+  Contains,
+  /// `[attr~=val]`，值按空白拆分后包含`val`
+  SpaceMatch,
+  /// `[attr|=val]`，值等于`val`或以`val-`开头
+  DashMatch
+}
+
+/// 属性选择器，没有`operator`时只判断属性是否存在
+#[derive(Debug, Clone)]
+pub struct AttrSelector {
+  pub name: String,
+  pub operator: Option<AttrOperator>,
+  pub value: Option<String>
+}
+
+impl AttrSelector {
+  /// 判断属性值是否满足选择器
+  pub fn matches(&self, val: &str) -> bool {
+    let expected = self.value.as_deref().unwrap_or("");
+    match self.operator {
+      None => true,
+      Some(AttrOperator::Eq) => val == expected,
+      Some(AttrOperator::StartsWith) => !expected.is_empty() && val.starts_with(expected),
+      Some(AttrOperator::EndsWith) => !expected.is_empty() && val.ends_with(expected),
+      Some(AttrOperator::Contains) => !expected.is_empty() && val.contains(expected),
+      Some(AttrOperator::SpaceMatch) => val.split_whitespace().any(|word| word == expected),
+      Some(AttrOperator::DashMatch) => val == expected || val.starts_with(&format!("{expected}-"))
+    }
+  }
+}
+
+impl fmt::Display for AttrSelector {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let operator = match self.operator {
+      None => return write!(f, "[{}]", self.name),
+      Some(AttrOperator::Eq) => "=",
+      Some(AttrOperator::StartsWith) => "^=",
+      Some(AttrOperator::EndsWith) => "$=",
+      Some(AttrOperator::Contains) => "*=",
+      Some(AttrOperator::SpaceMatch) => "~=",
+      Some(AttrOperator::DashMatch) => "|="
+    };
+    write!(f, "[{}{}\"{}\"]", self.name, operator, self.value.as_deref().unwrap_or(""))
+  }
+}
+
+/// 伪类
+#[derive(Debug, Clone, PartialEq)]
+pub enum PseudoClass {
+  /// `:first-child`
+  FirstChild,
+  /// `:last-child`
+  LastChild,
+  /// `:link`，未访问过的链接
+  Link,
+  /// `:visited`，访问过的链接
+  Visited,
+  /// `:focus`，当前获得焦点的元素
+  Focus,
+  /// 暂不支持的伪类，永远不会命中
+  Unsupported(String)
+}
+
+impl fmt::Display for PseudoClass {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      PseudoClass::FirstChild => write!(f, ":first-child"),
+      PseudoClass::LastChild => write!(f, ":last-child"),
+      PseudoClass::Link => write!(f, ":link"),
+      PseudoClass::Visited => write!(f, ":visited"),
+      PseudoClass::Focus => write!(f, ":focus"),
+      PseudoClass::Unsupported(name) => write!(f, ":{name}")
+    }
+  }
 }
 
 /// 简单选择器（即不包含选择器之间的关系组合用法）
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CSSSimpleSelector {
   /// ID选择器
   pub id: Vec<String>,
   /// class列表
   pub class: Vec<String>,
   /// 标签名
-  pub tag: Option<String>
+  pub tag: Option<String>,
+  /// 是否为通配选择器`*`，通配部分不计入优先级
+  pub universal: bool,
+  /// 属性选择器列表
+  pub attribute_selectors: Vec<AttrSelector>,
+  /// 伪类列表
+  pub pseudo_classes: Vec<PseudoClass>
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CSSRule {
   pub selectors: Vec<CSSSimpleSelector>,
   pub prop_value_set: Vec<CSSPropValue>
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Stylesheet {
   pub rules: Vec<CSSRule>
 }
@@ -105,6 +292,93 @@ pub struct Stylesheet {
 /// 选择器的专一性
 pub type Specificity = (usize, usize, usize);
 
+/// 获取四个方向的简写属性对应的长属性（依次为上、右、下、左），不是简写属性时返回`None`
+pub fn get_box_longhands(prop: &str) -> Option<[String; 4]> {
+  let sides = ["top", "right", "bottom", "left"];
+  match prop {
+    "margin" | "padding" => Some(sides.map(|side| format!("{prop}-{side}"))),
+    "border-width" | "border-color" => {
+      let suffix = &prop["border-".len()..];
+      Some(sides.map(|side| format!("border-{side}-{suffix}")))
+    },
+    _ => None
+  }
+}
+
+/// 从`background`简写属性的值中取出背景色，没有颜色时为初始值（透明）
+///
+/// 目前只支持颜色部分，图片、位置等其它部分直接忽略
+fn get_background_color(source: &str) -> CSSValue {
+  let values: Vec<CSSValue> = source.split_whitespace().map(parse_value_source).collect();
+  if let [val @ (CSSValue::Inherit | CSSValue::Initial | CSSValue::Unset)] = values.as_slice() {
+    return val.clone();
+  }
+  values
+    .into_iter()
+    .find(|val| matches!(val, CSSValue::Color(_) | CSSValue::Var(..)) || matches!(val, CSSValue::Keyword(keyword) if keyword == "currentColor"))
+    .unwrap_or(CSSValue::Color(CSSColor { r: 0, g: 0, b: 0, a: 0 }))
+}
+
+/// 获取`border`及`border-top`等简写属性作用的方向，不是这些简写属性时返回`None`
+fn get_border_sides(prop: &str) -> Option<&'static [&'static str]> {
+  match prop {
+    "border" => Some(&["top", "right", "bottom", "left"]),
+    "border-top" => Some(&["top"]),
+    "border-right" => Some(&["right"]),
+    "border-bottom" => Some(&["bottom"]),
+    "border-left" => Some(&["left"]),
+    _ => None
+  }
+}
+
+/// 从`border`简写属性的值中依次取出宽度、样式及颜色，各部分的顺序任意，值不合法时返回`None`
+///
+/// 省略的部分取初始值：宽度为`medium`，样式为`none`，颜色为`currentColor`；
+/// 目前并不绘制边框样式，但与浏览器一样，样式为`none`/`hidden`（包括省略）时宽度为0
+fn get_border_values(source: &str) -> Option<[CSSValue; 3]> {
+  let border_styles = ["none", "hidden", "dotted", "dashed", "solid", "double", "groove", "ridge", "inset", "outset"];
+  let (mut width, mut style, mut color) = (None, None, None);
+  for token in source.split_whitespace() {
+    let val = match token {
+      "thin" => CSSValue::Length(1.0, CSSUnit::Px),
+      "medium" => CSSValue::Length(3.0, CSSUnit::Px),
+      "thick" => CSSValue::Length(5.0, CSSUnit::Px),
+      _ => parse_value_source(token)
+    };
+    let slot = match &val {
+      CSSValue::Inherit | CSSValue::Initial | CSSValue::Unset if source.trim() == token => {
+        return Some([val.clone(), val.clone(), val]);
+      },
+      CSSValue::Length(..) => &mut width,
+      CSSValue::Color(_) => &mut color,
+      CSSValue::Keyword(keyword) if keyword == "currentColor" => &mut color,
+      CSSValue::Keyword(keyword) | CSSValue::Unknown(keyword) if border_styles.contains(&keyword.as_str()) => &mut style,
+      _ => return None
+    };
+    // 同一部分出现两次也是不合法的
+    if slot.replace(val).is_some() {
+      return None;
+    }
+  }
+  let style = style.unwrap_or(CSSValue::Keyword(String::from("none")));
+  let width = match &style {
+    CSSValue::Keyword(keyword) | CSSValue::Unknown(keyword) if keyword == "none" || keyword == "hidden" => CSSValue::Length(0.0, CSSUnit::Px),
+    _ => width.unwrap_or(CSSValue::Length(3.0, CSSUnit::Px))
+  };
+  Some([width, style, color.unwrap_or(CSSValue::Keyword(String::from("currentColor")))])
+}
+
+/// 解析单独一段值的源码
+fn parse_value_source(source: &str) -> CSSValue {
+  let mut parser = Parser {
+    pos: 0,
+    input: source.to_string(),
+    base_url: None,
+    imported: HashSet::new(),
+  };
+  parser.parse_value()
+}
+
 /// 解析`hex color`单个通道值
 /// 
 /// 相关链接：[How would I store hexedecimal values in a variable? - The Rust Programming Language Forum](https://users.rust-lang.org/t/how-would-i-store-hexedecimal-values-in-a-variable/45545)
@@ -114,8 +388,31 @@ fn parse_single_channel(val: &str) -> u8 {
 
 impl CSSSimpleSelector {
   /// 获取选择器的`specificity`（即优先级）；
+  ///
+  /// 通配符`*`不计入优先级：`*`为(0, 0, 0)，`div`为(0, 0, 1)，`*.active`与`.active`同为(0, 1, 0)
   pub fn get_specificity(&self) -> Specificity {
-    (self.id.len(), self.class.len(), self.tag.iter().count())
+    // 属性选择器、伪类与class的优先级相同
+    let class_count = self.class.len() + self.attribute_selectors.len() + self.pseudo_classes.len();
+    (self.id.len(), class_count, self.tag.iter().count())
+  }
+}
+
+impl fmt::Display for CSSSimpleSelector {
+  /// 还原选择器的源码文本
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let tag = self.tag.as_deref().unwrap_or("");
+    let ids: String = self.id.iter().map(|id| format!("#{id}")).collect();
+    let classes: String = self.class.iter().map(|class| format!(".{class}")).collect();
+    let attrs: String = self.attribute_selectors.iter().map(|attr| attr.to_string()).collect();
+    let pseudo_classes: String = self.pseudo_classes.iter().map(|pseudo| pseudo.to_string()).collect();
+    let text = format!("{tag}{ids}{classes}{attrs}{pseudo_classes}");
+    if self.universal {
+      write!(f, "*{text}")
+    } else if text.is_empty() {
+      write!(f, "*")
+    } else {
+      write!(f, "{text}")
+    }
   }
 }
 
@@ -150,7 +447,7 @@ impl Parser {
   }
 
   /// 连续消耗字符直至`test`函数返回`false`
-  fn consume_while<F>(&mut self, test: F) -> String where F: Fn(char) -> bool {
+  fn consume_while<F>(&mut self, mut test: F) -> String where F: FnMut(char) -> bool {
     let mut res = String::new();
     while !self.eof() && test(self.next_char()) {
       res.push(self.consume_char());
@@ -163,73 +460,225 @@ impl Parser {
     self.consume_while(char::is_whitespace);
   }
 
-  /// 解析标识符：字母数字且不能以数字开头
-  fn parse_identifier(&mut self) -> String {
-    if let '0'..='9' | '-' = self.next_char() {
-      panic!("标识符不能以数字、'-'开头")
+  /// 解析标识符：字母数字且不能以数字开头；自定义属性以`--`开头
+  ///
+  /// 不是合法的标识符（以数字、`-`开头或者为空）时返回`None`
+  fn parse_identifier(&mut self) -> Option<String> {
+    if self.eof() {
+      return None;
+    }
+    let is_custom = self.starts_with("--");
+    if let ('0'..='9' | '-', false) = (self.next_char(), is_custom) {
+      return None;
+    }
+    let identifier = self.consume_while(|c| matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | '-'));
+    if identifier.is_empty() {
+      None
     } else {
-      self.consume_while(|c| if let 'a'..='z' | 'A'..='Z' | '0'..='9' | '-' = c {
-        true
-      } else {
-        false
-      })
+      Some(identifier)
+    }
+  }
+
+  /// 跳过当前位置开始的`{}`块（包括其中嵌套的块），源码提前结束时停在末尾
+  fn skip_block(&mut self) {
+    let mut depth = 0;
+    while !self.eof() {
+      match self.consume_char() {
+        '{' => depth += 1,
+        '}' if depth <= 1 => break,
+        '}' => depth -= 1,
+        _ => {}
+      }
     }
   }
 
   /// 解析长度类型的值
   fn parse_value_length(&mut self) -> CSSValue {
-    let num = self.consume_while(|c| if let '0'..='9' | '.' = c {
+    // 可选的正负号
+    let sign = if let '-' | '+' = self.next_char() {
+      self.consume_char().to_string()
+    } else {
+      String::new()
+    };
+    let num = sign + &self.consume_while(|c| if let '0'..='9' | '.' = c {
       true
     } else {
       false
     });
-    let unit = self.consume_while(|c| c != ';');
+    let unit = self.consume_while(|c| c != ';' && c != '}');
+    let unit = unit.trim();
     let mut css_unit = CSSUnit::Px;
-    if unit == "px" {
+    if unit.is_empty() {
+      return CSSValue::Number(num.parse::<f32>().unwrap_or(0.0));
+    } else if unit == "px" {
       css_unit = CSSUnit::Px;
     } else if unit == "em" {
       css_unit = CSSUnit::Em;
     } else if unit == "rem" {
       css_unit = CSSUnit::Rem;
+    } else if unit == "%" {
+      css_unit = CSSUnit::Percent;
+    } else if unit == "vw" {
+      css_unit = CSSUnit::Vw;
+    } else if unit == "vh" {
+      css_unit = CSSUnit::Vh;
     }
     // 关于字符串转数字：https://stackoverflow.com/questions/27043268/convert-a-string-to-int
     CSSValue::Length(num.parse::<f32>().unwrap_or(0.0), css_unit)
   }
 
-  /// 解析`hex color`类型的值
+  /// 解析`hex color`类型的值，支持`#rgb`、`#rgba`、`#rrggbb`、`#rrggbbaa`四种写法
   fn parse_hex_color(&mut self) -> CSSValue {
     let hex = self.consume_while(|c| if let '0'..='9' | 'a'..='f' | 'A'..='F' = c {
       true
     } else {
       false
     });
-    assert!(hex.len() == 6, "目前只实现6位hex color解析");
-    let r = parse_single_channel(&hex[0..2]);
-    let g = parse_single_channel(&hex[2..4]);
-    let b = parse_single_channel(&hex[4..6]);
+    // 缩写形式的每一位重复一次即为完整形式，如`#f008`等价于`#ff000088`
+    let channels: Vec<u8> = match hex.len() {
+      3 | 4 => hex.chars().map(|c| parse_single_channel(&c.to_string().repeat(2))).collect(),
+      6 | 8 => (0..hex.len()).step_by(2).map(|idx| parse_single_channel(&hex[idx..idx + 2])).collect(),
+      _ => return CSSValue::Unknown(format!("#{hex}"))
+    };
     CSSValue::Color(CSSColor {
-      r,
-      g,
-      b,
-      a: 255
+      r: channels[0],
+      g: channels[1],
+      b: channels[2],
+      a: channels.get(3).copied().unwrap_or(255)
     })
   }
 
+  /// 解析`var(--name, fallback)`，名称不合法时整个值保持原样（不会生效）
+  fn parse_var(&mut self) -> CSSValue {
+    assert!(self.starts_with("var("));
+    self.pos += "var(".len();
+    self.consume_whitespace();
+    let Some(name) = self.parse_identifier() else {
+      return CSSValue::Unknown(self.input.clone());
+    };
+    self.consume_whitespace();
+    let mut fallback = None;
+    if !self.eof() && self.next_char() == ',' {
+      self.consume_char();
+      self.consume_whitespace();
+      // 后备值本身也可能包含`var()`，需要按括号配对找到结尾
+      let mut depth = 0;
+      let source = self.consume_while(|c| match c {
+        '(' => { depth += 1; true },
+        ')' if depth == 0 => false,
+        ')' => { depth -= 1; true },
+        _ => true
+      });
+      let mut parser = Parser {
+        pos: 0,
+        input: source.trim().to_string(),
+        base_url: None,
+        imported: HashSet::new(),
+      };
+      if !parser.eof() {
+        fallback = Some(Box::new(parser.parse_value()));
+      }
+    }
+    if !self.eof() && self.next_char() == ')' {
+      self.consume_char();
+    }
+    CSSValue::Var(name, fallback)
+  }
+
   /// 解析单个`CSS`值
   fn parse_value(&mut self) -> CSSValue {
+    if self.starts_with("var(") {
+      return self.parse_var();
+    }
     let keyword_list: Vec<&str> = vec!(
       "block",
       "none",
-      "inline"
+      "inline",
+      "inline-block",
+      "left",
+      "right",
+      "center",
+      "justify",
+      "both",
+      "auto",
+      "max-content",
+      "min-content",
+      "normal",
+      "hidden",
+      "nowrap",
+      "pre",
+      "ellipsis",
+      "baseline",
+      "middle",
+      "top",
+      "bottom",
+      "sub",
+      "super",
+      "text-top",
+      "text-bottom",
+      "visible",
+      "collapse",
+      "flex",
+      "grid",
+      "flex-start",
+      "flex-end",
+      "space-between",
+      "space-around",
+      "space-evenly",
+      "stretch",
+      "wrap",
+      "wrap-reverse",
+      "underline",
+      "line-through",
+      "static",
+      "fixed",
+      "sticky",
+      "row",
+      "column",
+      "clip",
+      "scroll",
+      "xx-small",
+      "x-small",
+      "small",
+      "medium",
+      "large",
+      "x-large",
+      "xx-large",
+      "xxx-large",
+      "smaller",
+      "larger"
     );
+    // 数值可以带正负号，但`-`开头的也可能是关键字（如`-webkit-box`），需要再往后看一个字符
+    let mut chars = self.cur_str().chars();
+    let is_number = match chars.next() {
+      Some('0'..='9' | '.') => true,
+      Some('-' | '+') => matches!(chars.next(), Some('0'..='9' | '.')),
+      _ => false
+    };
+    if is_number {
+      return self.parse_value_length();
+    }
     match self.next_char() {
-      '0'..='9' => self.parse_value_length(),
       '#' => {
         self.consume_char();
         self.parse_hex_color()
       },
       _ => {
-        let val = self.consume_while(|c| c != ';');
+        let val = self.consume_while(|c| c != ';' && c != '}').trim().to_string();
+        // `transparent`即完全透明的黑色
+        if val == "transparent" {
+          return CSSValue::Color(CSSColor { r: 0, g: 0, b: 0, a: 0 });
+        }
+        // 关键字不区分大小写，统一为`currentColor`
+        if val.eq_ignore_ascii_case("currentcolor") {
+          return CSSValue::Keyword(String::from("currentColor"));
+        }
+        match val.as_str() {
+          "inherit" => return CSSValue::Inherit,
+          "initial" => return CSSValue::Initial,
+          "unset" => return CSSValue::Unset,
+          _ => {}
+        }
         if keyword_list.contains(&&*val) {
           CSSValue::Keyword(val)
         } else {
@@ -239,42 +688,147 @@ impl Parser {
     }
   }
 
-  /// 解析单个`CSS`键值对
-  fn parse_prop_value(&mut self) -> CSSPropValue {
+  /// 解析单个`CSS`键值对，源码在键值对中途结束时返回`None`
+  ///
+  /// 四个方向的简写属性（如`margin`）会展开为对应的长属性，`!important`同样作用于展开后的每个长属性；
+  /// `background`展开为`background-color`；`border`及`border-top`等展开为各个方向的宽度、样式及颜色；
+  /// 属性名或值不合法（如缺少`:`、`!important`不在末尾）时整个声明无效，返回空列表
+  fn parse_prop_value(&mut self) -> Option<Vec<CSSPropValue>> {
     let prop = self.parse_identifier();
-    assert!(self.consume_char() == ':');
     self.consume_whitespace();
-    let value = self.parse_value();
-    assert!(self.consume_char() == ';');
-    CSSPropValue {
-      prop,
-      value,
+    if self.eof() {
+      return None;
     }
+    let prop = match prop {
+      Some(prop) if self.next_char() == ':' => prop,
+      _ => {
+        // 跳过整个无效的声明，继续解析下一个
+        self.consume_while(|c| c != ';' && c != '}');
+        if !self.eof() && self.next_char() == ';' {
+          self.consume_char();
+        }
+        return Some(vec![]);
+      }
+    };
+    self.consume_char();
+    self.consume_whitespace();
+    if self.eof() {
+      return None;
+    }
+    let source = self.consume_while(|c| c != ';' && c != '}');
+    // 规则内最后一个键值对的分号可以省略
+    if !self.eof() && self.next_char() == ';' {
+      self.consume_char();
+    }
+    // `!important`只能出现在值的末尾
+    let (source, important) = match source.trim_end().strip_suffix("important").and_then(|rest| rest.trim_end().strip_suffix('!')) {
+      Some(rest) => (rest.trim(), true),
+      None => (source.trim(), false)
+    };
+    if source.is_empty() || source.contains('!') {
+      return Some(vec![]);
+    }
+    // `background`简写属性展开为`background-color`，与其它声明一样按照层叠顺序覆盖
+    if prop == "background" {
+      return Some(vec![CSSPropValue {
+        prop: String::from("background-color"),
+        value: get_background_color(source),
+        important
+      }]);
+    }
+    // 包含`var()`的`border`简写属性同样无法拆分，保持原样（不会生效）
+    if let (Some(sides), false) = (get_border_sides(&prop), source.contains("var(")) {
+      let Some(values) = get_border_values(source) else {
+        return Some(vec![]);
+      };
+      return Some(sides
+        .iter()
+        .flat_map(|side| ["width", "style", "color"].map(|suffix| format!("border-{side}-{suffix}")))
+        .zip(values.iter().cycle())
+        .map(|(prop, value)| CSSPropValue {
+          prop,
+          value: value.clone(),
+          important
+        })
+        .collect());
+    }
+    // 轨道列表及网格线由多个值组成（如`1fr 200px`、`2 / 4`），保留源码在使用时再解析
+    if matches!(&*prop, "grid-template-columns" | "grid-column" | "grid-row") && !source.contains("var(") {
+      let value = match parse_value_source(source) {
+        CSSValue::Length(..) | CSSValue::Number(_) | CSSValue::Unknown(_) => CSSValue::Unknown(source.to_string()),
+        value => value
+      };
+      return Some(vec![CSSPropValue { prop, value, important }]);
+    }
+    let longhands = match get_box_longhands(&prop) {
+      // 包含`var()`的简写属性无法在解析阶段拆分，保持原样
+      Some(longhands) if !source.contains("var(") => longhands,
+      _ => return Some(vec![CSSPropValue {
+        prop,
+        value: parse_value_source(source),
+        important
+      }])
+    };
+    let values: Vec<CSSValue> = source.split_whitespace().map(parse_value_source).collect();
+    // 按照值的个数分配到上、右、下、左四个方向
+    let indexes = match values.len() {
+      1 => [0, 0, 0, 0],
+      2 => [0, 1, 0, 1],
+      3 => [0, 1, 2, 1],
+      4 => [0, 1, 2, 3],
+      _ => return Some(vec![])
+    };
+    Some(longhands
+      .into_iter()
+      .zip(indexes)
+      .map(|(prop, idx)| CSSPropValue {
+        prop,
+        value: values[idx].clone(),
+        important
+      })
+      .collect())
   }
 
   /// 解析一个规则内的所有键值对
+  ///
+  /// 规则没有闭合就到了源码末尾时，保留已经解析的键值对
   fn parse_prop_value_set(&mut self) -> Vec<CSSPropValue> {
-    assert!(self.consume_char() == '{');
     let mut sets = vec!();
+    if self.eof() {
+      return sets;
+    }
+    assert!(self.consume_char() == '{');
     loop {
       self.consume_whitespace();
+      if self.eof() {
+        return sets;
+      }
       if self.next_char() == '}' {
         break;
       }
-      sets.push(self.parse_prop_value());
+      match self.parse_prop_value() {
+        Some(mut prop_values) => sets.append(&mut prop_values),
+        None => return sets
+      }
     }
     assert!(self.consume_char() == '}');
     sets
   }
 
-  /// 解析单个选择器
-  fn parse_simple_selector(&mut self) -> CSSSimpleSelector {
+  /// 解析单个选择器，遇到不支持的字符（如`>`等组合器）或不合法的名称时返回`None`
+  fn parse_simple_selector(&mut self) -> Option<CSSSimpleSelector> {
     let mut selector = CSSSimpleSelector {
       id: vec!(),
       class: vec!(),
       tag: None,
+      universal: false,
+      attribute_selectors: vec!(),
+      pseudo_classes: vec!(),
     };
     loop {
+      if self.eof() {
+        break;
+      }
       let c = self.next_char();
       if c == '{' || c == ',' || c.is_whitespace() {
         break;
@@ -282,31 +836,50 @@ impl Parser {
       match self.next_char() {
         '#' => {
           self.consume_char();
-          selector.id.push(self.parse_identifier());
+          selector.id.push(self.parse_identifier()?);
         },
         '.' => {
           self.consume_char();
-          selector.class.push(self.parse_identifier());
+          selector.class.push(self.parse_identifier()?);
         },
+        // `*`可以与其它简单选择器组合，如`*.active`、`*[href]`
         '*' => {
           self.consume_char();
+          selector.universal = true;
+        },
+        '[' => {
+          selector.attribute_selectors.push(self.parse_attr_selector()?);
+        },
+        ':' => {
+          // 伪元素（`::before`等）同样视为暂不支持的伪类
+          let colons = self.consume_while(|c| c == ':');
+          let name = self.parse_identifier()?;
+          selector.pseudo_classes.push(match (colons.len(), name.as_str()) {
+            (1, "first-child") => PseudoClass::FirstChild,
+            (1, "last-child") => PseudoClass::LastChild,
+            (1, "link") => PseudoClass::Link,
+            (1, "visited") => PseudoClass::Visited,
+            (1, "focus") => PseudoClass::Focus,
+            _ => PseudoClass::Unsupported(name)
+          });
         },
         'a'..='z' => {
-          selector.tag = Some(self.parse_identifier());
+          selector.tag = Some(self.parse_identifier()?);
         },
-        _ => {
-          panic!("暂不支持的字符！");
-        }
+        _ => return None
       }
     }
-    selector
+    Some(selector)
   }
 
-  /// 解析一个规则对应的所有的选择器
-  fn parse_selectors(&mut self) -> Vec<CSSSimpleSelector> {
+  /// 解析一个规则对应的所有的选择器，其中任意一个选择器不合法时返回`None`
+  fn parse_selectors(&mut self) -> Option<Vec<CSSSimpleSelector>> {
     let mut selectors = vec!();
     loop {
       self.consume_whitespace();
+      if self.eof() {
+        break;
+      }
       let c = self.next_char();
       if c == '{' {
         break;
@@ -315,31 +888,155 @@ impl Parser {
         self.consume_char();
         self.consume_whitespace();
       }
-      selectors.push(self.parse_simple_selector());
+      selectors.push(self.parse_simple_selector()?);
+    }
+    assert!(self.eof() || self.next_char() == '{');
+    Some(selectors)
+  }
+
+  /// 解析属性选择器，如`[type]`、`[type="text"]`、`[lang|=en]`、`[href$=".pdf"]`；属性名或运算符不合法时返回`None`
+  fn parse_attr_selector(&mut self) -> Option<AttrSelector> {
+    assert_eq!(self.consume_char(), '[');
+    self.consume_whitespace();
+    let name = self.parse_identifier()?;
+    self.consume_whitespace();
+    let operator = match self.consume_while(|c| c != ']' && c != '"' && c != '\'' && !c.is_alphanumeric() && !c.is_whitespace()).as_str() {
+      "" => None,
+      "=" => Some(AttrOperator::Eq),
+      "^=" => Some(AttrOperator::StartsWith),
+      "$=" => Some(AttrOperator::EndsWith),
+      "*=" => Some(AttrOperator::Contains),
+      "~=" => Some(AttrOperator::SpaceMatch),
+      "|=" => Some(AttrOperator::DashMatch),
+      _ => return None
+    };
+    self.consume_whitespace();
+    // 值可以带引号，也可以是不带引号的标识符
+    let value = match (operator, self.eof()) {
+      (None, _) | (_, true) => None,
+      (Some(_), false) => match self.next_char() {
+        quote @ ('"' | '\'') => {
+          self.consume_char();
+          let val = self.consume_while(|c| c != quote);
+          if !self.eof() {
+            self.consume_char();
+          }
+          Some(val)
+        },
+        _ => Some(self.consume_while(|c| c != ']' && !c.is_whitespace()))
+      }
+    };
+    self.consume_while(|c| c != ']');
+    if !self.eof() {
+      self.consume_char();
     }
-    assert!(self.next_char() == '{');
-    selectors
+    Some(AttrSelector {
+      name,
+      operator,
+      value
+    })
   }
 
-  /// 解析单个`css`规则
-  fn parse_rule(&mut self) -> CSSRule {
-    let selectors = self.parse_selectors();
+  /// 解析单个`css`规则，选择器不合法时跳过整个规则（包括其中的键值对），返回`None`
+  fn parse_rule(&mut self) -> Option<CSSRule> {
+    let Some(selectors) = self.parse_selectors() else {
+      self.consume_while(|c| c != '{');
+      self.skip_block();
+      return None;
+    };
     let sets = self.parse_prop_value_set();
-    CSSRule {
+    Some(CSSRule {
       selectors,
       prop_value_set: sets
+    })
+  }
+
+  /// 解析`@import`的地址，支持`"..."`、`'...'`及`url(...)`三种写法；地址之后的媒体查询等内容直接忽略
+  fn parse_import_href(&mut self) -> String {
+    self.pos += "@import".len();
+    self.consume_whitespace();
+    let is_url = self.starts_with("url(");
+    if is_url {
+      self.pos += "url(".len();
+      self.consume_whitespace();
+    }
+    let href = match self.cur_str().chars().next() {
+      Some(quote @ ('"' | '\'')) => {
+        self.consume_char();
+        let href = self.consume_while(|c| c != quote);
+        if !self.eof() {
+          self.consume_char();
+        }
+        href
+      },
+      _ => self.consume_while(|c| !c.is_whitespace() && c != ')' && c != ';')
+    };
+    self.consume_while(|c| c != ';');
+    if !self.eof() {
+      self.consume_char();
+    }
+    href.trim().to_string()
+  }
+
+  /// 加载并解析`@import`引用的样式表，返回其中的规则；重复导入或加载失败时返回空列表
+  fn parse_import(&mut self) -> Vec<CSSRule> {
+    let href = self.parse_import_href();
+    let url = match &self.base_url {
+      Some(base_url) => base_url.join(&href).map_err(|_| fetch::FetchError::InvalidUrl(href.clone())),
+      None => fetch::parse_url(&href)
+    };
+    let url = match url {
+      Ok(url) => url,
+      Err(err) => {
+        log::warn!("无法导入样式表{href}：{err}");
+        return vec!();
+      }
+    };
+    if !self.imported.insert(url.clone()) {
+      log::warn!("样式表{url}已经导入过，忽略");
+      return vec!();
     }
+    let source = match fetch::fetch_stylesheet(&url) {
+      Ok(source) => source,
+      Err(err) => {
+        log::warn!("无法导入样式表{href}：{err}");
+        return vec!();
+      }
+    };
+    // 被导入的样式表共享导入记录，这样嵌套的循环导入也能被发现
+    let mut parser = Parser {
+      pos: 0,
+      input: source,
+      base_url: Some(url),
+      imported: std::mem::take(&mut self.imported),
+    };
+    let stylesheet = parser.parse_stylesheet();
+    self.imported = parser.imported;
+    stylesheet.rules
   }
 
   /// 解析一个样式表
   fn parse_stylesheet(&mut self) -> Stylesheet {
     let mut rules = vec!();
+    // 是否已经出现过普通规则
+    let mut has_rules = false;
     loop {
       self.consume_whitespace();
       if self.eof() {
         break;
       }
-      rules.push(self.parse_rule());
+      if self.starts_with("@import") {
+        // `@import`只能出现在其它规则之前，之后出现的直接忽略
+        if has_rules {
+          let href = self.parse_import_href();
+          log::warn!("@import {href}出现在其它规则之后，忽略");
+        } else {
+          rules.append(&mut self.parse_import());
+        }
+        continue;
+      }
+      rules.extend(self.parse_rule());
+      has_rules = true;
     }
     Stylesheet {
       rules
@@ -349,19 +1046,236 @@ impl Parser {
 
 /// 解析`css`样式表结构
 pub fn parse(source: String) -> Stylesheet {
+  parse_with_base(source, None)
+}
+
+/// 解析`css`样式表结构，`@import`的相对地址基于`base_url`（样式表自身的地址）解析
+pub fn parse_with_base(source: String, base_url: Option<Url>) -> Stylesheet {
   let mut parser = Parser {
     pos: 0,
     input: source,
+    imported: base_url.iter().cloned().collect(),
+    base_url,
   };
   parser.parse_stylesheet()
 }
 
+/// 解析选择器列表（如`div.foo, #bar`），不合法时返回空列表，即不命中任何元素
+pub fn parse_selectors(source: String) -> Vec<CSSSimpleSelector> {
+  let source = source + "{";
+  let mut parser = Parser {
+    pos: 0,
+    input: source,
+    base_url: None,
+    imported: HashSet::new(),
+  };
+  parser.parse_selectors().unwrap_or_default()
+}
+
+/// 解析`filter`的值，如`grayscale(1) blur(2px)`；无法识别的滤镜函数直接忽略
+pub fn parse_filters(source: &str) -> Vec<Filter> {
+  source
+    .split(')')
+    .filter_map(|func| {
+      let (name, arg) = func.split_once('(')?;
+      let (name, arg) = (name.trim(), arg.trim());
+      // 参数可以是数值或百分比，省略时取默认值
+      let default_amount = if name == "blur" { 0.0 } else { 1.0 };
+      let amount = if arg.is_empty() {
+        default_amount
+      } else {
+        match parse_value_source(arg) {
+          CSSValue::Number(num) => num,
+          CSSValue::Length(length, CSSUnit::Percent) => length / 100.0,
+          length @ CSSValue::Length(..) => length.to_px(),
+          _ => default_amount
+        }
+      };
+      match name {
+        "grayscale" => Some(Filter::Grayscale(amount.clamp(0.0, 1.0))),
+        "brightness" => Some(Filter::Brightness(amount.max(0.0))),
+        "blur" => Some(Filter::Blur(amount.max(0.0))),
+        _ => None
+      }
+    })
+    .collect()
+}
+
+/// 按空白拆分值，括号内的空白不拆分（如`repeat(3, 1fr)`）
+fn split_value_tokens(source: &str) -> Vec<&str> {
+  let mut tokens = vec![];
+  let mut depth = 0;
+  let mut start = None;
+  for (idx, c) in source.char_indices() {
+    match c {
+      '(' => depth += 1,
+      ')' => depth -= 1,
+      c if c.is_whitespace() && depth == 0 => {
+        if let Some(start) = start.take() {
+          tokens.push(&source[start..idx]);
+        }
+        continue;
+      },
+      _ => {}
+    }
+    start.get_or_insert(idx);
+  }
+  if let Some(start) = start {
+    tokens.push(&source[start..]);
+  }
+  tokens
+}
+
+/// 解析`grid-template-columns`的轨道列表，如`1fr 200px 1fr`或`repeat(3, 1fr)`
+///
+/// 百分比暂不支持，与无法识别的轨道一样按`auto`处理，保证列数不变；轨道总数不超过`MAX_GRID_TRACKS`
+pub fn parse_track_list(source: &str) -> Vec<TrackSize> {
+  let mut tracks = vec![];
+  for token in split_value_tokens(source) {
+    if let Some(args) = token.strip_prefix("repeat(").and_then(|rest| rest.strip_suffix(')')) {
+      let Some((count, list)) = args.split_once(',') else {
+        continue;
+      };
+      let count = count.trim().parse::<usize>().unwrap_or(0);
+      let list = parse_track_list(list);
+      let rest = MAX_GRID_TRACKS.saturating_sub(tracks.len());
+      tracks.extend(list.iter().cycle().take(list.len().saturating_mul(count).min(rest)));
+      continue;
+    }
+    if let Some(fraction) = token.strip_suffix("fr").and_then(|num| num.parse::<f32>().ok()) {
+      tracks.push(TrackSize::Fraction(fraction.max(0.0)));
+      continue;
+    }
+    tracks.push(match parse_value_source(token) {
+      CSSValue::Length(_, CSSUnit::Percent) => TrackSize::Auto,
+      length @ (CSSValue::Length(..) | CSSValue::Number(_)) => TrackSize::Fixed(length.to_px().max(0.0)),
+      _ => TrackSize::Auto
+    });
+  }
+  tracks.truncate(MAX_GRID_TRACKS);
+  tracks
+}
+
+/// 解析`grid-column`/`grid-row`的起止网格线，如`2 / 4`、`1 / span 2`、`span 3`；省略结束网格线时为`auto`
+///
+/// 网格线编号截断到`±MAX_GRID_LINE`，`span`截断到`1..=MAX_GRID_LINE`
+pub fn parse_grid_placement(source: &str) -> (GridLine, GridLine) {
+  let max_line = MAX_GRID_LINE as i64;
+  let parse_line = |source: &str| {
+    let source = source.trim();
+    if let Some(span) = source.strip_prefix("span") {
+      let span = span.trim().parse::<i64>().unwrap_or(1).clamp(1, max_line);
+      return GridLine::Span(span as usize);
+    }
+    match source.parse::<i64>() {
+      Ok(line) if line != 0 => GridLine::Line(line.clamp(-max_line, max_line) as i32),
+      _ => GridLine::Auto
+    }
+  };
+  match source.split_once('/') {
+    Some((start, end)) => (parse_line(start), parse_line(end)),
+    None => (parse_line(source), GridLine::Auto)
+  }
+}
+
 /// 解析内联样式
 pub fn parse_inline_style(style: String) -> Vec<CSSPropValue> {
   let source = "{".to_string() + &style + "}";
   let mut parser = Parser {
     pos: 0,
     input: source,
+    base_url: None,
+    imported: HashSet::new(),
   };
   parser.parse_prop_value_set()
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn get_props(rule: &CSSRule) -> Vec<&str> {
+    rule.prop_value_set.iter().map(|prop_value| prop_value.prop.as_str()).collect()
+  }
+
+  #[test]
+  fn skip_rules_with_invalid_selectors() {
+    let stylesheet = parse(String::from("div > p { color: red; } 1st { color: red; } [lang~~=en] { color: red; } A { color: red; } .ok { color: blue; }"));
+    assert_eq!(stylesheet.rules.len(), 1);
+    assert_eq!(stylesheet.rules[0].selectors[0].class, vec![String::from("ok")]);
+    assert!(parse_selectors(String::from("ul > li")).is_empty());
+  }
+
+  #[test]
+  fn skip_invalid_declarations() {
+    let stylesheet = parse(String::from("p { -webkit-appearance: none; color red; 2x: 1px; : 1px; color: blue; }"));
+    assert_eq!(get_props(&stylesheet.rules[0]), vec!["color"]);
+    assert_eq!(parse_inline_style(String::from("width 10px; height: 20px")).len(), 1);
+  }
+
+  #[test]
+  fn truncated_import_does_not_panic() {
+    assert!(parse(String::from("@import")).rules.is_empty());
+    assert!(parse(String::from("@import \"unterminated")).rules.is_empty());
+  }
+
+  #[test]
+  fn parse_negative_length() {
+    let prop_values = parse_inline_style(String::from("margin: -8px"));
+    assert_eq!(prop_values.len(), 4);
+    assert!(prop_values.iter().all(|prop_value| prop_value.value == CSSValue::Length(-8.0, CSSUnit::Px)));
+  }
+
+  #[test]
+  fn keep_unterminated_rule() {
+    let stylesheet = parse(String::from("p { color: red; } div { width: 10px"));
+    assert_eq!(stylesheet.rules.len(), 2);
+    assert_eq!(stylesheet.rules[1].selectors[0].tag.as_deref(), Some("div"));
+    assert_eq!(stylesheet.rules[1].prop_value_set[0].value, CSSValue::Length(10.0, CSSUnit::Px));
+  }
+
+  #[test]
+  fn parse_hex_alpha() {
+    let get_color = |source: &str| match &parse_inline_style(format!("color: {source}"))[0].value {
+      CSSValue::Color(color) => *color,
+      value => panic!("{value:?}")
+    };
+    assert_eq!(get_color("#ff000080"), CSSColor { r: 255, g: 0, b: 0, a: 128 });
+    assert_eq!(get_color("#f008"), CSSColor { r: 255, g: 0, b: 0, a: 136 });
+  }
+
+  #[test]
+  fn important_shorthand_marks_longhands() {
+    let prop_values = parse_inline_style(String::from("margin: 5px !important"));
+    assert_eq!(prop_values.iter().map(|prop_value| prop_value.prop.as_str()).collect::<Vec<_>>(), vec!["margin-top", "margin-right", "margin-bottom", "margin-left"]);
+    assert!(prop_values.iter().all(|prop_value| prop_value.important && prop_value.value == CSSValue::Length(5.0, CSSUnit::Px)));
+  }
+
+  #[test]
+  fn universal_selector_has_no_specificity() {
+    let universal = &parse_selectors(String::from("*"))[0];
+    assert!(universal.universal);
+    assert_eq!(universal.get_specificity(), (0, 0, 0));
+    assert_eq!(parse_selectors(String::from("div"))[0].get_specificity(), (0, 0, 1));
+    let active = &parse_selectors(String::from("*.active"))[0];
+    assert!(active.universal);
+    assert_eq!(active.class, vec![String::from("active")]);
+    assert_eq!(active.get_specificity(), (0, 1, 0));
+  }
+
+  #[test]
+  fn cap_repeated_tracks() {
+    assert_eq!(parse_track_list("repeat(3, 1fr 10px)").len(), 6);
+    let tracks = parse_track_list("100px repeat(1000000000, 10px)");
+    assert_eq!(tracks.len(), MAX_GRID_TRACKS);
+    assert_eq!(tracks[0], TrackSize::Fixed(100.0));
+    assert_eq!(parse_track_list("repeat(1000000000, repeat(1000000000, auto))").len(), MAX_GRID_TRACKS);
+  }
+
+  #[test]
+  fn clamp_grid_lines() {
+    assert_eq!(parse_grid_placement("1000000000"), (GridLine::Line(MAX_GRID_LINE), GridLine::Auto));
+    assert_eq!(parse_grid_placement("-99999999999 / span 1000000000"), (GridLine::Line(-MAX_GRID_LINE), GridLine::Span(MAX_GRID_LINE as usize)));
+    assert_eq!(parse_grid_placement("span 0 / 2"), (GridLine::Span(1), GridLine::Line(2)));
+  }
+}
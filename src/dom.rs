@@ -2,7 +2,9 @@ use std::collections::{
   HashMap,
   HashSet
 };
+use std::sync::Arc;
 use crate::css::Stylesheet;
+use url::Url;
 
 pub type AttrMap = HashMap<String, String>;
 #[derive(Debug)]
@@ -22,6 +24,10 @@ pub enum NodeType {
   Element(ElementData),
   Comment(String),
   Style(StyleData),
+  /// `script`元素，存放原样的脚本源码
+  Script(String),
+  /// 文档类型声明，存放声明的名称（如`html`）
+  Doctype(String),
 }
 #[derive(Debug)]
 pub struct Node {
@@ -32,10 +38,29 @@ pub struct Node {
 #[derive(Debug)]
 pub struct Document {
   pub root: Node,
-  pub stylesheets: Vec<Stylesheet>
+  /// 按层叠顺序排列的样式表，内容相同的样式表在多次解析之间共享
+  pub stylesheets: Vec<Arc<Stylesheet>>,
+  /// 页面地址，用于解析链接等相对地址
+  pub url: Option<Url>,
+  /// 文档类型声明的名称
+  pub doctype: Option<String>,
+  /// `<head>`中`<title>`的文本
+  pub title: Option<String>,
+  /// `<head>`中`<meta charset>`声明的编码
+  pub charset: Option<String>
 }
 
 impl ElementData {
+  /// 获取属性值，属性不存在时返回`None`
+  pub fn get_attribute(&self, name: &str) -> Option<&str> {
+    self.attrs.get(name).map(String::as_str)
+  }
+
+  /// 设置属性值，属性已存在时覆盖原来的值
+  pub fn set_attribute(&mut self, name: &str, value: &str) {
+    self.attrs.insert(name.to_string(), value.to_string());
+  }
+
   /// 获取元素`id`列表
   pub fn ids(&self) -> HashSet<&str> {
     match self.attrs.get("id") {
@@ -51,6 +76,72 @@ impl ElementData {
       None => HashSet::new()
     }
   }
+
+  /// 是否可以通过`Tab`键聚焦：带`href`的链接、表单控件，以及`tabindex`不小于0的元素
+  pub fn is_focusable(&self) -> bool {
+    if let Some(tab_index) = self.attrs.get("tabindex").and_then(|val| val.trim().parse::<i32>().ok()) {
+      return tab_index >= 0;
+    }
+    match self.tag_name.as_str() {
+      "a" => self.attrs.contains_key("href"),
+      "input" | "button" | "select" | "textarea" => !self.attrs.contains_key("disabled"),
+      _ => false
+    }
+  }
+}
+
+impl Node {
+  /// 在子节点列表的末尾追加节点
+  pub fn append_child(&mut self, child: Node) {
+    self.children.push(child);
+  }
+
+  /// 移除并返回第`index`个子节点，越界时返回`None`
+  pub fn remove_child(&mut self, index: usize) -> Option<Node> {
+    if index < self.children.len() {
+      Some(self.children.remove(index))
+    } else {
+      None
+    }
+  }
+
+  /// 按文档顺序收集所有可聚焦的元素，元素在其中的位置即为其焦点序号
+  pub fn focusable_elements(&self) -> Vec<&Node> {
+    let mut elements = vec![];
+    self.collect_focusable_elements(&mut elements);
+    elements
+  }
+
+  fn collect_focusable_elements<'a>(&'a self, elements: &mut Vec<&'a Node>) {
+    if let NodeType::Element(elem) = &self.node_type {
+      if elem.is_focusable() {
+        elements.push(self);
+      }
+    }
+    for child in &self.children {
+      child.collect_focusable_elements(elements);
+    }
+  }
+
+  /// 深度优先查找第一个标签名为`tag_name`的元素节点
+  pub fn find_element(&self, tag_name: &str) -> Option<&Node> {
+    if let NodeType::Element(elem) = &self.node_type {
+      if elem.tag_name == tag_name {
+        return Some(self);
+      }
+    }
+    self.children
+      .iter()
+      .find_map(|child| child.find_element(tag_name))
+  }
+
+  /// 获取节点内所有文本节点拼接后的内容
+  pub fn text_content(&self) -> String {
+    match &self.node_type {
+      NodeType::Text(text) => text.clone(),
+      _ => self.children.iter().map(|child| child.text_content()).collect()
+    }
+  }
 }
 
 /// 创建`text`节点
@@ -82,6 +173,22 @@ pub fn comment(content: String) -> Node {
   }
 }
 
+/// 创建`script`节点
+pub fn script(source: String) -> Node {
+  Node {
+    node_type: NodeType::Script(source),
+    children: vec!()
+  }
+}
+
+/// 创建`doctype`节点
+pub fn doctype(name: String) -> Node {
+  Node {
+    node_type: NodeType::Doctype(name),
+    children: vec!()
+  }
+}
+
 /// 创建`style`节点
 pub fn style(tag_name: String, attrs: AttrMap, inner_text: String) -> Node {
   Node {
@@ -0,0 +1,211 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::time::Duration;
+
+use url::Url;
+
+/// 网络请求的超时时间
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 页面可接受的`Content-Type`
+const HTML_TYPES: [&str; 2] = ["text/html", "application/xhtml+xml"];
+/// 样式表可接受的`Content-Type`
+const CSS_TYPES: [&str; 1] = ["text/css"];
+
+/// 加载页面时的错误
+#[derive(Debug)]
+pub enum FetchError {
+  /// 无法解析的`URL`
+  InvalidUrl(String),
+  /// 暂不支持的协议
+  UnsupportedScheme(String),
+  /// 网络请求失败（如连接失败等）
+  Transport(String),
+  /// 请求超时
+  Timeout,
+  /// 服务器返回了非`200`的状态码
+  Status(u16),
+  /// 响应内容的类型不符合预期（如页面不是`html`），存放响应的`Content-Type`
+  ContentType(String),
+  /// 读取本地文件或响应内容失败
+  Io(io::Error)
+}
+
+impl fmt::Display for FetchError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      FetchError::InvalidUrl(url) => write!(f, "无法解析的URL：{url}"),
+      FetchError::UnsupportedScheme(scheme) => write!(f, "暂不支持的协议：{scheme}"),
+      FetchError::Transport(msg) => write!(f, "网络请求失败：{msg}"),
+      FetchError::Timeout => write!(f, "请求超时"),
+      FetchError::Status(status) => write!(f, "服务器返回了状态码{status}"),
+      FetchError::ContentType(content_type) => write!(f, "响应内容的类型不符：{content_type}"),
+      FetchError::Io(err) => write!(f, "读取内容失败：{err}")
+    }
+  }
+}
+
+impl std::error::Error for FetchError {}
+
+impl From<io::Error> for FetchError {
+  fn from(err: io::Error) -> Self {
+    FetchError::Io(err)
+  }
+}
+
+/// 加载完成的页面
+#[derive(Debug)]
+pub struct Page {
+  /// 页面的地址，也是页面内相对地址的基准
+  pub url: Url,
+  /// 页面的`html`源码
+  pub html: String
+}
+
+impl Page {
+  /// 将页面内的相对地址（如图片、字体、样式表）解析为绝对地址
+  pub fn resolve_url(&self, href: &str) -> Result<Url, FetchError> {
+    self.url.join(href).map_err(|_| FetchError::InvalidUrl(href.to_string()))
+  }
+}
+
+/// 访问过的地址记录，用于匹配`:visited`
+#[derive(Debug, Clone, Default)]
+pub struct History {
+  visited: HashSet<Url>
+}
+
+impl History {
+  /// 记录访问过的地址，片段标识（`#`之后的部分）不影响是否访问过
+  pub fn visit(&mut self, url: &Url) {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    self.visited.insert(url);
+  }
+
+  /// 判断地址是否访问过
+  pub fn is_visited(&self, url: &Url) -> bool {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    self.visited.contains(&url)
+  }
+}
+
+/// 将地址解析为`URL`，不带协议的地址视为本地文件路径
+pub fn parse_url(url: &str) -> Result<Url, FetchError> {
+  Url::parse(url).or_else(|_| {
+    let path = fs::canonicalize(url)?;
+    Url::from_file_path(path).map_err(|_| FetchError::InvalidUrl(url.to_string()))
+  })
+}
+
+/// 加载页面，支持`http`、`https`及本地文件（`file`协议或文件路径）
+pub fn fetch(url: &str) -> Result<Page, FetchError> {
+  let url = parse_url(url)?;
+  let (url, html) = fetch_url(&url, &HTML_TYPES)?;
+  Ok(Page { url, html })
+}
+
+/// 异步加载页面，实际的请求在`tokio`的阻塞线程池中执行，超时返回`FetchError::Timeout`
+pub async fn fetch_async(url: String) -> Result<Page, FetchError> {
+  let task = tokio::task::spawn_blocking(move || fetch(&url));
+  match tokio::time::timeout(FETCH_TIMEOUT, task).await {
+    Ok(Ok(page)) => page,
+    Ok(Err(err)) => Err(FetchError::Transport(err.to_string())),
+    Err(_) => Err(FetchError::Timeout)
+  }
+}
+
+/// 加载外部样式表的源码
+pub fn fetch_stylesheet(url: &Url) -> Result<String, FetchError> {
+  fetch_url(url, &CSS_TYPES).map(|(_, source)| source)
+}
+
+/// 按协议加载资源，返回最终的地址及文本内容；`content_types`为`HTTP(S)`响应可接受的类型
+fn fetch_url(url: &Url, content_types: &[&str]) -> Result<(Url, String), FetchError> {
+  match url.scheme() {
+    "http" | "https" => fetch_http(url, content_types),
+    "file" => {
+      let path = url.to_file_path().map_err(|_| FetchError::InvalidUrl(url.to_string()))?;
+      let content = fs::read_to_string(path)?;
+      Ok((url.clone(), content))
+    },
+    scheme => Err(FetchError::UnsupportedScheme(scheme.to_string()))
+  }
+}
+
+/// 通过`HTTP(S)`下载资源，发生重定向时以最终的地址作为资源地址
+fn fetch_http(url: &Url, content_types: &[&str]) -> Result<(Url, String), FetchError> {
+  let agent = ureq::AgentBuilder::new().timeout(FETCH_TIMEOUT).build();
+  let response = match agent.get(url.as_str()).call() {
+    Ok(response) => response,
+    Err(ureq::Error::Status(status, _)) => return Err(FetchError::Status(status)),
+    Err(ureq::Error::Transport(transport)) if is_timeout(&transport) => return Err(FetchError::Timeout),
+    Err(err) => return Err(FetchError::Transport(err.to_string()))
+  };
+  // 2xx中除了200之外的状态（如204）都没有可以渲染的内容
+  if response.status() != 200 {
+    return Err(FetchError::Status(response.status()));
+  }
+  let content_type = response.content_type().to_string();
+  if !content_types.contains(&content_type.as_str()) {
+    return Err(FetchError::ContentType(content_type));
+  }
+  let url = Url::parse(response.get_url()).unwrap_or(url.clone());
+  let content = response.into_string()?;
+  Ok((url, content))
+}
+
+/// 请求是否因为超时而失败
+fn is_timeout(transport: &ureq::Transport) -> bool {
+  transport
+    .source()
+    .and_then(|err| err.downcast_ref::<io::Error>())
+    .is_some_and(|err| matches!(err.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::{BufRead, BufReader, Write};
+  use std::net::TcpListener;
+  use std::thread;
+
+  /// 在本地端口启动只响应一次请求的服务器，返回其地址
+  fn serve_once(status: &str, content_type: &str, body: &str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let response = format!(
+      "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+      body.len()
+    );
+    thread::spawn(move || {
+      let (mut stream, _) = listener.accept().unwrap();
+      // 读完请求头再响应
+      let mut reader = BufReader::new(stream.try_clone().unwrap());
+      let mut line = String::new();
+      while reader.read_line(&mut line).is_ok_and(|len| len > 2) {
+        line.clear();
+      }
+      stream.write_all(response.as_bytes()).unwrap();
+    });
+    format!("http://{addr}/index.html")
+  }
+
+  #[test]
+  fn fetch_async_from_local_server() {
+    let url = serve_once("200 OK", "text/html; charset=utf-8", "<p>hello</p>");
+    let page = tokio::runtime::Runtime::new().unwrap().block_on(fetch_async(url.clone())).unwrap();
+    assert_eq!(page.html, "<p>hello</p>");
+    assert_eq!(page.url.as_str(), url);
+  }
+
+  #[test]
+  fn fetch_reports_error_status() {
+    let url = serve_once("404 Not Found", "text/html", "missing");
+    assert!(matches!(fetch(&url), Err(FetchError::Status(404))));
+  }
+}
@@ -1,16 +1,54 @@
 use crate::dom;
 use crate::css;
+use crate::fetch;
 use std::collections::HashMap;
-use std::fs;
-use std::io::Error;
-use std::path::PathBuf;
+use std::fmt;
+use std::sync::{Arc, Mutex, OnceLock};
+use url::Url;
+
+/// 没有结束标签的空元素
+static VOID_TAGS: [&str; 6] = ["meta", "link", "br", "hr", "img", "input"];
+
+/// 样式表缓存的最大条目数，超出时清空缓存，避免样式表源码不断变化时缓存无限增长
+const STYLESHEET_CACHE_SIZE: usize = 64;
+
+/// 样式表缓存，以源码及地址为键
+type StylesheetCache = HashMap<(String, Option<Url>), Arc<css::Stylesheet>>;
+
+/// `html`解析错误
+///
+/// 缺失或不匹配的结束标签会按照浏览器的方式自动闭合，不会产生错误；只有无法恢复的语法错误（如源码在标签内部结束）才会返回错误
+#[derive(Debug, Clone, PartialEq)]
+pub struct HtmlParseError {
+  /// 期望的内容，如`>`、`-->`
+  pub expected: String,
+  /// 实际遇到的内容，源码提前结束时为`None`
+  pub found: Option<String>,
+  /// 出错的字节位置
+  pub pos: usize
+}
+
+impl fmt::Display for HtmlParseError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match &self.found {
+      Some(found) => write!(f, "html解析错误：位置{}处期望{}，实际为{found}", self.pos, self.expected),
+      None => write!(f, "html解析错误：位置{}处期望{}，源码已经结束", self.pos, self.expected)
+    }
+  }
+}
+
+impl std::error::Error for HtmlParseError {}
 
 struct Parser {
   /// 源码字符串
   input: String,
   /// 当前位置（字符位移）
   pos: usize,
-  stylesheets: Vec<css::Stylesheet>,
+  stylesheets: Vec<Arc<css::Stylesheet>>,
+  /// 尚未闭合的元素标签名栈，用于从缺失的结束标签中恢复
+  open_tags: Vec<String>,
+  /// 页面地址，用于解析外部资源的相对地址
+  base_url: Option<Url>,
 }
 
 impl Parser {
@@ -56,6 +94,19 @@ impl Parser {
     cur_char
   }
 
+  /// 消耗期望的字符串`s`，当前位置不是`s`时返回错误
+  fn expect(&mut self, s: &str) -> Result<(), HtmlParseError> {
+    if !self.starts_with(s) {
+      return Err(HtmlParseError {
+        expected: s.to_string(),
+        found: self.cur_str().chars().next().map(String::from),
+        pos: self.pos
+      });
+    }
+    self.pos += s.len();
+    Ok(())
+  }
+
   /// 连续消耗字符直至`test`函数返回`false`
   fn consume_while<F>(&mut self, test: F) -> String where F: Fn(char) -> bool {
     let mut res = String::new();
@@ -70,11 +121,6 @@ impl Parser {
     self.consume_while(char::is_whitespace);
   }
 
-  /// 消耗当前一行的连续字符
-  fn consume_line(&mut self) {
-    self.consume_while(|c| c != '\n');
-  }
-
   /// 解析标签名，实质上就是解析连续的`字母数字`字符串
   fn parse_tag_name(&mut self) -> String {
     // 匿名函数（rust中也称为闭包）；`..=`是连续范围操作符
@@ -90,159 +136,380 @@ impl Parser {
     dom::text(self.consume_while(|c| c != '<'))
   }
 
-  /// 解析属性值
-  fn parse_attr_val(&mut self) -> String {
-    let open_quote = self.consume_char();
-    assert!(open_quote == '"' || open_quote == '\'');
+  /// 解析属性值，没有引号时取到空白或`>`为止
+  fn parse_attr_val(&mut self) -> Result<String, HtmlParseError> {
+    let open_quote = match self.cur_str().chars().next() {
+      Some(quote @ ('"' | '\'')) => quote,
+      _ => return Ok(self.consume_while(|c| !c.is_whitespace() && c != '>'))
+    };
+    self.consume_char();
     let val = self.consume_while(|c| c != open_quote);
-    assert!(self.consume_char() == open_quote);
-    val
+    self.expect(&open_quote.to_string())?;
+    Ok(val)
+  }
+
+  /// 解析属性名，可以包含`-`等字符（如`data-id`）
+  fn parse_attr_name(&mut self) -> String {
+    self.consume_while(|c| !c.is_whitespace() && !matches!(c, '=' | '>' | '/' | '"' | '\''))
   }
 
   /// 解析属性key
-  fn parse_attr(&mut self) -> (String, String) {
-    let name = self.parse_tag_name();
+  fn parse_attr(&mut self) -> Result<(String, String), HtmlParseError> {
+    let name = self.parse_attr_name();
     self.consume_whitespace();
-    // 空属性
+    // 空属性：跳过无法识别的字符，避免死循环
     if name.is_empty() {
-      return (String::from(""), String::from(""));
+      self.consume_char();
+      return Ok((String::from(""), String::from("")));
     }
     if self.starts_with("=") {
-      let next_char = self.consume_char();
-      assert!(next_char == '=', "name: {}, next char: {}", name, next_char);
-      let val = self.parse_attr_val();
-      (name, val)
+      self.consume_char();
+      self.consume_whitespace();
+      let val = self.parse_attr_val()?;
+      Ok((name, val))
     } else {
-      (name, String::from("true")) // 布尔属性
+      Ok((name, String::new())) // 布尔属性（如`disabled`），值为空字符串
     }
     // TODO: 这里实际上很多边界情况没有处理
   }
 
   /// 解析多个属性（实质上就是某个标签内的所有属性）
-  fn parse_attrs(&mut self) -> dom::AttrMap {
+  fn parse_attrs(&mut self) -> Result<dom::AttrMap, HtmlParseError> {
     let mut attrs = HashMap::new();
     loop {
       self.consume_whitespace();
-      if self.next_char() == '>' {
+      if self.eof() {
+        self.expect(">")?;
+      }
+      if self.next_char() == '>' || self.starts_with("/>") {
         break;
       }
-      let (name, val) = self.parse_attr();
-      attrs.insert(name, val);
+      let (name, val) = self.parse_attr()?;
+      if !name.is_empty() {
+        attrs.insert(name, val);
+      }
     }
-    attrs
+    Ok(attrs)
   }
 
   /// 解析`style`内部语法
   fn parse_style(&mut self) -> String {
     let content = self.consume_while(|c| c != '<');
-    self.stylesheets.push(css::parse(content.clone()));
+    self.stylesheets.push(parse_stylesheet(content.clone(), self.base_url.clone()));
     content
   }
 
+  /// 加载`<link rel="stylesheet">`引用的外部样式表，按出现顺序参与层叠；加载失败时忽略该样式表
+  fn parse_link(&mut self, attrs: &dom::AttrMap) {
+    let is_stylesheet = attrs
+      .get("rel")
+      .is_some_and(|rel| rel.split_whitespace().any(|val| val.eq_ignore_ascii_case("stylesheet")));
+    let href = match attrs.get("href") {
+      Some(href) if is_stylesheet => href,
+      _ => return
+    };
+    // 没有页面地址时，相对地址视为相对于当前工作目录的文件路径
+    let url = match &self.base_url {
+      Some(base_url) => base_url.join(href).map_err(|_| fetch::FetchError::InvalidUrl(href.clone())),
+      None => fetch::parse_url(href)
+    };
+    // 样式表中`@import`的相对地址基于样式表自身的地址解析
+    match url.and_then(|url| fetch::fetch_stylesheet(&url).map(|source| (url, source))) {
+      Ok((url, source)) => self.stylesheets.push(parse_stylesheet(source, Some(url))),
+      Err(err) => log::warn!("无法加载样式表{href}：{err}")
+    }
+  }
+
+  /// 解析`script`内部的源码，原样保留到`</script>`为止，其中的`<`不会被当作标签
+  fn parse_script(&mut self) -> String {
+    let end = self.cur_str().find("</script").unwrap_or(self.cur_str().len());
+    let source = self.cur_str()[..end].to_string();
+    self.pos += end;
+    source
+  }
+
+  /// 仅返回接下来结束标签的标签名而不移动位置
+  fn peek_end_tag(&self) -> String {
+    self.cur_str()
+      .trim_start_matches("</")
+      .chars()
+      .take_while(char::is_ascii_alphanumeric)
+      .collect()
+  }
+
+  /// 消耗一个结束标签（如`</div>`），源码提前结束时直接返回
+  fn consume_end_tag(&mut self) {
+    if !self.starts_with("</") {
+      return;
+    }
+    self.pos += "</".len();
+    self.consume_while(|c| c != '>');
+    if !self.eof() {
+      self.consume_char();
+    }
+  }
+
+  /// 解析元素的子节点直到元素的结束标签
+  ///
+  /// 遇到的结束标签属于外层尚未闭合的元素时，视为当前元素的结束标签缺失，当前元素隐式闭合（不消耗该结束标签）；
+  /// 不属于任何尚未闭合元素的结束标签则直接忽略
+  fn parse_children(&mut self, tag_name: &str) -> Result<Vec<dom::Node>, HtmlParseError> {
+    let mut children = vec!();
+    self.open_tags.push(tag_name.to_string());
+    loop {
+      children.append(&mut self.parse_nodes()?);
+      if self.eof() {
+        log::warn!("<{tag_name}>缺少结束标签");
+        break;
+      }
+      let end_tag = self.peek_end_tag();
+      if end_tag == tag_name {
+        self.consume_end_tag();
+        break;
+      }
+      if self.open_tags.contains(&end_tag) {
+        log::warn!("<{tag_name}>缺少结束标签，在</{end_tag}>处隐式闭合");
+        break;
+      }
+      log::warn!("忽略多余的结束标签</{end_tag}>");
+      self.consume_end_tag();
+    }
+    self.open_tags.pop();
+    Ok(children)
+  }
+
   /// 解析单个标签元素（**不包含**自闭合标签）
-  fn parse_element(&mut self) -> dom::Node {
-    let mut res = dom::text(" ".to_string());
-    assert!(self.consume_char() == '<');
+  fn parse_element(&mut self) -> Result<dom::Node, HtmlParseError> {
+    self.expect("<")?;
     let name = self.parse_tag_name();
-    let tag_name = name.clone();
-    let attrs = self.parse_attrs();
-    assert!(self.consume_char() == '>');
+    let attrs = self.parse_attrs()?;
+    if self.next_char() == '/' {
+      self.consume_char();
+    }
+    self.expect(">")?;
+    // 空元素没有子节点和结束标签
+    if VOID_TAGS.contains(&name.as_str()) {
+      if name == "link" {
+        self.parse_link(&attrs);
+      }
+      return Ok(dom::element(name, attrs, vec!()));
+    }
     if name == "style" {
       let source = self.parse_style();
-      res = dom::style(name, attrs, source);
+      self.consume_end_tag();
+      Ok(dom::style(name, attrs, source))
+    } else if name == "script" {
+      let source = self.parse_script();
+      self.consume_end_tag();
+      Ok(dom::script(source))
     } else {
-      let children = self.parse_nodes();
-      res = dom::element(name, attrs, children);
-    }
-    assert!(self.consume_char() == '<');
-    assert!(self.consume_char() == '/');
-    let end_tag = self.parse_tag_name();
-    assert!(end_tag == tag_name, "tag name: {tag_name}, {end_tag}");
-    assert!(self.consume_char() == '>');
-    res
+      let mut children = self.parse_children(&name)?;
+      // 与浏览器一致，`<pre>`开始标签之后紧跟的换行会被忽略
+      if let (true, Some(dom::NodeType::Text(text))) = (name == "pre", children.first_mut().map(|child| &mut child.node_type)) {
+        if let Some(content) = text.strip_prefix('\n') {
+          *text = content.to_string();
+        }
+      }
+      Ok(dom::element(name, attrs, children))
+    }
   }
 
   /// 解析注释元素
-  fn parse_comment(&mut self) -> dom::Node {
-    // 注释开始
-    assert!(self.consume_char() == '<');
-    assert!(self.consume_char() == '!');
-    assert!(self.consume_char() == '-');
-    assert!(self.consume_char() == '-');
-    let mut content = String::new();
-    loop {
-      if self.starts_with("-->") {
-        break;
-      }
-      content.push(self.consume_char());
+  fn parse_comment(&mut self) -> Result<dom::Node, HtmlParseError> {
+    self.expect("<!--")?;
+    let content = match self.cur_str().find("-->") {
+      Some(end) => self.cur_str()[..end].to_string(),
+      None => return Err(HtmlParseError { expected: String::from("-->"), found: None, pos: self.input.len() })
+    };
+    self.pos += content.len();
+    self.expect("-->")?;
+    Ok(dom::comment(content))
+  }
+
+  /// 解析文档类型声明，如`<!DOCTYPE html>`（大小写不敏感）
+  fn parse_doctype(&mut self) -> dom::Node {
+    self.pos += "<!DOCTYPE".len();
+    let content = self.consume_while(|c| c != '>');
+    if !self.eof() {
+      self.consume_char();
     }
-    // 注释结束
-    assert!(self.consume_char() == '-');
-    assert!(self.consume_char() == '-');
-    assert!(self.consume_char() == '>');
-    dom::comment(content)
+    let name = content.split_whitespace().next().unwrap_or("").to_lowercase();
+    dom::doctype(name)
   }
 
   /// 解析单个节点
-  fn parse_node(&mut self) -> dom::Node {
+  fn parse_node(&mut self) -> Result<dom::Node, HtmlParseError> {
     if self.next_char() == '<' {
       if self.starts_with("<!--") { // 匹配注释开始部分
         self.parse_comment()
-      } else if self.starts_with("<!DOCTYPE") {
-        self.consume_line(); // 直接跳过doctype解析，同时避免报错
-        self.consume_whitespace();
-        self.parse_node()
+      } else if self.cur_str().get(..9).is_some_and(|s| s.eq_ignore_ascii_case("<!DOCTYPE")) {
+        Ok(self.parse_doctype())
       } else {
         self.parse_element()
       }
     } else {
-      self.parse_text()
+      Ok(self.parse_text())
     }
   }
 
   /// 解析连续的多个节点
-  fn parse_nodes(&mut self) -> Vec<dom::Node> {
+  ///
+  /// 标签之间只有空白的部分会被忽略；文本开头的空白保留在文本节点中，由布局时根据`white-space`决定是否合并
+  fn parse_nodes(&mut self) -> Result<Vec<dom::Node>, HtmlParseError> {
     let mut nodes = vec!();
     loop {
-      self.consume_whitespace();
+      let whitespace = self.consume_while(char::is_whitespace);
       if self.eof() || self.starts_with("</") {
         break;
       }
-      nodes.push(self.parse_node());
+      let mut node = self.parse_node()?;
+      if let dom::NodeType::Text(text) = &mut node.node_type {
+        text.insert_str(0, &whitespace);
+      }
+      nodes.push(node);
     }
-    nodes
+    Ok(nodes)
+  }
+}
+
+/// 浏览器内置的样式，编译时嵌入，运行时不再依赖源码目录
+static DEFAULT_CSS: &str = include_str!("config/default.css");
+
+/// 获取浏览器内置的样式，只在第一次使用时解析
+fn get_default_stylesheet() -> Arc<css::Stylesheet> {
+  static DEFAULT_STYLESHEET: OnceLock<Arc<css::Stylesheet>> = OnceLock::new();
+  DEFAULT_STYLESHEET.get_or_init(|| Arc::new(css::parse(DEFAULT_CSS.to_string()))).clone()
+}
+
+/// 解析样式表，源码及地址都相同的样式表只解析一次，之后共享同一份解析结果
+///
+/// 页面更新时流水线会重新解析整个文档，而其中的样式表通常没有变化；缓存以源码及地址本身为键，不会因为哈希碰撞取错样式表。
+/// 内置样式不经过这里（见`get_default_stylesheet`），缓存清空时不受影响
+fn parse_stylesheet(source: String, base_url: Option<Url>) -> Arc<css::Stylesheet> {
+  static STYLESHEET_CACHE: OnceLock<Mutex<StylesheetCache>> = OnceLock::new();
+  let key = (source, base_url);
+  let cache = STYLESHEET_CACHE.get_or_init(Default::default);
+  if let Some(stylesheet) = cache.lock().unwrap().get(&key) {
+    return stylesheet.clone();
+  }
+  // 解析时不持有锁，其它线程可以同时解析别的样式表
+  let stylesheet = Arc::new(css::parse_with_base(key.0.clone(), key.1.clone()));
+  let mut cache = cache.lock().unwrap();
+  if cache.len() >= STYLESHEET_CACHE_SIZE {
+    cache.clear();
   }
+  cache.insert(key, stylesheet.clone());
+  stylesheet
 }
 
-/// 获取浏览器内置的样式
-fn get_default_stylesheet() -> Result<css::Stylesheet, Error> {
-  let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-  file_path.push("src");
-  file_path.push("config");
-  file_path.push("default.css");
-  let file_path_url = file_path.to_str().unwrap_or("");
-  let content = fs::read_to_string(file_path_url)?;
-  let stylesheet = css::parse(content);
-  Ok(stylesheet)
+/// 从`<head>`中提取`<title>`文本及`<meta charset>`声明的编码
+fn parse_head(head: &dom::Node) -> (Option<String>, Option<String>) {
+  let title = head
+    .find_element("title")
+    .map(|title| title.text_content().trim().to_string());
+  let charset = head.children
+    .iter()
+    .find_map(|child| match &child.node_type {
+      dom::NodeType::Element(elem) if elem.tag_name == "meta" => elem.get_attribute("charset"),
+      _ => None
+    })
+    .map(|charset| charset.to_lowercase());
+  (title, charset)
 }
 
 /// 解析`html`子集语法成`DOM`节点数
-pub fn parse(source: String) -> dom::Document {
+pub fn parse(source: String) -> Result<dom::Document, HtmlParseError> {
+  parse_with_base(source, None)
+}
+
+/// 解析`html`，外部样式表等资源的相对地址基于`base_url`解析
+pub fn parse_with_base(source: String, base_url: Option<Url>) -> Result<dom::Document, HtmlParseError> {
   let mut parser = Parser {
     pos: 0,
     input: source,
-    stylesheets: vec!()
+    stylesheets: vec!(),
+    open_tags: vec!(),
+    base_url
   };
-  let mut nodes = parser.parse_nodes();
-  let root = if nodes.len() == 1 {
-    nodes.swap_remove(0)
-  } else {
-    dom::element(String::from("html"), HashMap::new(), nodes)
+  let mut root = dom::element(String::from("html"), HashMap::new(), vec!());
+  // 顶层多余的结束标签直接忽略
+  while !parser.eof() {
+    for node in parser.parse_nodes()? {
+      root.append_child(node);
+    }
+    parser.consume_end_tag();
+  }
+  // 文档类型声明不属于DOM树，单独存放
+  let doctype = root.children
+    .iter()
+    .position(|node| matches!(node.node_type, dom::NodeType::Doctype(_)))
+    .and_then(|idx| root.remove_child(idx))
+    .and_then(|node| match node.node_type {
+      dom::NodeType::Doctype(name) => Some(name),
+      _ => None
+    });
+  // 顶层只有一个元素时直接作为根节点；文本、注释、`style`及`script`等节点不能作为根节点
+  if root.children.len() == 1 && matches!(root.children[0].node_type, dom::NodeType::Element(_)) {
+    root = root.remove_child(0).unwrap();
+  }
+  parser.stylesheets.insert(0, get_default_stylesheet()); // 保证默认样式是优先级最低的
+  let (title, charset) = match root.find_element("head") {
+    Some(head) => parse_head(head),
+    None => (None, None)
   };
-  let default_stylesheet = get_default_stylesheet().unwrap_or(css::parse(String::from("")));
-  parser.stylesheets.insert(0, default_stylesheet); // 保证默认样式是优先级最低的
-  dom::Document {
+  Ok(dom::Document {
     root,
-    stylesheets: parser.stylesheets
+    stylesheets: parser.stylesheets,
+    url: parser.base_url,
+    doctype,
+    title,
+    charset
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn stylesheet_cache_shares_parsed_sheets() {
+    let source = String::from("<div><style>.cached { color: red; }</style></div>");
+    let first = parse(source.clone()).unwrap();
+    let second = parse(source).unwrap();
+    assert!(Arc::ptr_eq(&first.stylesheets[0], &second.stylesheets[0]));
+    assert!(Arc::ptr_eq(&first.stylesheets[1], &second.stylesheets[1]));
+    // 源码相同但地址不同的样式表分别解析
+    let base_url = Url::parse("https://example.com/").unwrap();
+    let other = parse_with_base(String::from("<div><style>.cached { color: red; }</style></div>"), Some(base_url)).unwrap();
+    assert!(!Arc::ptr_eq(&first.stylesheets[1], &other.stylesheets[1]));
+  }
+
+  #[test]
+  fn parse_boolean_attribute() {
+    let document = parse(String::from(r#"<div><br class="x" hidden><input disabled/></div>"#)).unwrap();
+    let dom::NodeType::Element(br) = &document.root.children[0].node_type else {
+      panic!("{:?}", document.root.children[0].node_type);
+    };
+    assert_eq!(br.tag_name, "br");
+    assert_eq!(br.attrs.get("class").map(String::as_str), Some("x"));
+    assert_eq!(br.attrs.get("hidden").map(String::as_str), Some(""));
+    let dom::NodeType::Element(input) = &document.root.children[1].node_type else {
+      panic!("{:?}", document.root.children[1].node_type);
+    };
+    assert_eq!(input.attrs.get("disabled").map(String::as_str), Some(""));
+  }
+
+  #[test]
+  fn parse_doctype_declaration() {
+    for doctype in ["<!DOCTYPE html>", "<!doctype HTML>"] {
+      let source = format!("{doctype}<html><head><title>Doc</title></head><body><p>text</p></body></html>");
+      let document = parse(source).unwrap();
+      assert_eq!(document.doctype.as_deref(), Some("html"));
+      let dom::NodeType::Element(root) = &document.root.node_type else {
+        panic!("{:?}", document.root.node_type);
+      };
+      assert_eq!(root.tag_name, "html");
+      assert_eq!(document.title.as_deref(), Some("Doc"));
+    }
   }
 }
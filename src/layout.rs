@@ -1,23 +1,26 @@
+use std::borrow::Cow;
+use std::ops::Range;
 use std::sync::{Arc, Mutex};
 
-use fontdue::layout::{TextStyle, GlyphPosition, LayoutSettings};
+use fontdue::layout::{TextStyle, GlyphPosition, LayoutSettings, WrapStyle};
 use ggez::graphics;
 
 use crate::dom::NodeType;
 use crate::font::TextLayout;
 use crate::style::{
   StyledNode,
-  Display, StyleTree
+  Display, StyleTree, Float, TextAlign, Clear, VerticalAlign, Viewport, JustifyContent, Position, FlexDirection, WhiteSpace, AlignItems,
+  FlexWrap, AlignContent
 };
 use crate::css::{
   CSSValue,
-  CSSUnit
+  CSSUnit,
+  TrackSize,
+  GridLine
 };
 
-/// [Global variables? Do they exist? : rust](https://www.reddit.com/r/rust/comments/2v2h8l/global_variables_do_they_exist/)
-///
-/// 在rust里，限定了全局变量的声明方式，过于动态的全局变量是unsafe的
-static mut TEXT_LAYOUTS: Vec<TextLayout> = vec![]; // TODO: 这里静态变量的初始化可以考虑使用lazy_static；https://course.rs/advance/global-variable.html#lazy_static
+/// 宽度比较时容许的浮点误差
+const EPSILON: f32 = 0.01;
 
 /// 四周边距
 #[derive(Debug, Copy, Clone)]
@@ -56,12 +59,47 @@ pub struct Box {
 pub enum BoxType<'a> {
   Block(Arc<StyledNode<'a>>),
   Inline(Arc<StyledNode<'a>>),
+  /// `inline-block`，内部按块级盒子布局，自身在line box中排列
+  InlineBlock(Arc<StyledNode<'a>>),
+  /// `flex`容器，子级（flex item）在同一行内水平排列
+  Flex(Arc<StyledNode<'a>>),
+  /// `grid`容器，子级（grid item）按照列轨道逐行放置
+  Grid(Arc<StyledNode<'a>>),
   /// 匿名`block box`，用于存放多个`inline box`
   AnonymousBlock(Arc<StyledNode<'a>>),
-  /// 匿名`inline box`，一般是由块级box直接包含的文字产生，样式直接继承父级
-  AnonymousInline(&'a String, Arc<StyledNode<'a>>),
-  /// line box
-  Line
+  /// 匿名`inline box`，一般是由块级box直接包含的文字产生，样式直接继承父级；换行拆分后的文本为新建的字符串
+  AnonymousInline(Cow<'a, str>, Arc<StyledNode<'a>>),
+  /// line box，样式（如字号）取所在的块级容器
+  Line(Arc<StyledNode<'a>>)
+}
+
+/// 浮动盒子信息
+#[derive(Debug, Copy, Clone)]
+pub struct FloatBox {
+  /// 浮动方向
+  pub float: Float,
+  /// 浮动盒子的`margin-box`区域（绝对坐标）
+  pub area: RectArea
+}
+
+/// 粘性定位的阈值，窗口滚动时据此调整盒子的绘制位置
+#[derive(Debug, Copy, Clone)]
+pub struct StickyOffsets {
+  /// 盒子顶部与视窗顶部的距离小于该值时，固定在该位置
+  pub top: Option<f32>,
+  /// 盒子底部与视窗底部的距离小于该值时，固定在该位置
+  pub bottom: Option<f32>,
+  /// 包含块的`content-box`区域，盒子不会被移出这个区域
+  pub limit: RectArea
+}
+
+/// grid item所在的网格区域，行列均为从0开始的轨道下标，结束下标不包含在内
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GridArea {
+  pub row_start: usize,
+  pub row_end: usize,
+  pub column_start: usize,
+  pub column_end: usize
 }
 
 /// 布局树（`layout tree`）节点
@@ -71,6 +109,32 @@ pub struct LayoutBox<'a> {
   pub box_type: BoxType<'a>,
   pub children: Vec<LayoutBox<'a>>,
   pub glyphs: Arc<Mutex<Vec<GlyphPosition>>>,
+  /// 文本基线到`content`顶部的距离，仅对匿名inline盒子有意义
+  pub baseline: f32,
+  /// 当前块级格式化上下文中已放置的浮动盒子
+  pub floats: Vec<FloatBox>,
+  /// 包含块的确定高度，用于计算百分比高度；包含块高度取决于内容时为`None`
+  pub containing_height: Option<f32>,
+  /// 粘性定位的阈值，不是粘性定位时为`None`
+  pub sticky: Option<StickyOffsets>,
+  /// grid item所在的网格区域，不是grid item时为`None`
+  pub grid_area: Option<GridArea>,
+}
+
+/// 水平排列的flex容器中的一行
+struct FlexLine {
+  /// 行内子项的下标范围
+  items: Range<usize>,
+  /// 行在竖直方向上的尺寸，即行内最高子项的`margin-box`高度
+  cross_size: f32
+}
+
+/// 子树的布局结果
+pub struct SubtreeLayout<'a> {
+  /// 整个文档的`style tree`根节点；子树的样式继承依赖祖先节点，因此需要一并持有
+  _style_root: Arc<StyledNode<'a>>,
+  /// 子树根节点的布局结点
+  pub layout_box: LayoutBox<'a>
 }
 
 pub struct LayoutTree {
@@ -109,14 +173,55 @@ impl RectArea {
     }
   }
 
+  /// 同时包含两个区域的最小区域
+  pub fn union(self, other: RectArea) -> RectArea {
+    let x = self.x.min(other.x);
+    let y = self.y.min(other.y);
+    RectArea {
+      x,
+      y,
+      width: (self.x + self.width).max(other.x + other.width) - x,
+      height: (self.y + self.height).max(other.y + other.height) - y
+    }
+  }
+
+  /// 两个区域的重叠区域，不重叠时宽高为0
+  pub fn intersect(self, other: RectArea) -> RectArea {
+    let x = self.x.max(other.x);
+    let y = self.y.max(other.y);
+    RectArea {
+      x,
+      y,
+      width: ((self.x + self.width).min(other.x + other.width) - x).max(0.0),
+      height: ((self.y + self.height).min(other.y + other.height) - y).max(0.0)
+    }
+  }
+
+  /// 像素中心是否落在区域内
+  pub fn contains_pixel(&self, x: i64, y: i64) -> bool {
+    let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+    px >= self.x && px < self.x + self.width && py >= self.y && py < self.y + self.height
+  }
+
+  /// 序列化为JSON对象
+  fn to_json(self) -> String {
+    format!(
+      "{{\"x\":{},\"y\":{},\"width\":{},\"height\":{}}}",
+      json_number(self.x),
+      json_number(self.y),
+      json_number(self.width),
+      json_number(self.height)
+    )
+  }
+
   pub fn to_ggez_rect(&self) -> graphics::Rect {
     graphics::Rect::new(self.x, self.y, self.width, self.height)
   }
 }
 
-impl Box {
+impl Default for Box {
   /// 默认值
-  pub fn default() -> Box {
+  fn default() -> Box {
     Box {
       content: RectArea::default(),
       padding: EdgeSizes::default(),
@@ -124,7 +229,9 @@ impl Box {
       margin: EdgeSizes::default()
     }
   }
+}
 
+impl Box {
   /// `padding-box`区域
   pub fn padding_box(self) -> RectArea {
     self.content.expanded_by(self.padding)
@@ -141,16 +248,67 @@ impl Box {
   }
 }
 
+impl GridArea {
+  fn new(row: usize, row_span: usize, column: usize, column_span: usize) -> Self {
+    GridArea {
+      row_start: row,
+      row_end: row + row_span,
+      column_start: column,
+      column_end: column + column_span
+    }
+  }
+}
+
 impl<'a> LayoutBox<'a> {
   fn new(box_type: BoxType) -> LayoutBox {
     LayoutBox {
       box_model: Box::default(),
       box_type,
       children: vec![],
-      glyphs: Arc::new(Mutex::new(vec![]))
+      glyphs: Arc::new(Mutex::new(vec![])),
+      baseline: 0.0,
+      floats: vec![],
+      containing_height: None,
+      sticky: None,
+      grid_area: None
     }
   }
 
+  /// 将以当前盒子为根的布局树序列化为JSON（单行），用于调试或者对比布局结果，不需要光栅化
+  ///
+  /// 每个节点包含盒子类型、元素的标签名（匿名盒子及line box为`null`）、文本（仅匿名inline盒子）、
+  /// `content`/`padding`/`border`/`margin`四个区域、字形数量以及子节点
+  pub fn to_json(&self) -> String {
+    let (box_type, style_node) = match &self.box_type {
+      BoxType::Block(style_node) => ("Block", Some(style_node)),
+      BoxType::Inline(style_node) => ("Inline", Some(style_node)),
+      BoxType::InlineBlock(style_node) => ("InlineBlock", Some(style_node)),
+      BoxType::Flex(style_node) => ("Flex", Some(style_node)),
+      BoxType::Grid(style_node) => ("Grid", Some(style_node)),
+      BoxType::AnonymousBlock(_) => ("AnonymousBlock", None),
+      BoxType::AnonymousInline(..) => ("AnonymousInline", None),
+      BoxType::Line(_) => ("Line", None)
+    };
+    let tag = match style_node.map(|style_node| &style_node.node.node_type) {
+      Some(NodeType::Element(elem)) => json_string(&elem.tag_name),
+      _ => String::from("null")
+    };
+    let text = match &self.box_type {
+      BoxType::AnonymousInline(content, _) => format!(",\"text\":{}", json_string(content)),
+      _ => String::new()
+    };
+    let children: Vec<String> = self.children.iter().map(|child| child.to_json()).collect();
+    format!(
+      "{{\"box_type\":\"{box_type}\",\"tag\":{tag}{text},\"content\":{},\"padding\":{},\"border\":{},\"margin\":{},\"glyphs\":{},\"children\":[{}]}}",
+      self.box_model.content.to_json(),
+      self.box_model.padding_box().to_json(),
+      self.box_model.border_box().to_json(),
+      self.box_model.margin_box().to_json(),
+      self.glyphs.lock().unwrap().len(),
+      children.join(",")
+    )
+  }
+
   /// 获取`inline`节点的容器节点（这里的self就是`inline`节点的父节点）
   /// 
   /// 主要是判断在`block`节点内混用`inline`和`block`节点时，需要对连续的`inline`节点人为增加匿名容器
@@ -158,7 +316,7 @@ impl<'a> LayoutBox<'a> {
     // 本身如果是匿名块级box或内联box则无需新建容器
     match &self.box_type {
       BoxType::Inline(_) | BoxType::AnonymousBlock(_) => self,
-      BoxType::Block(style_node) => {
+      BoxType::Block(style_node) | BoxType::InlineBlock(style_node) | BoxType::Flex(style_node) | BoxType::Grid(style_node) => {
         // 上一个元素如果正好是匿名块级box则无需再新建，直接共用？标准里好像没见到…… →（连续的inline节点共用一个匿名block box）
         // 按理说，如果自身是block box，且子级正好是非匿名的inline box还有必要借用匿名block box吗？→（按照规范，确实需要）
         // NOTICE: 事实上这里的逻辑就是判断上一个节点是否为匿名block box，不是则新建一个匿名block box；这里的匿名block box就是inline box的容器。
@@ -175,94 +333,261 @@ impl<'a> LayoutBox<'a> {
 
   /// 获取样式节点
   fn get_style_node(&self) -> Arc<StyledNode<'a>> {
-    if let BoxType::Block(style_node) | BoxType::Inline(style_node) | BoxType::AnonymousBlock(style_node) = &self.box_type {
-      style_node.clone()
-    } else {
-      // TODO: 其他盒模型的样式与继承
-      panic!("匿名结点没有样式！{:#?}", self.box_type)
+    match &self.box_type {
+      BoxType::Block(style_node) | BoxType::Inline(style_node) | BoxType::InlineBlock(style_node) | BoxType::Flex(style_node) | BoxType::Grid(style_node) | BoxType::AnonymousBlock(style_node) | BoxType::AnonymousInline(_, style_node) | BoxType::Line(style_node) => style_node.clone()
     }
   }
 
-  /// 计算块级元素宽度
-  fn calc_block_width(&mut self, containing_block: Box, is_anonymous: bool) {
-    let style_node = self.get_style_node();
-    let auto = CSSValue::Keyword(String::from("auto"));
-    let zero = CSSValue::Length(0.0, CSSUnit::Px);
-    let mut width = style_node.get_val("width").unwrap_or(auto.clone());
-    let mut margin_left = if is_anonymous { zero.clone() } else { style_node.look_up("margin-left", "margin", &zero) };
-    let mut margin_right = if is_anonymous { zero.clone() } else { style_node.look_up("margin-right", "margin", &zero) };
-    let padding_left = if is_anonymous { zero.clone() } else { style_node.look_up("padding-left", "padding", &zero) };
-    let padding_right = if is_anonymous { zero.clone() } else { style_node.look_up("padding-right", "padding", &zero) };
-    let border_left = if is_anonymous { zero.clone() } else { style_node.look_up("border-left-width", "border-width", &zero) };
-    let border_right = if is_anonymous { zero.clone() } else { style_node.look_up("border-right-width", "border-width", &zero) };
-    let total_width: f32 = [
-      &margin_left,
-      &border_left,
-      &padding_left,
-      &width,
-      &padding_right,
-      &border_right,
-      &margin_right
-    ].iter().map(|val| val.to_px()).sum(); // 总宽度（实际上就是`margin-box`宽度）
+  /// 按照阅读顺序提取布局树中的文本，每个`line box`占一行，不需要光栅化
+  ///
+  /// `display: none`的元素不会生成盒子，因此其中的文本自然被排除；`visibility: hidden`的文本同样不会被提取
+  pub fn get_text_content(&self) -> String {
+    let mut lines = vec![];
+    self.collect_text_lines(&mut lines);
+    lines.join("\n")
+  }
 
-    // 当前元素总宽度超过其包含块宽度时
-    if width != auto && total_width > containing_block.content.width {
-      // 首先压缩外边距宽度
-      if margin_left == auto {
-        margin_left = zero.clone();
+  /// 收集每个`line box`内的文本
+  fn collect_text_lines(&self, lines: &mut Vec<String>) {
+    if let BoxType::Line(_) = self.box_type {
+      let mut line = String::new();
+      self.collect_text(&mut line);
+      let line = line.trim();
+      if !line.is_empty() {
+        lines.push(line.to_string());
       }
-      if margin_right == auto {
-        margin_right = zero.clone();
+      return;
+    }
+    for child in &self.children {
+      child.collect_text_lines(lines);
+    }
+  }
+
+  /// 按顺序拼接所有可见文本盒子的内容（包括`inline-block`内部的文本）
+  fn collect_text(&self, text: &mut String) {
+    if let BoxType::AnonymousInline(content, style_node) = &self.box_type {
+      if style_node.is_visible() {
+        text.push_str(content);
       }
     }
+    for child in &self.children {
+      child.collect_text(text);
+    }
+  }
 
-    //TODO: 包含块剩余宽度（关键是上面改变外边距的行为不会导致总宽度变化吗？）
-    let rest_wdith = containing_block.content.width - total_width;
+  /// 平移盒子及其所有子级
+  fn translate(&mut self, dx: f32, dy: f32) {
+    self.box_model.content.x += dx;
+    self.box_model.content.y += dy;
+    if let Some(sticky) = &mut self.sticky {
+      sticky.limit.x += dx;
+      sticky.limit.y += dy;
+    }
+    for child in self.children.iter_mut() {
+      child.translate(dx, dy);
+    }
+  }
 
-    println!("width: {}, rest: {}", total_width, rest_wdith);
-    
-    match (width == auto, margin_left == auto, margin_right == auto) {
-      (false, false, false) => {
-        // 这里填充右侧外边距的目的是当溢出的时候，通过负边距来修正，而宽度剩余时只是简单地填满剩余宽度
-        margin_right = CSSValue::Length(margin_right.to_px() + rest_wdith, CSSUnit::Px);
-      },
-      (false, true, false) => {
-        margin_left = CSSValue::Length(rest_wdith, CSSUnit::Px);
-      },
-      (false, false, true) => {
-        margin_right = CSSValue::Length(rest_wdith, CSSUnit::Px);
+  /// 获取盒子计算后的字号（像素）
+  fn get_font_size(&self) -> f32 {
+    self.get_style_node().get_font_size()
+  }
+
+  /// 获取`line-height`对应的像素高度，`normal`时返回`None`
+  fn get_line_height(&self) -> Option<f32> {
+    match self.get_style_node().get_val("line-height") {
+      Some(CSSValue::Number(num)) => Some(num * self.get_font_size()),
+      // `em`及百分比都相对于自身的字号
+      Some(length @ CSSValue::Length(..)) => Some(length.to_px_with(self.get_font_size(), self.get_font_size())),
+      _ => None
+    }
+  }
+
+  /// 是否为固定定位的盒子（固定定位的元素已经被块级化）
+  pub fn is_fixed(&self) -> bool {
+    match &self.box_type {
+      BoxType::Block(style_node) | BoxType::Flex(style_node) | BoxType::Grid(style_node) => style_node.get_position() == Position::Fixed,
+      _ => false
+    }
+  }
+
+  /// 获取定位盒子的层叠顺序（`z-index`），`auto`视为0
+  pub fn get_z_index(&self) -> i32 {
+    match &self.box_type {
+      BoxType::Block(style_node) | BoxType::Flex(style_node) | BoxType::Grid(style_node) => style_node.get_z_index().unwrap_or(0),
+      _ => 0
+    }
+  }
+
+  /// 获取盒子的浮动类型，只有块级box才可能浮动
+  fn get_float(&self) -> Float {
+    if let BoxType::Block(style_node) = &self.box_type {
+      style_node.get_float()
+    } else {
+      Float::None
+    }
+  }
+
+  /// 计算内在宽度（`content-box`宽度），`is_max`为真时计算`max-content`，否则计算`min-content`
+  ///
+  /// 文本可以在单词间换行，因此`min-content`取最宽的单词，`max-content`则是连续文本排在同一行的宽度；
+  /// 不自动换行（`nowrap`/`pre`）的文本两者相同，都是最宽的一行
+  fn get_intrinsic_width(&self, is_max: bool, text_layout: &mut TextLayout) -> f32 {
+    match &self.box_type {
+      BoxType::AnonymousInline(content, style_node) if style_node.get_white_space() != WhiteSpace::Normal => {
+        let mut width: f32 = 0.0;
+        for line in content.lines() {
+          width = width.max(self.calc_text_layout(line, text_layout).0);
+        }
+        width
       },
-      (false, true, true) => {
-        margin_left = CSSValue::Length(rest_wdith / 2.0, CSSUnit::Px);
-        margin_right = CSSValue::Length(rest_wdith / 2.0, CSSUnit::Px);
+      BoxType::AnonymousInline(content, _) if is_max => self.calc_text_layout(content, text_layout).0,
+      BoxType::AnonymousInline(content, _) => {
+        let mut width: f32 = 0.0;
+        for word in content.split_whitespace() {
+          width = width.max(self.calc_text_layout(word, text_layout).0);
+        }
+        width
       },
-      (true, _, _) => {
-        // width的auto优先级最高
-        if margin_left == auto {
-          margin_left = zero.clone();
+      BoxType::Inline(_) | BoxType::AnonymousBlock(_) => {
+        let mut width: f32 = 0.0;
+        for child in &self.children {
+          let child_width = child.get_intrinsic_outer_width(is_max, text_layout);
+          width = if is_max { width + child_width } else { width.max(child_width) };
         }
-        if margin_right == auto {
-          margin_right = zero.clone();
+        width
+      },
+      BoxType::Block(_) | BoxType::InlineBlock(_) => {
+        let mut width: f32 = 0.0;
+        for child in &self.children {
+          width = width.max(child.get_intrinsic_outer_width(is_max, text_layout));
         }
-        if rest_wdith < 0.0 {
-          width = zero.clone();
-          // 通过边距来修正
-          margin_right = CSSValue::Length(margin_right.to_px() + rest_wdith, CSSUnit::Px);
-        } else {
-          width = CSSValue::Length(rest_wdith, CSSUnit::Px);
-          println!("此时的width: {}", width.to_px());
+        width
+      },
+      // flex item排在同一行，`max-content`为所有子项之和
+      BoxType::Flex(_) => {
+        let mut width: f32 = 0.0;
+        for child in &self.children {
+          let child_width = child.get_intrinsic_outer_width(is_max, text_layout);
+          width = if is_max { width + child_width } else { width.max(child_width) };
         }
-      }
+        width
+      },
+      // grid item按列排列，`max-content`为各列宽度之和
+      BoxType::Grid(_) => {
+        let tracks = self.get_grid_tracks();
+        let areas = self.get_grid_areas(tracks.len());
+        self.get_grid_column_widths(&tracks, &areas, is_max, text_layout).iter().sum()
+      },
+      BoxType::Line(_) => 0.0
+    }
+  }
+
+  /// 计算子级对父级内在宽度的贡献，块级盒子及inline-block为`margin-box`宽度；显式指定的宽度优先于内在宽度
+  fn get_intrinsic_outer_width(&self, is_max: bool, text_layout: &mut TextLayout) -> f32 {
+    if let BoxType::Block(style_node) | BoxType::InlineBlock(style_node) | BoxType::Flex(style_node) | BoxType::Grid(style_node) = &self.box_type {
+      let zero = CSSValue::Length(0.0, CSSUnit::Px);
+      let font_size = style_node.get_font_size();
+      // 百分比和`auto`在这里无法确定，按0处理
+      let edge_width: f32 = [
+        style_node.look_up("margin-left", "margin", &zero),
+        style_node.look_up("margin-right", "margin", &zero),
+        style_node.look_up("border-left-width", "border-width", &zero),
+        style_node.look_up("border-right-width", "border-width", &zero),
+        style_node.look_up("padding-left", "padding", &zero),
+        style_node.look_up("padding-right", "padding", &zero)
+      ].iter().map(|val| val.to_px_with(font_size, 0.0)).sum();
+      let width = match style_node.get_val("width") {
+        Some(CSSValue::Length(_, CSSUnit::Percent)) | Some(CSSValue::Keyword(_)) | None => self.get_intrinsic_width(is_max, text_layout),
+        Some(val) => val.to_px_with(font_size, 0.0)
+      };
+      width + edge_width
+    } else {
+      self.get_intrinsic_width(is_max, text_layout)
+    }
+  }
+
+  /// 计算块级元素宽度
+  fn calc_block_width(&mut self, containing_block: Box, is_anonymous: bool, text_layout: &mut TextLayout) {
+    let style_node = self.get_style_node();
+    let auto = CSSValue::Keyword(String::from("auto"));
+    let zero = CSSValue::Length(0.0, CSSUnit::Px);
+    let containing_width = containing_block.content.width;
+    let font_size = style_node.get_font_size();
+    // 长度统一换算为像素：`em`相对于自身的字号，水平方向的百分比均相对于包含块的宽度
+    let resolve = |val: CSSValue| match val {
+      CSSValue::Length(..) => CSSValue::Length(val.to_px_with(font_size, containing_width), CSSUnit::Px),
+      _ => val
+    };
+    // 匿名box的样式来自父级，宽度应当自动填满父级
+    let width = match style_node.get_val("width") {
+      _ if is_anonymous => auto.clone(),
+      Some(CSSValue::Keyword(keyword)) if keyword == "max-content" || keyword == "min-content" => {
+        CSSValue::Length(self.get_intrinsic_width(keyword == "max-content", text_layout), CSSUnit::Px)
+      },
+      Some(val) => resolve(val),
+      None => auto.clone()
+    };
+    let margin_left = if is_anonymous { zero.clone() } else { resolve(style_node.look_up("margin-left", "margin", &zero)) };
+    let margin_right = if is_anonymous { zero.clone() } else { resolve(style_node.look_up("margin-right", "margin", &zero)) };
+    let padding_left = if is_anonymous { zero.clone() } else { resolve(style_node.look_up("padding-left", "padding", &zero)) };
+    let padding_right = if is_anonymous { zero.clone() } else { resolve(style_node.look_up("padding-right", "padding", &zero)) };
+    let border_left = if is_anonymous { zero.clone() } else { resolve(style_node.look_up("border-left-width", "border-width", &zero)) };
+    let border_right = if is_anonymous { zero.clone() } else { resolve(style_node.look_up("border-right-width", "border-width", &zero)) };
+    let edge_width = [
+      &border_left,
+      &padding_left,
+      &padding_right,
+      &border_right
+    ].iter().map(|val| val.to_px()).sum();
+    // 浮动盒子、固定定位盒子和inline-block的宽度不需要填满包含块
+    let is_inline_block = matches!(self.box_type, BoxType::InlineBlock(_));
+    let is_float = self.get_float() != Float::None || is_inline_block || self.is_fixed();
+    // inline-block的`auto`宽度取收缩适应宽度：`min(max(min-content, 可用宽度), max-content)`
+    let width = if is_inline_block && width == auto {
+      let available_width = containing_width - edge_width - margin_left.to_px() - margin_right.to_px();
+      let min_content = self.get_intrinsic_width(false, text_layout);
+      let max_content = self.get_intrinsic_width(true, text_layout);
+      CSSValue::Length(min_content.max(available_width).min(max_content), CSSUnit::Px)
+    } else {
+      width
+    };
+
+    let (mut used_width, mut used_margin_left, mut used_margin_right) = distribute_block_width(
+      containing_width,
+      is_float,
+      edge_width,
+      width,
+      margin_left.clone(),
+      margin_right.clone()
+    );
+
+    // 将宽度限制在`[min-width, max-width]`范围内；宽度被修正时需要以修正后的宽度重新分配外边距
+    let min_width = if is_anonymous { 0.0 } else { style_node.get_val("min-width").map(|val| resolve(val).to_px()).unwrap_or(0.0) };
+    let max_width = match style_node.get_val("max-width") {
+      Some(val @ CSSValue::Length(..)) if !is_anonymous => resolve(val).to_px(),
+      _ => f32::INFINITY
+    };
+    let clamped_width = used_width.to_px().min(max_width).max(min_width);
+    if clamped_width != used_width.to_px() {
+      (used_width, used_margin_left, used_margin_right) = distribute_block_width(
+        containing_width,
+        is_float,
+        edge_width,
+        CSSValue::Length(clamped_width, CSSUnit::Px),
+        margin_left,
+        margin_right
+      );
     }
 
     // 更新水平方向的宽度信息
-    self.box_model.content.width = width.to_px();
+    self.box_model.content.width = used_width.to_px();
     self.box_model.padding.left = padding_left.to_px();
     self.box_model.padding.right = padding_right.to_px();
     self.box_model.border.left = border_left.to_px();
     self.box_model.border.right = border_right.to_px();
-    self.box_model.margin.left = margin_left.to_px();
-    self.box_model.margin.right = margin_right.to_px();
+    self.box_model.margin.left = used_margin_left.to_px();
+    self.box_model.margin.right = used_margin_right.to_px();
   }
 
   /// 获取盒模型的竖直方向距离信息
@@ -274,13 +599,14 @@ impl<'a> LayoutBox<'a> {
     } else {
       let style_node = self.get_style_node();
       let zero = CSSValue::Length(0.0, CSSUnit::Px);
+      let font_size = style_node.get_font_size();
       (
-        style_node.look_up("margin-top", "margin", &zero).to_px(),
-        style_node.look_up("margin-bottom", "margin", &zero).to_px(),
-        style_node.look_up("border-top-width", "border-width", &zero).to_px(),
-        style_node.look_up("border-bottom-width", "border-width", &zero).to_px(),
-        style_node.look_up("padding-top", "padding", &zero).to_px(),
-        style_node.look_up("padding-bottom", "padding", &zero).to_px(),
+        style_node.look_up("margin-top", "margin", &zero).to_px_with(font_size, 0.0),
+        style_node.look_up("margin-bottom", "margin", &zero).to_px_with(font_size, 0.0),
+        style_node.look_up("border-top-width", "border-width", &zero).to_px_with(font_size, 0.0),
+        style_node.look_up("border-bottom-width", "border-width", &zero).to_px_with(font_size, 0.0),
+        style_node.look_up("padding-top", "padding", &zero).to_px_with(font_size, 0.0),
+        style_node.look_up("padding-bottom", "padding", &zero).to_px_with(font_size, 0.0),
       )
     }
   }
@@ -299,31 +625,219 @@ impl<'a> LayoutBox<'a> {
     box_model.content.x = containing_block.content.x + box_model.margin.left + box_model.border.left + box_model.padding.left;
     // 当前包含块的高度就是之前的子级元素撑开的高度，需要累加到当前元素的偏移中！
     box_model.content.y = containing_block.content.y + containing_block.content.height + box_model.margin.top + box_model.border.top + box_model.padding.top;
-    println!("border box: {:#?}", box_model.border);
-    println!("padding box: {:#?}", box_model.padding);
-    println!("content box: {:#?}", box_model.content);
+    // 高度由子级自底向上累加得到，累加前先清零，避免重复布局（如浮动盒子）时沿用旧的高度
+    box_model.content.height = 0.0;
+    // 清除浮动：`border-box`的顶部需要移动到相应浮动盒子的底部之下，移动的距离（clearance）计入上外边距
+    let clearance = self.get_clearance();
+    let box_model = &mut self.box_model;
+    box_model.margin.top += clearance;
+    box_model.content.y += clearance;
+    log::debug!("border box: {:#?}", box_model.border);
+    log::debug!("padding box: {:#?}", box_model.padding);
+    log::debug!("content box: {:#?}", box_model.content);
+  }
+
+  /// 计算清除浮动时需要向下移动的距离
+  fn get_clearance(&self) -> f32 {
+    let clear = match &self.box_type {
+      BoxType::Block(style_node) => style_node.get_clear(),
+      _ => Clear::None
+    };
+    if clear == Clear::None {
+      return 0.0;
+    }
+    let float_bottom = self.floats
+      .iter()
+      .filter(|float_box| match clear {
+        Clear::Left => float_box.float == Float::Left,
+        Clear::Right => float_box.float == Float::Right,
+        _ => true
+      })
+      .map(|float_box| float_box.area.y + float_box.area.height)
+      .fold(f32::MIN, f32::max);
+    let border_top = self.box_model.border_box().y;
+    (float_bottom - border_top).max(0.0)
+  }
+
+  /// 获取显式指定的高度；`auto`以及包含块高度不确定时的百分比返回`None`，匿名盒子的样式来自父级，同样返回`None`
+  fn get_explicit_height(&self) -> Option<f32> {
+    if let BoxType::AnonymousBlock(_) | BoxType::Line(_) = self.box_type {
+      return None;
+    }
+    match self.get_style_node().get_val("height") {
+      Some(CSSValue::Length(length, CSSUnit::Percent)) => self.containing_height.map(|height| length / 100.0 * height),
+      Some(height @ CSSValue::Length(..)) => Some(height.to_px_with(self.get_font_size(), 0.0)),
+      _ => None
+    }
+  }
+
+  /// 获取盒子内容的确定高度，作为子级计算百分比高度时包含块的高度；高度取决于内容时返回`None`
+  ///
+  /// 匿名盒子没有自己的高度，沿用其包含块的高度
+  fn get_definite_height(&self) -> Option<f32> {
+    match self.box_type {
+      BoxType::AnonymousBlock(_) | BoxType::Line(_) => self.containing_height,
+      _ => self.get_explicit_height()
+    }
   }
 
   /// 计算块级元素高度
+  ///
+  /// `auto`高度已经在计算子级时由line box和块级子级的外边距盒累加得到，这里只处理显式高度（包括相对于包含块高度的百分比）；
+  /// 显式高度小于内容高度时，子级保持原有的布局，超出的部分溢出
   fn calc_block_height(&mut self) {
-    if let Some(CSSValue::Length(height, CSSUnit::Px)) = self.get_style_node().get_val("height") {
+    if let Some(height) = self.get_explicit_height() {
       self.box_model.content.height = height;
     }
   }
 
+  /// 获取盒模型的上外边距（匿名box和line box没有外边距）
+  ///
+  /// 当上外边距与第一个子级的上外边距折叠时，返回折叠后的外边距
+  fn get_margin_top(&self) -> f32 {
+    if let BoxType::Block(_) | BoxType::Flex(_) | BoxType::Grid(_) = self.box_type {
+      let margin_top = self.get_box_vertical_info().0;
+      match self.children.iter().find(|child| child.get_float() == Float::None) {
+        Some(first_child) if self.collapses_with_first_child() => collapse_margins(margin_top, first_child.get_margin_top()),
+        _ => margin_top
+      }
+    } else {
+      0.0
+    }
+  }
+
+  /// 上外边距是否与第一个子级的上外边距折叠：没有上边框和上内边距分隔，且不是浮动盒子
+  fn collapses_with_first_child(&self) -> bool {
+    if let BoxType::Block(_) = self.box_type {
+      let (_, _, border_top, _, padding_top, _) = self.get_box_vertical_info();
+      border_top == 0.0 && padding_top == 0.0 && self.get_float() == Float::None
+    } else {
+      false
+    }
+  }
+
+  /// 下外边距是否与最后一个子级的下外边距折叠：没有下边框和下内边距分隔，高度为`auto`，且不是浮动盒子
+  fn collapses_with_last_child(&self) -> bool {
+    if let BoxType::Block(_) = self.box_type {
+      let (_, _, _, border_bottom, _, padding_bottom) = self.get_box_vertical_info();
+      let is_auto_height = self.get_explicit_height().is_none();
+      border_bottom == 0.0 && padding_bottom == 0.0 && is_auto_height && self.get_float() == Float::None
+    } else {
+      false
+    }
+  }
+
   /// 计算块级元素子元素布局
-  fn calc_block_children(&mut self) {
-    self.calc_block_line_box(); // 先计算line box，因为line box本质上改变了box tree的结构
+  fn calc_block_children(&mut self, text_layout: &mut TextLayout) {
+    self.calc_block_line_box(text_layout); // 先计算line box，因为line box本质上改变了box tree的结构
+    let collapse_top = self.collapses_with_first_child();
+    let collapse_bottom = self.collapses_with_last_child();
+    // 与第一个子级折叠上外边距：折叠后的外边距作为自身的上外边距，子级的上外边距则不再占据空间
+    let first_margin_top = self.children
+      .iter()
+      .find(|child| child.get_float() == Float::None)
+      .map(|child| child.get_margin_top());
+    if let (true, Some(first_margin_top)) = (collapse_top, first_margin_top) {
+      let margin_top = collapse_margins(self.box_model.margin.top, first_margin_top);
+      self.box_model.content.y += margin_top - self.box_model.margin.top;
+      self.box_model.margin.top = margin_top;
+    }
+    let definite_height = self.get_definite_height();
     let box_model = &mut self.box_model;
+    // 上一个兄弟节点的下外边距，用于相邻兄弟节点的外边距折叠
+    let mut prev_margin_bottom: Option<f32> = None;
     // 考虑到line box是动态产生的，这里应该用栈结构进行遍历
     for child in &mut self.children {
+      child.containing_height = definite_height;
+      // 浮动盒子在整个块级格式化上下文中共享
+      child.floats = self.floats.clone();
+      // 浮动盒子脱离文档流，不占据包含块的高度
+      if child.get_float() != Float::None {
+        child.calc_float_layout(*box_model, text_layout);
+        self.floats = child.floats.clone();
+        continue;
+      }
+      // 相邻的上下外边距会折叠，折叠后多出来的部分需要从包含块已有的高度中扣除
+      let overlap = match prev_margin_bottom {
+        Some(margin_bottom) => {
+          let margin_top = child.get_margin_top();
+          margin_bottom + margin_top - collapse_margins(margin_bottom, margin_top)
+        },
+        None if collapse_top => child.get_margin_top(),
+        None => 0.0
+      };
+      let mut containing_block = *box_model;
+      containing_block.content.height -= overlap;
       // 自顶向下计算元素布局
-      child.calc_layout(*box_model);
+      child.calc_layout(containing_block, text_layout);
       // 自底向上计算元素高度
-      box_model.content.height = box_model.content.height + child.box_model.margin_box().height;
+      box_model.content.height = box_model.content.height + child.box_model.margin_box().height - overlap;
+      prev_margin_bottom = Some(child.box_model.margin.bottom);
+      self.floats = child.floats.clone(); // 子级内部产生的浮动同样会影响后续兄弟节点
+    }
+    // 与最后一个子级折叠下外边距
+    if let (true, Some(last_margin_bottom)) = (collapse_bottom, prev_margin_bottom) {
+      box_model.content.height -= last_margin_bottom;
+      box_model.margin.bottom = collapse_margins(box_model.margin.bottom, last_margin_bottom);
     }
   }
 
+  /// 计算浮动盒子的布局
+  ///
+  /// 浮动盒子从当前文档流位置开始，贴靠包含块的左/右边缘放置，当剩余宽度放不下时则下移到已有浮动盒子的下方
+  fn calc_float_layout(&mut self, containing_block: Box, text_layout: &mut TextLayout) {
+    let float = self.get_float();
+    // 先计算宽度，以便确定浮动盒子的放置位置
+    self.calc_block_width(containing_block, false, text_layout);
+    let width = self.box_model.margin_box().width;
+    let mut y = containing_block.content.y + containing_block.content.height;
+    let (left, right) = loop {
+      let (left, right) = get_float_free_range(&self.floats, containing_block.content, y, 0.0);
+      if fits_within(width, right - left) {
+        break (left, right);
+      }
+      // 下移到当前行所有浮动盒子中最靠上的底边
+      let next_y = self.floats
+        .iter()
+        .map(|float_box| float_box.area.y + float_box.area.height)
+        .filter(|bottom| *bottom > y)
+        .min_by(|a, b| a.total_cmp(b));
+      match next_y {
+        Some(next_y) => y = next_y,
+        None => break (left, right)
+      }
+    };
+    let mut float_block = containing_block;
+    float_block.content.x = if float == Float::Left { left } else { right - width };
+    float_block.content.y = y;
+    float_block.content.width = width;
+    float_block.content.height = 0.0;
+    // 浮动盒子会建立新的块级格式化上下文，内部不受外部浮动影响
+    let floats = std::mem::take(&mut self.floats);
+    self.calc_block_layout(float_block, false, text_layout);
+    self.floats = floats;
+    self.floats.push(FloatBox {
+      float,
+      area: self.box_model.margin_box()
+    });
+  }
+
+  /// 新建一个`line box`，宽度为避开浮动盒子后的剩余宽度
+  ///
+  /// `lines`为当前已经生成的`line box`，用于确定新`line box`的竖直位置
+  fn new_line_box(&self, lines: &[LayoutBox], height: f32) -> LayoutBox<'a> {
+    let y = self.box_model.content.y + lines
+      .iter()
+      .filter(|line| matches!(line.box_type, BoxType::Line(_)))
+      .map(|line| line.children.iter().map(|child| child.box_model.margin_box().height).fold(0.0, f32::max))
+      .sum::<f32>();
+    let (left, right) = get_float_free_range(&self.floats, self.box_model.content, y, height);
+    let mut line = LayoutBox::new(BoxType::Line(self.get_style_node()));
+    line.box_model.content.x = left - self.box_model.content.x; // 相对包含块的水平偏移
+    line.box_model.content.width = right - left;
+    line
+  }
+
   /// 将inline box的子级全部平展到一维（应该是深度优先遍历？）
   fn flat_inline_box<'b>(&mut self) -> Vec<LayoutBox<'a>> {
     // 这里'b的生命周期应该在'a之内？
@@ -331,7 +845,7 @@ impl<'a> LayoutBox<'a> {
     while self.children.len() > 0 {
       let mut child = self.children.remove(0);
       match child.box_type {
-        BoxType::AnonymousInline(..) => {
+        BoxType::AnonymousInline(..) | BoxType::InlineBlock(_) => {
           all_children.push(child)
         },
         BoxType::Inline(_) => {
@@ -346,25 +860,95 @@ impl<'a> LayoutBox<'a> {
 
   /// 获取当前`line box`的剩余宽度
   fn get_line_rest_width(&self) -> f32 {
-    if let BoxType::Line = self.box_type {
-      self.box_model.content.width - self.children.iter().map(|child| child.box_model.content.width).sum::<f32>()
+    if let BoxType::Line(_) = self.box_type {
+      self.box_model.content.width - self.children.iter().map(|child| child.box_model.margin_box().width).sum::<f32>()
     } else {
       0.0
     }
   }
 
+  /// 截断`line box`内溢出的文本，并在截断处追加省略号
+  ///
+  /// 从第一个溢出的文本末尾逐个移除字符，直到剩余文本加上省略号能够放下，之后的文本全部丢弃
+  fn truncate_line_box(&mut self, text_layout: &mut TextLayout) {
+    if fits_within(0.0, self.get_line_rest_width()) {
+      return;
+    }
+    let (ellipsis_width, ellipsis_height) = self.calc_text_layout("…", text_layout);
+    let ellipsis_glyphs = text_layout.layout.glyphs().clone();
+    let max_width = self.box_model.content.width - ellipsis_width;
+    let overflow_idx = self.children
+      .iter()
+      .position(|child| !fits_within(child.box_model.margin_box().x + child.box_model.margin_box().width, max_width));
+    if let Some(idx) = overflow_idx {
+      // inline-block无法截断，直接丢弃
+      if let BoxType::InlineBlock(_) = self.children[idx].box_type {
+        self.children.truncate(idx);
+        return;
+      }
+      self.children.truncate(idx + 1);
+      let child = &mut self.children[idx];
+      let rest_width = max_width - child.box_model.content.x;
+      let mut glyphs = child.glyphs.lock().unwrap();
+      while let Some(glyph) = glyphs.last() {
+        if fits_within(glyph.x + glyph.width as f32, rest_width) {
+          break;
+        }
+        glyphs.pop();
+      }
+      let text_width = glyphs.last().map(|glyph| glyph.x + glyph.width as f32).unwrap_or(0.0).max(0.0);
+      let half_leading = (child.box_model.content.height - ellipsis_height) / 2.0;
+      glyphs.extend(ellipsis_glyphs.into_iter().map(|mut glyph| {
+        glyph.x += text_width;
+        glyph.y += half_leading;
+        glyph
+      }));
+      drop(glyphs);
+      child.box_model.content.width = text_width + ellipsis_width;
+    }
+  }
+
+  /// 按照`text-align`调整`line box`内子级的水平位置
+  ///
+  /// `justify`时剩余宽度平分到子级之间的间隙，最后一行则按左对齐处理
+  fn align_line_box(&mut self, text_align: TextAlign, is_last_line: bool) {
+    let rest_width = self.get_line_rest_width();
+    if fits_within(rest_width, 0.0) {
+      return;
+    }
+    match text_align {
+      TextAlign::Left => {},
+      TextAlign::Right | TextAlign::Center => {
+        let offset = if text_align == TextAlign::Right { rest_width } else { rest_width / 2.0 };
+        for child in self.children.iter_mut() {
+          child.translate(offset, 0.0);
+        }
+      },
+      TextAlign::Justify => {
+        if is_last_line || self.children.len() < 2 {
+          return;
+        }
+        let gap = rest_width / (self.children.len() - 1) as f32;
+        for (idx, child) in self.children.iter_mut().enumerate() {
+          child.translate(gap * idx as f32, 0.0);
+        }
+      }
+    }
+  }
+
   /// 计算block box内部的line box结构
   ///
   /// 这里顺便计算了line box内部文本（匿名inline box）的宽度，高度和起始位置
-  fn calc_block_line_box(&mut self) {
+  fn calc_block_line_box(&mut self, text_layout: &mut TextLayout) {
     if self.children.len() == 0 {
       return;
     }
+    let definite_height = self.get_definite_height();
     let mut all_children: Vec<LayoutBox<'_>> = vec![];
     while self.children.len() > 0 {
       let mut cur_child = self.children.remove(0);
       match cur_child.box_type {
-        BoxType::Block(_) | BoxType::AnonymousBlock(_) | BoxType::AnonymousInline(..) => {
+        BoxType::Block(_) | BoxType::Flex(_) | BoxType::Grid(_) | BoxType::AnonymousBlock(_) | BoxType::AnonymousInline(..) | BoxType::InlineBlock(_) => {
           all_children.push(cur_child)
         },
         BoxType::Inline(_) => {
@@ -374,80 +958,644 @@ impl<'a> LayoutBox<'a> {
         _ => {} // 初始box tree不会产生line box，所以不需要考虑
       }
     }
+    let style_node = self.get_style_node();
+    let is_keyword = |name: &str, keyword: &str| matches!(style_node.get_val(name), Some(CSSValue::Keyword(val)) if val == keyword);
+    // `white-space: nowrap`/`pre`时文本不会自动换行
+    let is_nowrap = style_node.get_white_space() != WhiteSpace::Normal;
     let mut line_and_children: Vec<LayoutBox<'_>> = vec![];
+    // `pre`文本中的换行符：`break_after_next`表示队列中的下一个盒子之后需要换行，`force_break`表示接下来的盒子需要排在新的一行
+    let mut break_after_next = false;
+    let mut force_break = false;
     while all_children.len() > 0 {
       let mut cur_child = all_children.remove(0);
       match cur_child.box_type {
-        BoxType::Block(_) | BoxType::AnonymousBlock(_) => {
+        BoxType::Block(_) | BoxType::Flex(_) | BoxType::Grid(_) | BoxType::AnonymousBlock(_) => {
           line_and_children.push(cur_child)
         },
-        BoxType::AnonymousInline(content, _) => {
-          let (w, h) = cur_child.calc_text_layout(content);
-          println!("文本宽高: {w}, {h}; {content}");
-          let text_layout = get_text_layout();
-          cur_child.box_model.content.width = w;
-          cur_child.box_model.content.height = h; // 设置行高
-          let mut glyphs = cur_child.glyphs.lock().unwrap();
-          *glyphs = text_layout.layout.glyphs().clone(); // TODO: 不知道这里能不能引用，主要是担心clear操作会清空
+        BoxType::AnonymousInline(..) | BoxType::InlineBlock(_) => {
+          if let BoxType::AnonymousInline(content, style_node) = &cur_child.box_type {
+            let is_line_start = force_break || line_and_children
+              .iter()
+              .rev()
+              .find(|child| matches!(child.box_type, BoxType::Line(_)))
+              .is_none_or(|line| line.children.is_empty());
+            if style_node.get_white_space() == WhiteSpace::Pre {
+              // 在第一个换行符处拆分，换行符之前的部分排在当前行，剩余部分排在新的一行；空行用一个空格撑起行高
+              if let Some((head, tail)) = content.split_once('\n') {
+                let head = head.strip_suffix('\r').unwrap_or(head);
+                let head = if head.is_empty() { " " } else { head };
+                if !tail.is_empty() {
+                  all_children.insert(0, LayoutBox::new(BoxType::AnonymousInline(Cow::Owned(tail.to_string()), style_node.clone())));
+                }
+                all_children.insert(0, LayoutBox::new(BoxType::AnonymousInline(Cow::Owned(head.to_string()), style_node.clone())));
+                break_after_next = true;
+                continue;
+              }
+            } else if is_line_start && content.starts_with(' ') {
+              // 行首合并后的空白会被忽略
+              let content = content.trim_start_matches(' ');
+              if !content.is_empty() {
+                all_children.insert(0, LayoutBox::new(BoxType::AnonymousInline(Cow::Owned(content.to_string()), style_node.clone())));
+              }
+              continue;
+            }
+          }
+          let is_break_after = break_after_next;
+          break_after_next = false;
+          // 行内元素也可以单独设置`white-space`
+          let is_nowrap = is_nowrap || matches!(&cur_child.box_type, BoxType::AnonymousInline(_, style_node) if style_node.get_white_space() != WhiteSpace::Normal);
+          let (w, line_height) = match &cur_child.box_type {
+            BoxType::AnonymousInline(content, _) => {
+              let (w, h) = cur_child.calc_text_layout(content, text_layout);
+              log::debug!("文本宽高: {w}, {h}; {content}");
+              // 行高不小于文本实际高度，多出来的部分平分到文本上下（即半行距）
+              let line_height = cur_child.get_line_height().unwrap_or(h).max(h);
+              let half_leading = (line_height - h) / 2.0;
+              cur_child.box_model.content.width = w;
+              cur_child.box_model.content.height = line_height; // 设置行高
+              let mut glyphs = cur_child.glyphs.lock().unwrap();
+              *glyphs = text_layout.layout.glyphs().clone(); // TODO: 不知道这里能不能引用，主要是担心clear操作会清空
+              for glyph in glyphs.iter_mut() {
+                glyph.y += half_leading;
+              }
+              drop(glyphs);
+              let ascent = text_layout.layout.lines().and_then(|lines| lines.first()).map(|line| line.baseline_y).unwrap_or(h);
+              cur_child.baseline = half_leading + ascent;
+              (w, line_height)
+            },
+            _ => {
+              // inline-block内部按块级盒子布局，先以原点为起点计算，放入line box后再整体平移
+              let mut containing_block = self.box_model;
+              containing_block.content.x = 0.0;
+              containing_block.content.y = 0.0;
+              containing_block.content.height = 0.0;
+              cur_child.containing_height = definite_height;
+              cur_child.calc_block_layout(containing_block, false, text_layout);
+              let margin_box = cur_child.box_model.margin_box();
+              (margin_box.width, margin_box.height)
+            }
+          };
+          if force_break {
+            let new_line = self.new_line_box(&line_and_children, line_height);
+            line_and_children.push(new_line);
+            force_break = false;
+          }
           let mut last_line: Option<&mut LayoutBox> = None;
-          drop(glyphs);
 
           for child in line_and_children.iter_mut() {
-            if let BoxType::Line = child.box_type {
+            if let BoxType::Line(_) = child.box_type {
               last_line = Some(child);
             }
           }
 
           if let None = last_line {
-            let mut new_line = LayoutBox::new(BoxType::Line);
-            new_line.box_model.content.width = self.box_model.content.width;
+            let new_line = self.new_line_box(&line_and_children, line_height);
             line_and_children.push(new_line);
             last_line = line_and_children.last_mut();
           }
 
-          let mut last_line_box = last_line.unwrap();
+          let last_line_box = last_line.unwrap();
           let rest_width = last_line_box.get_line_rest_width();
+          let is_empty_line = last_line_box.children.is_empty();
+          let is_fit = fits_within(w, rest_width);
 
-          if rest_width >= w {
-            println!("剩余宽度: {rest_width}");
-            cur_child.box_model.content.x = last_line_box.box_model.content.width - rest_width; // 水平排列
+          // 文本在当前行放不下时按单词拆分：放得下的部分和剩余部分重新加入待排列的队列
+          if let (false, BoxType::AnonymousInline(content, style_node)) = (is_fit || is_nowrap, &cur_child.box_type) {
+            if let Some((head, tail)) = split_text(content, style_node.get_font_size(), rest_width, is_empty_line, text_layout) {
+              if !tail.trim().is_empty() {
+                all_children.insert(0, LayoutBox::new(BoxType::AnonymousInline(Cow::Owned(tail), style_node.clone())));
+              }
+              all_children.insert(0, LayoutBox::new(BoxType::AnonymousInline(Cow::Owned(head), style_node.clone())));
+              continue;
+            }
+          }
+
+          // 空行中放不下的内容只能溢出
+          if is_fit || is_nowrap || is_empty_line {
+            log::debug!("剩余宽度: {rest_width}");
+            cur_child.translate(last_line_box.box_model.content.width - rest_width, 0.0); // 水平排列
             last_line_box.children.push(cur_child);
-          } else { // line box剩余宽度不够时则新加一行（目前不考虑单行文本换行的情况）
-            let mut new_line = LayoutBox::new(BoxType::Line);
-            new_line.box_model.content.width = self.box_model.content.width;
+            force_break = is_break_after;
+          } else { // line box剩余宽度不够时则新加一行，文本在新的一行中重新排列（仍然放不下时会继续拆分）
+            let new_line = self.new_line_box(&line_and_children, line_height);
             line_and_children.push(new_line);
-            last_line = line_and_children.last_mut();
-            last_line_box = last_line.unwrap();
-            cur_child.box_model.content.x = 0.0;
-            last_line_box.children.push(cur_child);
+            if let BoxType::AnonymousInline(content, style_node) = &cur_child.box_type {
+              // 行首的空白会被忽略
+              let content = content.trim_start().to_string();
+              all_children.insert(0, LayoutBox::new(BoxType::AnonymousInline(Cow::Owned(content), style_node.clone())));
+            } else {
+              line_and_children.last_mut().unwrap().children.push(cur_child);
+            }
           }
         },
         _ => {} // 这里理论上不存在不包含文字的line box了
       }
     }
 
+    // 溢出隐藏且不换行时，溢出的文本以省略号结尾
+    if is_keyword("overflow", "hidden") && is_nowrap && is_keyword("text-overflow", "ellipsis") {
+      for line in line_and_children.iter_mut().filter(|child| matches!(child.box_type, BoxType::Line(_))) {
+        line.truncate_line_box(text_layout);
+      }
+    }
+
+    // 根据`text-align`分配每个line box的剩余宽度
+    let text_align = style_node.get_text_align();
+    let line_num = line_and_children.iter().filter(|child| matches!(child.box_type, BoxType::Line(_))).count();
+    for (idx, line) in line_and_children.iter_mut().filter(|child| matches!(child.box_type, BoxType::Line(_))).enumerate() {
+      line.align_line_box(text_align, idx + 1 == line_num);
+    }
+
     self.children = line_and_children;
   }
 
-  fn calc_block_layout(&mut self, containing_block: Box, is_anonymous: bool) {
+  fn calc_block_layout(&mut self, containing_block: Box, is_anonymous: bool, text_layout: &mut TextLayout) {
     // 自顶向下计算宽度和起点
-    self.calc_block_width(containing_block, is_anonymous);
+    self.calc_block_width(containing_block, is_anonymous, text_layout);
     self.calc_block_position(containing_block);
-    self.calc_block_children();
+    self.calc_block_children(text_layout);
     // 自底向上计算高度
     self.calc_block_height();
+    self.calc_sticky_children();
+  }
+
+  /// 记录粘性定位子级的阈值，子级的包含块即为当前盒子，因此需要在自身高度确定之后计算
+  ///
+  /// `top`/`bottom`只支持长度，百分比按`auto`处理
+  fn calc_sticky_children(&mut self) {
+    let limit = self.box_model.content;
+    for child in &mut self.children {
+      let style_node = match &child.box_type {
+        BoxType::Block(style_node) | BoxType::Flex(style_node) | BoxType::Grid(style_node) if style_node.get_position() == Position::Sticky => style_node.clone(),
+        _ => continue
+      };
+      let get_offset = |name: &str| match style_node.get_val(name) {
+        Some(CSSValue::Length(_, CSSUnit::Percent)) => None,
+        Some(length @ CSSValue::Length(..)) => Some(length.to_px_with(style_node.get_font_size(), 0.0)),
+        _ => None
+      };
+      child.sticky = Some(StickyOffsets {
+        top: get_offset("top"),
+        bottom: get_offset("bottom"),
+        limit
+      });
+    }
+  }
+
+  /// 计算固定定位盒子的布局
+  ///
+  /// 包含块总是视窗（初始包含块），与盒子在文档中的位置无关；先在视窗左上角布局，再按照`top`/`right`/`bottom`/`left`整体平移。
+  /// 同时指定`left`和`right`时，`auto`宽度填满两者之间的区域；偏移都为`auto`时留在视窗左上角
+  fn calc_fixed_layout(&mut self, viewport: Box, text_layout: &mut TextLayout) {
+    let style_node = self.get_style_node();
+    let viewport_area = viewport.content;
+    let get_offset = |name: &str, base: f32| match style_node.get_val(name) {
+      Some(length @ CSSValue::Length(..)) => Some(length.to_px_with(style_node.get_font_size(), base)),
+      _ => None
+    };
+    let top = get_offset("top", viewport_area.height);
+    let right = get_offset("right", viewport_area.width);
+    let bottom = get_offset("bottom", viewport_area.height);
+    let left = get_offset("left", viewport_area.width);
+    self.containing_height = Some(viewport_area.height);
+    let mut containing_block = viewport;
+    containing_block.content.height = 0.0; // 包含块的高度用于累加竖直偏移
+    if let (Some(left), Some(right)) = (left, right) {
+      containing_block.content.x += left;
+      containing_block.content.width -= left + right;
+    }
+    match self.box_type {
+      BoxType::Flex(_) => self.calc_flex_layout(containing_block, text_layout),
+      BoxType::Grid(_) => self.calc_grid_layout(containing_block, text_layout),
+      _ => self.calc_block_layout(containing_block, false, text_layout)
+    }
+    let margin_box = self.box_model.margin_box();
+    let dx = match (left, right) {
+      (Some(left), None) => left,
+      (None, Some(right)) => viewport_area.width - right - margin_box.width,
+      _ => 0.0
+    };
+    let dy = match (top, bottom) {
+      (Some(top), _) => top,
+      (None, Some(bottom)) => viewport_area.height - bottom - margin_box.height,
+      _ => 0.0
+    };
+    self.translate(dx, dy);
+  }
+
+  /// 计算`flex`容器的布局
+  ///
+  /// 子项先取`flex-basis`作为主轴上的基础尺寸，剩余空间按`flex-grow`分配，仍有剩余时按`justify-content`对齐；
+  /// 交叉轴上按`align-items`对齐；容器的`auto`高度能够容纳所有子项。只有水平排列支持换行（`flex-wrap`），竖直排列总是单行
+  fn calc_flex_layout(&mut self, containing_block: Box, text_layout: &mut TextLayout) {
+    self.calc_block_width(containing_block, false, text_layout);
+    self.calc_block_position(containing_block);
+    let definite_height = self.get_definite_height();
+    for child in &mut self.children {
+      child.containing_height = definite_height;
+    }
+    let style_node = self.get_style_node();
+    let align_items = style_node.get_align_items();
+    match style_node.get_flex_direction() {
+      FlexDirection::Row => {
+        let lines = self.calc_flex_row_children(align_items, text_layout);
+        self.box_model.content.height = lines.iter().map(|line| line.cross_size).sum();
+        self.calc_block_height();
+        // 交叉轴尺寸即容器的高度，需要在容器高度确定之后分配各行并对齐子项
+        self.align_flex_lines(lines, align_items);
+      },
+      FlexDirection::Column => {
+        self.box_model.content.height = self.calc_flex_column_children(definite_height, align_items, text_layout);
+        self.calc_block_height();
+      }
+    }
+    self.calc_sticky_children();
+  }
+
+  /// 水平排列子项：基础尺寸为`flex-basis`，`auto`时取`max-content`宽度（显式宽度优先）
+  ///
+  /// `flex-wrap: wrap`时按基础尺寸贪心地分行，放不下的子项移到新的一行；每一行独立分配剩余空间。
+  /// 各行依次紧挨着排列，子项都在所在行的顶部布局，`align-items: baseline`时先在行内按基线对齐；返回所有行
+  fn calc_flex_row_children(&mut self, align_items: AlignItems, text_layout: &mut TextLayout) -> Vec<FlexLine> {
+    let container_box = self.box_model;
+    let container = container_box.content;
+    let base_widths: Vec<f32> = self.children
+      .iter()
+      .map(|child| match child.get_flex_basis(container.width) {
+        Some(basis) => basis + child.get_horizontal_edge_width(),
+        None => child.get_intrinsic_outer_width(true, text_layout)
+      })
+      .collect();
+    let style_node = self.get_style_node();
+    let is_wrap = style_node.get_flex_wrap() == FlexWrap::Wrap;
+    let justify_content = style_node.get_justify_content();
+    // 每一行至少有一个子项
+    let mut ranges: Vec<Range<usize>> = vec![];
+    let mut line_width = 0.0;
+    for (idx, width) in base_widths.iter().enumerate() {
+      match ranges.last_mut() {
+        Some(range) if !is_wrap || fits_within(line_width + width, container.width) => {
+          range.end = idx + 1;
+          line_width += width;
+        },
+        _ => {
+          ranges.push(idx..idx + 1);
+          line_width = *width;
+        }
+      }
+    }
+    let mut lines = Vec::with_capacity(ranges.len());
+    let mut y = 0.0;
+    for range in ranges {
+      let items = &mut self.children[range.clone()];
+      let widths = grow_flex_items(items, &base_widths[range.clone()], container.width);
+      let (mut x, gap) = get_distribution_offsets(justify_content, items.len(), container.width - widths.iter().sum::<f32>());
+      let mut cross_size: f32 = 0.0;
+      for (child, width) in items.iter_mut().zip(widths) {
+        let mut item_block = container_box;
+        item_block.content.x = container.x + x;
+        item_block.content.y = container.y + y;
+        item_block.content.width = width;
+        item_block.content.height = 0.0;
+        child.calc_flex_item_layout(item_block, text_layout);
+        cross_size = cross_size.max(child.box_model.margin_box().height);
+        x += width + gap;
+      }
+      if align_items == AlignItems::Baseline {
+        cross_size = align_flex_baselines(items, container.y + y);
+      }
+      y += cross_size;
+      lines.push(FlexLine { items: range, cross_size });
+    }
+    lines
+  }
+
+  /// 按照`align-content`在竖直方向上分配各行，再按照`align-items`对齐每一行内的子项
+  ///
+  /// 单行（`nowrap`）容器的行高即容器的高度；多行容器有剩余空间时，`stretch`（默认）平分给每一行
+  fn align_flex_lines(&mut self, lines: Vec<FlexLine>, align_items: AlignItems) {
+    let style_node = self.get_style_node();
+    let count = lines.len();
+    let rest_space = self.box_model.content.height - lines.iter().map(|line| line.cross_size).sum::<f32>();
+    // 第一行的偏移、行之间的间距及每一行增加的高度
+    let (mut offset, gap, extra) = match (style_node.get_flex_wrap(), style_node.get_align_content()) {
+      (FlexWrap::NoWrap, _) => (0.0, 0.0, rest_space),
+      (FlexWrap::Wrap, AlignContent::Stretch) => (0.0, 0.0, if count > 0 { rest_space.max(0.0) / count as f32 } else { 0.0 }),
+      (FlexWrap::Wrap, align_content) => {
+        let justify_content = match align_content {
+          AlignContent::FlexEnd => JustifyContent::FlexEnd,
+          AlignContent::Center => JustifyContent::Center,
+          AlignContent::SpaceBetween => JustifyContent::SpaceBetween,
+          AlignContent::SpaceAround => JustifyContent::SpaceAround,
+          AlignContent::SpaceEvenly => JustifyContent::SpaceEvenly,
+          _ => JustifyContent::FlexStart
+        };
+        let (offset, gap) = get_distribution_offsets(justify_content, count, rest_space);
+        (offset, gap, 0.0)
+      }
+    };
+    for line in lines {
+      let items = &mut self.children[line.items];
+      for child in items.iter_mut() {
+        child.translate(0.0, offset);
+      }
+      align_flex_line_items(items, align_items, line.cross_size + extra);
+      offset += gap + extra;
+    }
+  }
+
+  /// 获取列轨道列表，没有显式的列轨道时只有一个`auto`列
+  fn get_grid_tracks(&self) -> Vec<TrackSize> {
+    let tracks = self.get_style_node().get_grid_template_columns();
+    if tracks.is_empty() {
+      vec![TrackSize::Auto]
+    } else {
+      tracks
+    }
+  }
+
+  /// 获取各列的内在宽度：固定尺寸的列取其尺寸，其它列取只占据该列的子项的内在宽度（`margin-box`）中的最大值；
+  /// 跨越多列的子项不影响列宽
+  fn get_grid_column_widths(&self, tracks: &[TrackSize], areas: &[GridArea], is_max: bool, text_layout: &mut TextLayout) -> Vec<f32> {
+    let mut widths: Vec<f32> = tracks
+      .iter()
+      .map(|track| match track {
+        TrackSize::Fixed(size) => *size,
+        _ => 0.0
+      })
+      .collect();
+    for (child, area) in self.children.iter().zip(areas) {
+      let column = area.column_start;
+      if area.column_end == column + 1 && !matches!(tracks[column], TrackSize::Fixed(_)) {
+        widths[column] = widths[column].max(child.get_intrinsic_outer_width(is_max, text_layout));
+      }
+    }
+    widths
+  }
+
+  /// 确定各个grid item所在的网格区域，列方向不会产生隐式轨道，超出列轨道数量的部分被截断
+  ///
+  /// 先放置行列位置都确定的子项，再把只确定了行的子项放到该行第一个放得下的位置，最后按文档顺序自动放置其余子项；
+  /// 自动放置的游标只会向后移动，跳过已经被占据的单元格
+  fn get_grid_areas(&self, column_count: usize) -> Vec<GridArea> {
+    let placements: Vec<(GridSpan, GridSpan)> = self.children
+      .iter()
+      .map(|child| match &child.box_type {
+        BoxType::Block(style_node) | BoxType::Flex(style_node) | BoxType::Grid(style_node) => {
+          let (row_start, row_end) = style_node.get_grid_row();
+          let (column_start, column_end) = style_node.get_grid_column();
+          // 没有显式的行轨道，负数编号的行网格线都指向第一条
+          let row = resolve_grid_lines(row_start, row_end, 0);
+          let (column, column_span) = resolve_grid_lines(column_start, column_end, column_count);
+          let column_span = column_span.min(column_count);
+          (row, (column.map(|column| column.min(column_count - column_span)), column_span))
+        },
+        _ => ((None, 1), (None, 1))
+      })
+      .collect();
+    let mut occupied: Vec<Vec<bool>> = vec![];
+    let mut areas: Vec<Option<GridArea>> = vec![None; placements.len()];
+    let mut place = |idx: usize, area: GridArea, occupied: &mut Vec<Vec<bool>>| {
+      if occupied.len() < area.row_end {
+        occupied.resize(area.row_end, vec![false; column_count]);
+      }
+      for cells in &mut occupied[area.row_start..area.row_end] {
+        cells[area.column_start..area.column_end].fill(true);
+      }
+      areas[idx] = Some(area);
+    };
+    for (idx, &((row, row_span), (column, column_span))) in placements.iter().enumerate() {
+      if let (Some(row), Some(column)) = (row, column) {
+        place(idx, GridArea::new(row, row_span, column, column_span), &mut occupied);
+      }
+    }
+    for (idx, &((row, row_span), (column, column_span))) in placements.iter().enumerate() {
+      if let (Some(row), None) = (row, column) {
+        let column = (0..=column_count - column_span)
+          .find(|&column| is_grid_area_free(&occupied, &GridArea::new(row, row_span, column, column_span)))
+          .unwrap_or(0);
+        place(idx, GridArea::new(row, row_span, column, column_span), &mut occupied);
+      }
+    }
+    let (mut cursor_row, mut cursor_column) = (0, 0);
+    for (idx, &((row, row_span), (column, column_span))) in placements.iter().enumerate() {
+      if row.is_some() {
+        continue;
+      }
+      let area = match column {
+        Some(column) => {
+          if column < cursor_column {
+            cursor_row += 1;
+          }
+          while !is_grid_area_free(&occupied, &GridArea::new(cursor_row, row_span, column, column_span)) {
+            cursor_row += 1;
+          }
+          GridArea::new(cursor_row, row_span, column, column_span)
+        },
+        None => loop {
+          if cursor_column + column_span > column_count {
+            cursor_row += 1;
+            cursor_column = 0;
+          }
+          let area = GridArea::new(cursor_row, row_span, cursor_column, column_span);
+          if is_grid_area_free(&occupied, &area) {
+            break area;
+          }
+          cursor_column += 1;
+        }
+      };
+      cursor_column = area.column_end;
+      place(idx, area, &mut occupied);
+    }
+    areas.into_iter().flatten().collect()
+  }
+
+  /// 计算`grid`容器的布局
+  ///
+  /// 子项按`grid-column`/`grid-row`放置到网格区域（见`get_grid_areas`），列宽按`grid-template-columns`确定（见`size_grid_tracks`）；
+  /// 行高为只占据该行的子项中最高的`margin-box`高度，跨行子项放不下时增加其最后一行的高度；
+  /// `auto`高度的子项拉伸填满所在的区域，容器的`auto`高度能够容纳所有行
+  fn calc_grid_layout(&mut self, containing_block: Box, text_layout: &mut TextLayout) {
+    self.calc_block_width(containing_block, false, text_layout);
+    self.calc_block_position(containing_block);
+    let definite_height = self.get_definite_height();
+    for child in &mut self.children {
+      child.containing_height = definite_height;
+    }
+    let container_box = self.box_model;
+    let container = container_box.content;
+    let tracks = self.get_grid_tracks();
+    let areas = self.get_grid_areas(tracks.len());
+    let content_widths = self.get_grid_column_widths(&tracks, &areas, true, text_layout);
+    let column_offsets = get_track_offsets(&size_grid_tracks(&tracks, &content_widths, container.width));
+    // 子项先统一在容器顶部布局，确定行高之后再平移到所在的行
+    for (child, area) in self.children.iter_mut().zip(&areas) {
+      let mut item_block = container_box;
+      item_block.content.x = container.x + column_offsets[area.column_start];
+      item_block.content.width = column_offsets[area.column_end] - column_offsets[area.column_start];
+      item_block.content.height = 0.0;
+      child.calc_flex_item_layout(item_block, text_layout);
+      child.grid_area = Some(*area);
+    }
+    let row_count = areas.iter().map(|area| area.row_end).max().unwrap_or(0);
+    let mut heights = vec![0.0; row_count];
+    for (child, area) in self.children.iter().zip(&areas) {
+      if area.row_end == area.row_start + 1 {
+        heights[area.row_start] = child.box_model.margin_box().height.max(heights[area.row_start]);
+      }
+    }
+    for (child, area) in self.children.iter().zip(&areas) {
+      let spanned: f32 = heights[area.row_start..area.row_end].iter().sum();
+      let rest_space = child.box_model.margin_box().height - spanned;
+      if rest_space > 0.0 {
+        heights[area.row_end - 1] += rest_space;
+      }
+    }
+    let row_offsets = get_track_offsets(&heights);
+    for (child, area) in self.children.iter_mut().zip(&areas) {
+      child.translate(0.0, row_offsets[area.row_start]);
+      align_flex_line_items(std::slice::from_mut(child), AlignItems::Stretch, row_offsets[area.row_end] - row_offsets[area.row_start]);
+    }
+    self.box_model.content.height = row_offsets[row_count];
+    self.calc_block_height();
+    self.calc_sticky_children();
+  }
+
+  /// 获取盒子内第一行文本的基线位置（绝对坐标），没有文本时返回`None`
+  fn get_first_baseline(&self) -> Option<f32> {
+    match &self.box_type {
+      BoxType::AnonymousInline(..) if !self.glyphs.lock().unwrap().is_empty() => Some(self.box_model.content.y + self.baseline),
+      _ => self.children.iter().find_map(|child| child.get_first_baseline())
+    }
+  }
+
+  /// 竖直排列时子项在水平方向上的`margin-box`宽度：`stretch`且`auto`宽度时填满容器，
+  /// 否则取`max-content`宽度（显式宽度优先），`auto`宽度不超过容器的宽度
+  fn get_flex_cross_width(&self, align_items: AlignItems, container_width: f32, text_layout: &mut TextLayout) -> f32 {
+    let is_auto_width = match &self.box_type {
+      BoxType::Block(style_node) | BoxType::Flex(style_node) | BoxType::Grid(style_node) => matches!(
+        style_node.get_val("width"),
+        Some(CSSValue::Length(_, CSSUnit::Percent)) | Some(CSSValue::Keyword(_)) | None
+      ),
+      _ => true
+    };
+    match (is_auto_width, align_items) {
+      (true, AlignItems::Stretch) => container_width,
+      (true, _) => self.get_intrinsic_outer_width(true, text_layout).min(container_width),
+      (false, _) => self.get_intrinsic_outer_width(true, text_layout)
+    }
+  }
+
+  /// 竖直排列子项：基础尺寸为`flex-basis`，`auto`时取子项按容器宽度布局后的`margin-box`高度；
+  /// 只有容器高度确定时才有剩余空间可以分配；返回容器的内容高度，即所有子项占据的高度
+  ///
+  /// 子项的布局与竖直位置无关，因此先统一在容器顶部布局，确定主轴尺寸后再平移到各自的位置；
+  /// 水平方向上按照`align-items`确定子项的宽度及位置
+  fn calc_flex_column_children(&mut self, definite_height: Option<f32>, align_items: AlignItems, text_layout: &mut TextLayout) -> f32 {
+    let container = self.box_model.content;
+    let mut base_heights = Vec::with_capacity(self.children.len());
+    for child in &mut self.children {
+      let width = child.get_flex_cross_width(align_items, container.width, text_layout);
+      let mut item_block = self.box_model;
+      item_block.content.x = container.x + match align_items {
+        AlignItems::FlexEnd => container.width - width,
+        AlignItems::Center => (container.width - width) / 2.0,
+        _ => 0.0
+      };
+      item_block.content.width = width;
+      item_block.content.height = 0.0;
+      child.calc_flex_item_layout(item_block, text_layout);
+      let base_height = match child.get_flex_basis(definite_height.unwrap_or(0.0)) {
+        Some(basis) => basis + child.get_vertical_edge_height(),
+        None => child.box_model.margin_box().height
+      };
+      base_heights.push(base_height);
+    }
+    let main_size = definite_height.unwrap_or(base_heights.iter().sum());
+    let heights = grow_flex_items(&self.children, &base_heights, main_size);
+    let justify_content = self.get_style_node().get_justify_content();
+    let (mut y, gap) = get_distribution_offsets(justify_content, heights.len(), main_size - heights.iter().sum::<f32>());
+    for (child, height) in self.children.iter_mut().zip(&heights) {
+      child.translate(0.0, y);
+      // 主轴尺寸由flex算法决定，内容超出时溢出
+      child.box_model.content.height = (height - child.get_vertical_edge_height()).max(0.0);
+      y += height + gap;
+    }
+    if heights.is_empty() { 0.0 } else { y - gap }
+  }
+
+  /// 获取子项的`flex-basis`（`content-box`尺寸），百分比相对于容器主轴方向的尺寸；`auto`返回`None`
+  fn get_flex_basis(&self, container_size: f32) -> Option<f32> {
+    let BoxType::Block(style_node) = &self.box_type else {
+      return None;
+    };
+    match style_node.get_val("flex-basis") {
+      Some(length @ CSSValue::Length(..)) => Some(length.to_px_with(style_node.get_font_size(), container_size)),
+      _ => None
+    }
+  }
+
+  /// 左右外边距、边框及内边距的宽度之和
+  fn get_horizontal_edge_width(&self) -> f32 {
+    let BoxType::Block(style_node) = &self.box_type else {
+      return 0.0;
+    };
+    let zero = CSSValue::Length(0.0, CSSUnit::Px);
+    [
+      style_node.look_up("margin-left", "margin", &zero),
+      style_node.look_up("margin-right", "margin", &zero),
+      style_node.look_up("border-left-width", "border-width", &zero),
+      style_node.look_up("border-right-width", "border-width", &zero),
+      style_node.look_up("padding-left", "padding", &zero),
+      style_node.look_up("padding-right", "padding", &zero)
+    ].iter().map(|val| val.to_px_with(style_node.get_font_size(), 0.0)).sum()
+  }
+
+  /// 上下外边距、边框及内边距的高度之和
+  fn get_vertical_edge_height(&self) -> f32 {
+    let (margin_top, margin_bottom, border_top, border_bottom, padding_top, padding_bottom) = self.get_box_vertical_info();
+    margin_top + margin_bottom + border_top + border_bottom + padding_top + padding_bottom
+  }
+
+  /// 计算flex item（以及grid item）的布局，`containing_block`的宽度即为子项分配到的`margin-box`宽度
+  ///
+  /// 子项会建立新的块级格式化上下文，外边距也不与容器折叠
+  fn calc_flex_item_layout(&mut self, containing_block: Box, text_layout: &mut TextLayout) {
+    let is_anonymous = matches!(self.box_type, BoxType::AnonymousBlock(_));
+    self.floats = vec![];
+    self.calc_block_width(containing_block, is_anonymous, text_layout);
+    // 主轴尺寸由flex算法决定，`auto`外边距按0处理，内容宽度取剩余部分
+    let (margin_left, margin_right) = if is_anonymous {
+      (0.0, 0.0)
+    } else {
+      let style_node = self.get_style_node();
+      let zero = CSSValue::Length(0.0, CSSUnit::Px);
+      (
+        style_node.look_up("margin-left", "margin", &zero).to_px_with(style_node.get_font_size(), 0.0),
+        style_node.look_up("margin-right", "margin", &zero).to_px_with(style_node.get_font_size(), 0.0)
+      )
+    };
+    let box_model = &mut self.box_model;
+    box_model.margin.left = margin_left;
+    box_model.margin.right = margin_right;
+    let edge_width = margin_left + margin_right + box_model.border.left + box_model.border.right + box_model.padding.left + box_model.padding.right;
+    box_model.content.width = (containing_block.content.width - edge_width).max(0.0);
+    self.calc_block_position(containing_block);
+    self.calc_block_children(text_layout);
+    self.calc_block_height();
+    self.calc_sticky_children();
   }
 
-  fn calc_inline_children(&mut self, containing_block: Box) {
+  fn calc_inline_children(&mut self, containing_block: Box, text_layout: &mut TextLayout) {
     let box_model = &mut self.box_model;
     for child in &mut self.children {
-      child.calc_layout(containing_block)
+      child.calc_layout(containing_block, text_layout)
     }
   }
 
-  fn calc_inline_width(&mut self, containing_block: Box) {
+  fn calc_inline_width(&mut self, containing_block: Box, text_layout: &mut TextLayout) {
     // TODO: 在哪里给line box重新分配现有的inline box？
-    self.calc_inline_children(containing_block);
+    self.calc_inline_children(containing_block, text_layout);
   }
 
   fn calc_inline_layout(&mut self, containing_block: Box) {
@@ -455,78 +1603,529 @@ impl<'a> LayoutBox<'a> {
   }
 
   /// 计算单行文本的宽高信息
-  fn calc_text_layout(&self, text: &String) -> (f32, f32) {
-    let text_layout = get_text_layout();
+  fn calc_text_layout(&self, text: &str, text_layout: &mut TextLayout) -> (f32, f32) {
     // text_layout.layout.clear();
     text_layout.layout.reset(&LayoutSettings {
-      max_width: Some(10000.0), // 暂时不考虑换行
+      max_width: Some(10000.0), // 换行由line box负责（见`split_text`），这里只排单行
       ..Default::default()
     });
-    text_layout.layout.append(&text_layout.fonts, &TextStyle::new(text.as_str(), 16.0, 0));
-    // TODO: 除了超出宽度的自动换行，还有换行符可以直接触发换行，因此当文字中有换行符就不可控了
-    let last_text = text_layout.layout.glyphs().last().unwrap();
-    // 文字的起始位置取决于最近的一个line box；
-    (last_text.x + (last_text.width as f32), text_layout.layout.height())
+    text_layout.layout.append(&text_layout.fonts, &TextStyle::new(text, self.get_font_size(), 0));
+    // 文本中的换行符在此之前已经处理：合并为空格，或者（`pre`）拆分到不同的line box
+    // 文字的起始位置取决于最近的一个line box；空文本没有字符，宽度为0
+    // 空白字符没有位图，结尾的空白（如与后面的行内元素之间的空格）需要取其步进宽度
+    let width = text_layout.layout.glyphs().last().map(|last_text| {
+      let width = if last_text.parent.is_whitespace() {
+        text_layout.fonts[last_text.font_index].metrics(last_text.parent, last_text.key.px).advance_width
+      } else {
+        last_text.width as f32
+      };
+      last_text.x + width
+    }).unwrap_or(0.0);
+    (width, text_layout.layout.height())
+  }
+
+  /// 获取行内级盒子的`vertical-align`
+  ///
+  /// 文本的样式节点没有自身样式，取其所在inline元素的`vertical-align`
+  fn get_vertical_align(&self) -> VerticalAlign {
+    match &self.box_type {
+      BoxType::InlineBlock(style_node) => style_node.get_vertical_align(),
+      BoxType::AnonymousInline(_, style_node) => style_node.parent
+        .as_ref()
+        .and_then(|parent| parent.upgrade())
+        .filter(|parent| matches!(parent.get_display(), Display::Inline))
+        .map(|parent| parent.get_vertical_align())
+        .unwrap_or(VerticalAlign::Baseline),
+      _ => VerticalAlign::Baseline
+    }
   }
 
   /// 计算`line box`的布局信息
-  fn calc_line_box_layout(&mut self, containing_block: Box) {
-    let max_h = self.children.iter().map(|child| child.box_model.content.height).max_by(|a, b| a.total_cmp(b)).unwrap();
-    self.box_model.content.x = containing_block.content.x;
+  ///
+  /// 先按`vertical-align`对齐基线确定各子级的竖直位置，`top`/`bottom`/`middle`则相对整个line box对齐
+  fn calc_line_box_layout(&mut self, containing_block: Box, text_layout: &mut TextLayout) {
+    // `text-top`、`sub`等相对于父级（即line box所在的元素）的字体计算
+    let font_size = self.get_font_size();
+    let (ascent, descent) = text_layout.fonts[0]
+      .horizontal_line_metrics(font_size)
+      .map(|metrics| (metrics.ascent, metrics.descent))
+      .unwrap_or((font_size, 0.0));
+    // `middle`将子级的中点对齐到父级基线之上半个x-height处
+    let x_height = match text_layout.fonts[0].metrics('x', font_size).bounds.height {
+      height if height > 0.0 => height,
+      _ => font_size * 0.5
+    };
+    // 每个子级的对齐方式、高度，以及其顶部到父级基线的距离（仅对相对基线对齐的子级有意义）
+    let metrics: Vec<(VerticalAlign, f32, f32)> = self.children.iter().map(|child| {
+      let height = child.box_model.margin_box().height;
+      // 文本的基线在排版时已经算出（半行距加上字体ascent），inline-block的基线取其`margin-box`底边
+      let baseline = match child.box_type {
+        BoxType::AnonymousInline(..) => child.baseline,
+        _ => height
+      };
+      let vertical_align = child.get_vertical_align();
+      let above = match vertical_align {
+        VerticalAlign::Sub => baseline - font_size * 0.2,
+        VerticalAlign::Super => baseline + font_size * 0.33,
+        VerticalAlign::TextTop => ascent,
+        VerticalAlign::TextBottom => height + descent,
+        VerticalAlign::Middle => (height + x_height) / 2.0,
+        _ => baseline
+      };
+      (vertical_align, height, above)
+    }).collect();
+    let is_line_relative = |vertical_align: VerticalAlign| matches!(vertical_align, VerticalAlign::Top | VerticalAlign::Bottom);
+    // 基线在line box中的位置，以及基线对齐部分的总高度
+    let baseline = metrics
+      .iter()
+      .filter(|(vertical_align, ..)| !is_line_relative(*vertical_align))
+      .map(|(_, _, above)| *above)
+      .fold(0.0, f32::max);
+    let baseline_height = metrics
+      .iter()
+      .filter(|(vertical_align, ..)| !is_line_relative(*vertical_align))
+      .map(|(_, height, above)| baseline - above + height)
+      .fold(0.0, f32::max);
+    let max_h = metrics.iter().map(|(_, height, _)| *height).fold(baseline_height, f32::max);
+    self.box_model.content.x += containing_block.content.x; // 水平位置需要加上避开浮动盒子的偏移
     self.box_model.content.y = containing_block.content.y + containing_block.content.height; // 竖直位置取决于当前包含块高度
     self.box_model.content.height = max_h; // 高度取决于当前包含的最高的inline box
-    println!("line box: {:#?}", self.box_model.content);
+    log::debug!("line box: {:#?}", self.box_model.content);
     // 同时修正line box下所有子级的位置
-    for child in self.children.iter_mut() {
-      child.box_model.content.x += self.box_model.content.x;
-      child.box_model.content.y += self.box_model.content.y;
+    for (child, (vertical_align, height, above)) in self.children.iter_mut().zip(metrics) {
+      let dy = match vertical_align {
+        VerticalAlign::Top => 0.0,
+        VerticalAlign::Bottom => max_h - height,
+        _ => baseline - above
+      };
+      child.translate(self.box_model.content.x, self.box_model.content.y + dy);
     }
   }
 
   /// 计算渲染需要的布局，会对初始的`box tree`进行结构调整
-  fn calc_layout(&mut self, containing_block: Box) {
+  fn calc_layout(&mut self, containing_block: Box, text_layout: &mut TextLayout) {
     // 这里的包含块有可能是匿名块级box，实际上计算百分比属性时不应该用匿名块级box作为包含块
 
     // 经过line box的重新组织后，这里应该不再会出现inline/匿名inline的情况了
     match self.box_type {
-      BoxType::Block(_) => self.calc_block_layout(containing_block, false),
+      BoxType::Block(_) => self.calc_block_layout(containing_block, false, text_layout),
+      BoxType::Flex(_) => self.calc_flex_layout(containing_block, text_layout),
+      BoxType::Grid(_) => self.calc_grid_layout(containing_block, text_layout),
       // TODO: line box怎么确定？line box只由IFC产生，那么应该都是在inline box内部？
       // 根据测试(https://codepen.io/xxf1996/pen/oNyLWLd)，同一个line box可能包含多个不同inline box的内容；因此line box确实只能存在block box内？
       BoxType::AnonymousBlock(_) => {
         // 匿名容器布局计算
-        println!("AnonymousBlock");
-        self.calc_block_layout(containing_block, true) // TODO: 匿名block不应该再计算padding/border/margin及一些样式，不然就重复了
+        log::debug!("AnonymousBlock");
+        self.calc_block_layout(containing_block, true, text_layout) // TODO: 匿名block不应该再计算padding/border/margin及一些样式，不然就重复了
       },
-      BoxType::Line => {
-        self.calc_line_box_layout(containing_block)
+      BoxType::Line(_) => {
+        self.calc_line_box_layout(containing_block, text_layout)
       },
       _ => {}
     }
   }
 }
 
+/// 按`max_width`拆分文本，返回`(放得下的部分, 剩余部分)`
+///
+/// 优先在单词间换行；`is_empty_line`为真时即使第一个单词也放不下，也会按字符拆分出至少一个字符，
+/// 否则返回`None`，由调用方将整段文本移到下一行
+fn split_text(text: &str, font_size: f32, max_width: f32, is_empty_line: bool, text_layout: &mut TextLayout) -> Option<(String, String)> {
+  // fontdue会把开头的空白单独排成一行，因此先去掉开头的空白，可用宽度相应减去空白的宽度
+  let content = text.trim_start();
+  let lead = text.len() - content.len();
+  let lead_width = if lead > 0 {
+    text_layout.layout.reset(&LayoutSettings::default());
+    text_layout.layout.append(&text_layout.fonts, &TextStyle::new(text, font_size, 0));
+    text_layout.layout.glyphs().iter().find(|glyph| glyph.byte_offset >= lead).map(|glyph| glyph.x).unwrap_or(0.0)
+  } else {
+    0.0
+  };
+  let max_width = max_width - lead_width;
+  let mut split_at = |wrap_style: WrapStyle| {
+    text_layout.layout.reset(&LayoutSettings {
+      max_width: Some(max_width.max(0.0)),
+      wrap_style,
+      ..Default::default()
+    });
+    text_layout.layout.append(&text_layout.fonts, &TextStyle::new(content, font_size, 0));
+    let glyphs = text_layout.layout.glyphs();
+    let lines = text_layout.layout.lines()?;
+    let first_line = lines.first()?;
+    let last_glyph = glyphs.get(first_line.glyph_end)?;
+    let fits = fits_within(last_glyph.x + last_glyph.width as f32, max_width);
+    let next_glyph = glyphs.get(lines.get(1)?.glyph_start)?;
+    Some((fits, next_glyph.byte_offset))
+  };
+  // 单词比`max_width`还长时fontdue也会在单词内部换行，这里只接受空白处或非ASCII字符（如中文）之间的拆分
+  let is_word_boundary = |idx: usize| {
+    let prev = content[..idx].chars().next_back();
+    let next = content[idx..].chars().next();
+    [prev, next].iter().flatten().any(|c| c.is_whitespace() || !c.is_ascii())
+  };
+  let split_idx = match split_at(WrapStyle::Word) {
+    Some((true, idx)) if is_word_boundary(idx) => idx,
+    _ if is_empty_line => split_at(WrapStyle::Letter)?.1,
+    _ => return None
+  };
+  // 拆分处的空白留给剩余部分，如果剩余部分仍排在当前行，空白就是两部分之间的间隔
+  let head = content[..split_idx].trim_end();
+  if head.is_empty() {
+    return None;
+  }
+  let head_end = lead + head.len();
+  Some((text[..head_end].to_string(), text[head_end..].to_string()))
+}
+
+/// 按照`white-space`处理文本节点的内容：除了`pre`，连续的空白（包括换行符）都合并为一个空格
+fn collapse_whitespace(text: &str, white_space: WhiteSpace) -> Cow<'_, str> {
+  let is_collapsed = !text.contains(|c: char| c.is_ascii_whitespace() && c != ' ') && !text.contains("  ");
+  if white_space == WhiteSpace::Pre || is_collapsed {
+    return Cow::Borrowed(text);
+  }
+  let mut content = String::with_capacity(text.len());
+  for c in text.chars() {
+    if !c.is_ascii_whitespace() {
+      content.push(c);
+    } else if !content.ends_with(' ') {
+      content.push(' ');
+    }
+  }
+  Cow::Owned(content)
+}
+
+/// 按照`flex-grow`把剩余空间分配给子项，返回子项在主轴上的`margin-box`尺寸；没有剩余空间时保持基础尺寸
+fn grow_flex_items(items: &[LayoutBox], base_sizes: &[f32], main_size: f32) -> Vec<f32> {
+  let grows: Vec<f32> = items
+    .iter()
+    .map(|child| match &child.box_type {
+      BoxType::Block(style_node) => style_node.get_flex_grow(),
+      _ => 0.0
+    })
+    .collect();
+  let total_grow: f32 = grows.iter().sum();
+  let free_space = main_size - base_sizes.iter().sum::<f32>();
+  base_sizes
+    .iter()
+    .zip(&grows)
+    .map(|(size, grow)| if free_space > 0.0 && total_grow > 0.0 {
+      size + free_space * grow / total_grow
+    } else {
+      *size
+    })
+    .collect()
+}
+
+/// grid item在单个方向上的位置：起始轨道的下标（需要自动放置时为`None`）及跨越的轨道数
+type GridSpan = (Option<usize>, usize);
+
+/// 将起止网格线解析为grid item在该方向上的位置
+///
+/// `track_count`为显式轨道的数量，用于解析负数编号的网格线；起止网格线相同时跨越一个轨道
+fn resolve_grid_lines(start: GridLine, end: GridLine, track_count: usize) -> GridSpan {
+  // 网格线`n`是第`n`个轨道的起点，`-1`为最后一条网格线
+  let index = |line: i32| if line > 0 {
+    line as usize - 1
+  } else {
+    (track_count as i32 + 1 + line).max(0) as usize
+  };
+  match (start, end) {
+    (GridLine::Line(start), GridLine::Line(end)) => {
+      let (start, end) = (index(start), index(end));
+      (Some(start.min(end)), start.abs_diff(end).max(1))
+    },
+    (GridLine::Line(start), GridLine::Span(span)) => (Some(index(start)), span),
+    (GridLine::Line(start), GridLine::Auto) => (Some(index(start)), 1),
+    (GridLine::Span(span), GridLine::Line(end)) => {
+      let end = index(end);
+      let start = end.saturating_sub(span);
+      (Some(start), (end - start).max(1))
+    },
+    (GridLine::Auto, GridLine::Line(end)) => (Some(index(end).saturating_sub(1)), 1),
+    (GridLine::Span(span), _) | (GridLine::Auto, GridLine::Span(span)) => (None, span),
+    (GridLine::Auto, GridLine::Auto) => (None, 1)
+  }
+}
+
+/// 网格区域内的单元格是否都没有被占据，`occupied`之外的行都是空行
+fn is_grid_area_free(occupied: &[Vec<bool>], area: &GridArea) -> bool {
+  (area.row_start..area.row_end).all(|row| occupied
+    .get(row)
+    .is_none_or(|cells| cells[area.column_start..area.column_end].iter().all(|cell| !cell))
+  )
+}
+
+/// 由各个轨道的尺寸得到每条网格线的偏移，第一条为0，最后一条为所有轨道的尺寸之和
+fn get_track_offsets(sizes: &[f32]) -> Vec<f32> {
+  let mut offsets = Vec::with_capacity(sizes.len() + 1);
+  let mut offset = 0.0;
+  offsets.push(offset);
+  for size in sizes {
+    offset += size;
+    offsets.push(offset);
+  }
+  offsets
+}
+
+/// 计算各列轨道的宽度，`content_widths`为各列的内在宽度（见`LayoutBox::get_grid_column_widths`）
+///
+/// 固定尺寸的轨道取其尺寸，`auto`轨道先取内在宽度；剩余空间按比例分配给`fr`轨道（比例之和小于1时只分配相应的部分），
+/// 没有`fr`轨道时平分给`auto`轨道；没有剩余空间时`fr`轨道宽度为0
+fn size_grid_tracks(tracks: &[TrackSize], content_widths: &[f32], container_width: f32) -> Vec<f32> {
+  let mut widths: Vec<f32> = tracks
+    .iter()
+    .zip(content_widths)
+    .map(|(track, width)| match track {
+      TrackSize::Fraction(_) => 0.0,
+      _ => *width
+    })
+    .collect();
+  let rest_space = (container_width - widths.iter().sum::<f32>()).max(0.0);
+  let total_fraction: f32 = tracks
+    .iter()
+    .map(|track| match track {
+      TrackSize::Fraction(fraction) => *fraction,
+      _ => 0.0
+    })
+    .sum();
+  let auto_count = tracks.iter().filter(|track| **track == TrackSize::Auto).count();
+  for (width, track) in widths.iter_mut().zip(tracks) {
+    match track {
+      TrackSize::Fraction(fraction) => *width = rest_space * fraction / total_fraction.max(1.0),
+      TrackSize::Auto if total_fraction == 0.0 => *width += rest_space / auto_count as f32,
+      _ => {}
+    }
+  }
+  widths
+}
+
+/// 按照`justify-content`（`align-content`同样适用）计算`count`个子项（或行）中第一个的起点及相互之间的间距
+///
+/// 剩余空间为负（溢出）时，`space-between`等同于`flex-start`，`space-around`/`space-evenly`等同于`center`
+fn get_distribution_offsets(justify_content: JustifyContent, count: usize, rest_space: f32) -> (f32, f32) {
+  let count = count as f32;
+  match justify_content {
+    JustifyContent::FlexEnd => (rest_space, 0.0),
+    JustifyContent::SpaceBetween if count > 1.0 && rest_space > 0.0 => (0.0, rest_space / (count - 1.0)),
+    JustifyContent::SpaceAround if count > 0.0 && rest_space > 0.0 => (rest_space / count / 2.0, rest_space / count),
+    JustifyContent::SpaceEvenly if count > 0.0 && rest_space > 0.0 => (rest_space / (count + 1.0), rest_space / (count + 1.0)),
+    JustifyContent::Center | JustifyContent::SpaceAround | JustifyContent::SpaceEvenly => (rest_space / 2.0, 0.0),
+    _ => (0.0, 0.0)
+  }
+}
+
+/// 按照第一行文本的基线对齐同一行内的子项，没有文本的子项以`border-box`底边作为基线；返回对齐后行的高度
+///
+/// `top`为行的顶部，子项此时都位于行的顶部
+fn align_flex_baselines(items: &mut [LayoutBox], top: f32) -> f32 {
+  let ascents: Vec<f32> = items
+    .iter()
+    .map(|child| {
+      let border_box = child.box_model.border_box();
+      child.get_first_baseline().unwrap_or(border_box.y + border_box.height) - top
+    })
+    .collect();
+  let max_ascent = ascents.iter().fold(0.0, |max: f32, ascent| max.max(*ascent));
+  let mut height: f32 = 0.0;
+  for (child, ascent) in items.iter_mut().zip(ascents) {
+    child.translate(0.0, max_ascent - ascent);
+    let margin_box = child.box_model.margin_box();
+    height = height.max(margin_box.y + margin_box.height - top);
+  }
+  height
+}
+
+/// 按照`align-items`在竖直方向上对齐同一行内的子项，子项此时都位于行的顶部；`stretch`时`auto`高度的子项填满行高`cross_size`
+fn align_flex_line_items(items: &mut [LayoutBox], align_items: AlignItems, cross_size: f32) {
+  for child in items {
+    let rest_space = cross_size - child.box_model.margin_box().height;
+    match align_items {
+      AlignItems::FlexEnd => child.translate(0.0, rest_space),
+      AlignItems::Center => child.translate(0.0, rest_space / 2.0),
+      AlignItems::Stretch if child.get_explicit_height().is_none() => {
+        child.box_model.content.height = (cross_size - child.get_vertical_edge_height()).max(0.0);
+      },
+      _ => {}
+    }
+  }
+}
+
+/// 将数值序列化为JSON，JSON不支持的`NaN`及无穷大输出为`null`
+fn json_number(num: f32) -> String {
+  if num.is_finite() {
+    num.to_string()
+  } else {
+    String::from("null")
+  }
+}
+
+/// 将字符串序列化为JSON字符串（包括两侧的引号）
+fn json_string(text: &str) -> String {
+  let mut res = String::with_capacity(text.len() + 2);
+  res.push('"');
+  for c in text.chars() {
+    match c {
+      '"' => res.push_str("\\\""),
+      '\\' => res.push_str("\\\\"),
+      '\n' => res.push_str("\\n"),
+      '\r' => res.push_str("\\r"),
+      '\t' => res.push_str("\\t"),
+      c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+      c => res.push(c)
+    }
+  }
+  res.push('"');
+  res
+}
+
+/// 判断宽度`width`能否放进`max_width`，容许`EPSILON`的浮点误差，避免恰好放得下的内容因为误差而换行
+fn fits_within(width: f32, max_width: f32) -> bool {
+  width <= max_width + EPSILON
+}
+
+/// 计算两个相邻外边距折叠后的值
+///
+/// 同为正数时取最大值，同为负数时取最小值（即绝对值最大的），一正一负时取两者之和
+fn collapse_margins(a: f32, b: f32) -> f32 {
+  if a >= 0.0 && b >= 0.0 {
+    a.max(b)
+  } else if a <= 0.0 && b <= 0.0 {
+    a.min(b)
+  } else {
+    a.max(b) + a.min(b)
+  }
+}
+
+/// 根据包含块宽度分配块级元素的`width`及左右外边距（主要是处理`auto`值），返回`(width, margin_left, margin_right)`
+///
+/// `edge_width`为左右边框及内边距的像素宽度之和
+fn distribute_block_width(
+  containing_width: f32,
+  is_float: bool,
+  edge_width: f32,
+  mut width: CSSValue,
+  mut margin_left: CSSValue,
+  mut margin_right: CSSValue
+) -> (CSSValue, CSSValue, CSSValue) {
+  let auto = CSSValue::Keyword(String::from("auto"));
+  let zero = CSSValue::Length(0.0, CSSUnit::Px);
+  let total_width = edge_width + [
+    &margin_left,
+    &width,
+    &margin_right
+  ].iter().map(|val| val.to_px()).sum::<f32>(); // 总宽度（实际上就是`margin-box`宽度）
+
+  // 当前元素总宽度超过其包含块宽度时；浮动元素的auto外边距也直接视为0
+  if is_float || (width != auto && !fits_within(total_width, containing_width)) {
+    // 首先压缩外边距宽度
+    if margin_left == auto {
+      margin_left = zero.clone();
+    }
+    if margin_right == auto {
+      margin_right = zero.clone();
+    }
+  }
+
+  //TODO: 包含块剩余宽度（关键是上面改变外边距的行为不会导致总宽度变化吗？）
+  let rest_wdith = containing_width - total_width;
+
+  log::debug!("width: {}, rest: {}", total_width, rest_wdith);
+
+  match (width == auto, margin_left == auto, margin_right == auto) {
+    (false, false, false) => {
+      // 这里填充右侧外边距的目的是当溢出的时候，通过负边距来修正，而宽度剩余时只是简单地填满剩余宽度
+      // 浮动元素的宽度不需要填满包含块
+      if !is_float {
+        margin_right = CSSValue::Length(margin_right.to_px() + rest_wdith, CSSUnit::Px);
+      }
+    },
+    (false, true, false) => {
+      margin_left = CSSValue::Length(rest_wdith, CSSUnit::Px);
+    },
+    (false, false, true) => {
+      margin_right = CSSValue::Length(rest_wdith, CSSUnit::Px);
+    },
+    (false, true, true) => {
+      margin_left = CSSValue::Length(rest_wdith / 2.0, CSSUnit::Px);
+      margin_right = CSSValue::Length(rest_wdith / 2.0, CSSUnit::Px);
+    },
+    (true, _, _) => {
+      // width的auto优先级最高
+      if margin_left == auto {
+        margin_left = zero.clone();
+      }
+      if margin_right == auto {
+        margin_right = zero.clone();
+      }
+      if rest_wdith < 0.0 {
+        width = zero.clone();
+        // 通过边距来修正
+        margin_right = CSSValue::Length(margin_right.to_px() + rest_wdith, CSSUnit::Px);
+      } else {
+        width = CSSValue::Length(rest_wdith, CSSUnit::Px);
+        log::debug!("此时的width: {}", width.to_px());
+      }
+    }
+  }
+
+  (width, margin_left, margin_right)
+}
+
+/// 计算在竖直区间`[y, y + height)`内，`area`被浮动盒子占据后剩余的水平区间
+fn get_float_free_range(floats: &[FloatBox], area: RectArea, y: f32, height: f32) -> (f32, f32) {
+  let mut left = area.x;
+  let mut right = area.x + area.width;
+  for float_box in floats {
+    let top = float_box.area.y;
+    let bottom = float_box.area.y + float_box.area.height;
+    if bottom <= y || (top >= y + height && top > y) {
+      continue; // 竖直方向上不相交
+    }
+    match float_box.float {
+      Float::Left => left = left.max(float_box.area.x + float_box.area.width),
+      Float::Right => right = right.min(float_box.area.x),
+      Float::None => {}
+    }
+  }
+  (left, right)
+}
+
 /// 生成布局树结构（实际上是构建初始的`box tree`）
-fn get_layout_tree_struct<'a>(style_tree: Arc<StyledNode<'a>>) -> LayoutBox<'a> {
+///
+/// 固定定位的元素与其在文档中的位置无关，不放入父级盒子，而是按文档顺序收集到`fixed_boxes`中
+fn get_layout_tree_struct<'a>(style_tree: Arc<StyledNode<'a>>, fixed_boxes: &mut Vec<LayoutBox<'a>>) -> LayoutBox<'a> {
   let mut root = LayoutBox::new(
     match style_tree.get_display() {
       Display::Block => BoxType::Block(style_tree.clone()),
       Display::Inline => {
         if let NodeType::Text(content) = &style_tree.node.node_type {
-          BoxType::AnonymousInline(&content, style_tree.clone())
+          BoxType::AnonymousInline(collapse_whitespace(content, style_tree.get_white_space()), style_tree.clone())
         } else {
           BoxType::Inline(style_tree.clone())
         }
       },
-      Display::None => panic!("根节点不能设置`display: none`")
+      Display::InlineBlock => BoxType::InlineBlock(style_tree.clone()),
+      Display::Flex => BoxType::Flex(style_tree.clone()),
+      Display::Grid => BoxType::Grid(style_tree.clone()),
+      // 根节点`display: none`时（如只有`<head>`的文档）生成空的匿名块级盒子，不包含任何内容
+      Display::None => return LayoutBox::new(BoxType::AnonymousBlock(style_tree.clone()))
     }
   );
 
   let children = style_tree.children.lock().unwrap();
 
   for child in children.iter() {
-    match child.get_display() {
-      Display::Block => root.children.push(get_layout_tree_struct(child.clone())),
-      Display::Inline => root.get_inline_container().children.push(get_layout_tree_struct(child.clone())),
+    let display = child.get_display();
+    if child.get_position() == Position::Fixed && !matches!(display, Display::None) {
+      // 先占位，保证外层的固定定位盒子排在其内部的固定定位盒子之前
+      let index = fixed_boxes.len();
+      let fixed_box = get_layout_tree_struct(child.clone(), fixed_boxes);
+      fixed_boxes.insert(index, fixed_box);
+      continue;
+    }
+    match display {
+      Display::Block | Display::Flex | Display::Grid => root.children.push(get_layout_tree_struct(child.clone(), fixed_boxes)),
+      Display::Inline | Display::InlineBlock => root.get_inline_container().children.push(get_layout_tree_struct(child.clone(), fixed_boxes)),
       Display::None => {} // 跳过display为none的节点
     }
   }
@@ -536,29 +2135,353 @@ fn get_layout_tree_struct<'a>(style_tree: Arc<StyledNode<'a>>) -> LayoutBox<'a>
   root
 }
 
-pub fn get_text_layout<'a>() -> &'a mut TextLayout {
-  unsafe {
-    if TEXT_LAYOUTS.len() == 0 {
-      panic!("文字布局还未加载成功！")
+impl LayoutTree {
+  /// 从样式树生成布局树
+  ///
+  /// 文字布局模块由流水线持有，整个布局过程中会一直持有其互斥锁，保证文字排版的`reset`/`append`/`glyphs`是串行的
+  pub fn get_layout_tree<'a>(&'a self, init_box: Box, text_layout: &Mutex<TextLayout>) -> LayoutBox<'a> {
+    let viewport = Viewport {
+      width: init_box.content.width,
+      height: init_box.content.height
+    };
+    let style_tree = self.style_tree.get_style_tree(viewport);
+    layout_root(style_tree, init_box, text_layout)
+  }
+
+  /// 只布局第一个命中`selector`的元素及其子孙（如组件测试），以`containing_block`作为包含块及视窗
+  ///
+  /// 元素的样式仍然基于整个文档计算（继承、结构伪类等不受影响）；行内级的元素按块级盒子布局，没有命中或者`display: none`时返回`None`
+  pub fn get_subtree_layout<'a>(&'a self, selector: &str, containing_block: Box, text_layout: &Mutex<TextLayout>) -> Option<SubtreeLayout<'a>> {
+    let viewport = Viewport {
+      width: containing_block.content.width,
+      height: containing_block.content.height
+    };
+    let style_root = self.style_tree.get_style_tree(viewport);
+    let styled_node = self.style_tree.query_styled_node(&style_root, selector)?;
+    if matches!(styled_node.get_display(), Display::None) {
+      return None;
     }
-    TEXT_LAYOUTS.get_mut(0).unwrap()
+    let layout_box = layout_root(styled_node, containing_block, text_layout);
+    Some(SubtreeLayout {
+      _style_root: style_root,
+      layout_box
+    })
   }
 }
 
-impl LayoutTree {
-  /// 从样式树生成布局树
-  pub fn get_layout_tree<'a>(&'a self, mut init_box: Box) -> LayoutBox<'a> {
-    let style_tree = self.style_tree.get_style_tree();
-    unsafe {
-      // 初始化文字布局模块
-      if TEXT_LAYOUTS.len() == 0 {
-        TEXT_LAYOUTS.push(TextLayout::default())
+/// 以`init_box`为初始包含块（视窗）布局`style_tree`及其子孙
+///
+/// 固定定位的盒子在文档流布局之后以视窗为包含块布局，并追加到根节点子级的末尾
+fn layout_root<'a>(style_tree: Arc<StyledNode<'a>>, mut init_box: Box, text_layout: &Mutex<TextLayout>) -> LayoutBox<'a> {
+  let mut fixed_boxes = Vec::new();
+  let mut root_box = get_layout_tree_struct(style_tree, &mut fixed_boxes);
+  // 行内级的根节点按块级盒子布局
+  if let BoxType::Inline(style_node) | BoxType::InlineBlock(style_node) = &root_box.box_type {
+    root_box.box_type = BoxType::Block(style_node.clone());
+  }
+  // 视窗高度作为初始包含块的高度；为0时（无窗口渲染整个文档）视为不确定
+  root_box.containing_height = Some(init_box.content.height).filter(|height| *height > 0.0);
+  let mut viewport_box = init_box;
+  // 包含块的高度同时用于累加子级的竖直偏移，根节点从0开始
+  init_box.content.height = 0.0;
+  let mut text_layout = text_layout.lock().unwrap();
+  root_box.calc_layout(init_box, &mut text_layout);
+  // 视窗高度不确定时，固定定位以整个文档的高度作为视窗高度
+  if viewport_box.content.height <= 0.0 {
+    viewport_box.content.height = root_box.box_model.margin_box().height;
+  }
+  for mut fixed_box in fixed_boxes {
+    fixed_box.calc_fixed_layout(viewport_box, &mut text_layout);
+    root_box.children.push(fixed_box);
+  }
+  root_box
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::css::MAX_GRID_LINE;
+  use crate::html;
+  use crate::style::StyleTree;
+
+  fn get_layout_tree(source: &str) -> LayoutTree {
+    LayoutTree {
+      style_tree: StyleTree { document: html::parse(source.to_string()).unwrap(), history: Default::default(), focused: None }
+    }
+  }
+
+  /// 在`width`x`height`的视窗中布局`source`，将根盒子交给`f`检查
+  fn with_layout(source: &str, width: f32, height: f32, f: impl FnOnce(&LayoutBox)) {
+    let layout_tree = get_layout_tree(source);
+    let mut viewport = Box::default();
+    viewport.content.width = width;
+    viewport.content.height = height;
+    let root = layout_tree.get_layout_tree(viewport, &Mutex::new(TextLayout::default()));
+    f(&root);
+  }
+
+  /// 深度优先查找`id`属性为`id`的元素生成的盒子
+  fn find_box<'b, 'a>(layout_box: &'b LayoutBox<'a>, id: &str) -> Option<&'b LayoutBox<'a>> {
+    if let BoxType::Block(style_node) | BoxType::Inline(style_node) | BoxType::InlineBlock(style_node) | BoxType::Flex(style_node) | BoxType::Grid(style_node) = &layout_box.box_type {
+      if let NodeType::Element(element) = &style_node.node.node_type {
+        if element.get_attribute("id") == Some(id) {
+          return Some(layout_box);
+        }
       }
     }
-    init_box.content.height = 0.0;
-    let mut root_box = get_layout_tree_struct(style_tree);
-    root_box.calc_layout(init_box);
-    root_box
+    layout_box.children.iter().find_map(|child| find_box(child, id))
+  }
+
+  /// 按文档顺序收集所有`line box`
+  fn get_line_boxes<'b, 'a>(layout_box: &'b LayoutBox<'a>) -> Vec<&'b LayoutBox<'a>> {
+    if let BoxType::Line(_) = layout_box.box_type {
+      return vec![layout_box];
+    }
+    layout_box.children.iter().flat_map(get_line_boxes).collect()
   }
-}
 
+  #[test]
+  fn adjacent_sibling_margins_collapse() {
+    let source = r#"<div><div id="a" style="height: 10px; margin: 20px 0"></div><div id="b" style="height: 10px; margin: 20px 0"></div></div>"#;
+    with_layout(source, 800.0, 600.0, |root| {
+      let a = find_box(root, "a").unwrap().box_model.border_box();
+      let b = find_box(root, "b").unwrap().box_model.border_box();
+      assert_eq!(b.y - (a.y + a.height), 20.0);
+    });
+  }
+
+  #[test]
+  fn em_lengths_use_own_font_size() {
+    let source = r#"<div style="font-size: 20px; width: 10em; padding-left: 1em; margin-top: 2em; border-top-width: 0.5rem"></div>"#;
+    with_layout(source, 800.0, 600.0, |root| {
+      assert_eq!(root.box_model.content.width, 200.0);
+      assert_eq!(root.box_model.padding.left, 20.0);
+      assert_eq!(root.box_model.margin.top, 40.0);
+      assert_eq!(root.box_model.border.top, 8.0);
+    });
+  }
+
+  #[test]
+  fn line_height_sets_line_box_height() {
+    with_layout(r#"<div style="font-size: 16px; line-height: 2">hello</div>"#, 800.0, 600.0, |root| {
+      let lines = get_line_boxes(root);
+      assert_eq!(lines.len(), 1);
+      assert_eq!(lines[0].box_model.content.height, 32.0);
+    });
+  }
+
+  #[test]
+  fn text_align_center_splits_rest_width() {
+    with_layout(r#"<div style="text-align: center">hello</div>"#, 800.0, 600.0, |root| {
+      let line = get_line_boxes(root)[0];
+      let text = &line.children[0];
+      let rest_width = line.box_model.content.width - text.box_model.margin_box().width;
+      assert!(rest_width > 0.0);
+      assert!((text.box_model.margin_box().x - line.box_model.content.x - rest_width / 2.0).abs() < EPSILON);
+    });
+  }
+
+  #[test]
+  fn style_text_is_not_rendered() {
+    with_layout("<div><style>p { color: red; }</style><p>hello</p></div>", 800.0, 600.0, |root| {
+      assert_eq!(root.get_text_content(), "hello");
+    });
+  }
+
+  #[test]
+  fn hidden_root_produces_empty_box() {
+    for source in ["<!-- comment -->", "<style>p { color: red; }</style>", "<script>1 + 1</script>", "<head><title>t</title></head>"] {
+      with_layout(source, 800.0, 600.0, |root| {
+        assert_eq!(root.get_text_content(), "");
+        assert_eq!(root.box_model.content.height, 0.0);
+      });
+    }
+  }
+
+  #[test]
+  fn min_max_width_clamp_percent_width() {
+    let source = r#"<div><div id="wide" style="width: 50%; max-width: 300px"></div><div id="narrow" style="width: 10%; min-width: 200px"></div></div>"#;
+    with_layout(source, 1280.0, 600.0, |root| {
+      assert_eq!(find_box(root, "wide").unwrap().box_model.content.width, 300.0);
+      assert_eq!(find_box(root, "narrow").unwrap().box_model.content.width, 200.0);
+    });
+  }
+
+  #[test]
+  fn auto_height_contains_line_boxes() {
+    let source = r#"<div><div id="text" style="width: 60px; line-height: 24px">hello world</div></div>"#;
+    with_layout(source, 800.0, 600.0, |root| {
+      let text = find_box(root, "text").unwrap();
+      assert_eq!(get_line_boxes(text).len(), 2);
+      assert_eq!(text.box_model.content.height, 48.0);
+    });
+  }
+
+  #[test]
+  fn max_content_width_fits_phrase() {
+    with_layout(r#"<div><div id="box" style="width: max-content">hello world</div></div>"#, 800.0, 600.0, |root| {
+      let content = find_box(root, "box").unwrap();
+      let line = get_line_boxes(content)[0];
+      let phrase_width: f32 = line.children.iter().map(|child| child.box_model.margin_box().width).sum();
+      assert!(phrase_width > 0.0);
+      assert!((content.box_model.content.width - phrase_width).abs() < EPSILON);
+    });
+  }
+
+  #[test]
+  fn inline_blocks_share_a_line() {
+    let source = r#"<div><div id="a" style="display: inline-block; width: 100px; height: 20px"></div><div id="b" style="display: inline-block; width: 150px; height: 20px"></div></div>"#;
+    with_layout(source, 800.0, 600.0, |root| {
+      let a = find_box(root, "a").unwrap().box_model;
+      let b = find_box(root, "b").unwrap().box_model;
+      assert_eq!(a.content.width, 100.0);
+      assert_eq!(b.content.width, 150.0);
+      assert_eq!(a.content.y, b.content.y);
+      assert_eq!(b.margin_box().x, a.margin_box().x + a.margin_box().width);
+    });
+  }
+
+  #[test]
+  fn exact_fit_does_not_wrap() {
+    let source = r#"<div style="width: 300px"><div id="a" style="display: inline-block; width: 100.1px; height: 10px"></div><div id="b" style="display: inline-block; width: 199.9px; height: 10px"></div></div>"#;
+    with_layout(source, 800.0, 600.0, |root| {
+      assert_eq!(get_line_boxes(root).len(), 1);
+      assert_eq!(find_box(root, "a").unwrap().box_model.content.y, find_box(root, "b").unwrap().box_model.content.y);
+    });
+  }
+
+  #[test]
+  fn text_content_skips_hidden_elements() {
+    let source = r#"<div><p>first</p><p style="display: none">gone</p><p style="visibility: hidden">hidden</p><p>second <span>third</span></p></div>"#;
+    with_layout(source, 800.0, 600.0, |root| {
+      assert_eq!(root.get_text_content(), "first\nsecond third");
+    });
+  }
+
+  #[test]
+  fn vw_resolves_against_viewport() {
+    with_layout(r#"<div><div id="box" style="width: 50vw"></div></div>"#, 1280.0, 600.0, |root| {
+      assert_eq!(find_box(root, "box").unwrap().box_model.content.width, 640.0);
+    });
+  }
+
+  #[test]
+  fn flex_space_between_positions_items() {
+    let source = r#"<div style="display: flex; justify-content: space-between; width: 400px"><div id="a" style="width: 50px"></div><div id="b" style="width: 50px"></div><div id="c" style="width: 50px"></div></div>"#;
+    with_layout(source, 800.0, 600.0, |root| {
+      let xs: Vec<f32> = ["a", "b", "c"].iter().map(|id| find_box(root, id).unwrap().box_model.content.x).collect();
+      assert_eq!(xs, vec![0.0, 175.0, 350.0]);
+    });
+  }
+
+  #[test]
+  fn vh_uses_viewport_height() {
+    with_layout(r#"<div><div id="box" style="height: 100vh"></div></div>"#, 800.0, 600.0, |root| {
+      assert_eq!(find_box(root, "box").unwrap().box_model.content.height, 600.0);
+    });
+  }
+
+  #[test]
+  fn mixed_font_sizes_share_baseline() {
+    let source = r#"<p><span style="font-size: 12px">small</span><span style="font-size: 24px">BIG</span></p>"#;
+    with_layout(source, 800.0, 600.0, |root| {
+      let line = get_line_boxes(root)[0];
+      let baselines: Vec<f32> = line.children.iter().map(|text| text.box_model.content.y + text.baseline).collect();
+      assert_eq!(baselines.len(), 2);
+      assert!((baselines[0] - baselines[1]).abs() < 1.0, "{baselines:?}");
+      assert!(line.children[0].box_model.content.y > line.children[1].box_model.content.y);
+    });
+  }
+
+  #[test]
+  fn nowrap_keeps_one_line() {
+    let sentence = "a long sentence that does not fit in a narrow box";
+    with_layout(&format!(r#"<div style="width: 100px; white-space: nowrap">{sentence}</div>"#), 800.0, 600.0, |root| {
+      assert_eq!(get_line_boxes(root).len(), 1);
+    });
+    with_layout(&format!(r#"<div style="width: 100px">{sentence}</div>"#), 800.0, 600.0, |root| {
+      assert!(get_line_boxes(root).len() > 1);
+    });
+  }
+
+  #[test]
+  fn layout_json_contains_content_widths() {
+    with_layout(r#"<div><div style="width: 100px; height: 10px"></div><div style="width: 200px; height: 20px"></div></div>"#, 800.0, 600.0, |root| {
+      let json = root.to_json();
+      assert!(json.starts_with(r#"{"box_type":"Block","tag":"div","content":{"x":0,"y":0,"width":800,"height":30}"#), "{json}");
+      assert!(json.contains(r#""tag":"div","content":{"x":0,"y":0,"width":100,"height":10}"#), "{json}");
+      assert!(json.contains(r#""tag":"div","content":{"x":0,"y":10,"width":200,"height":20}"#), "{json}");
+    });
+  }
+
+  #[test]
+  fn layout_prints_nothing_by_default() {
+    // 测试框架会捕获输出，因此在不捕获输出的子进程中布局，检查前后两个标记之间没有任何输出
+    const CHILD_ENV: &str = "TOY_BROWSER_LAYOUT_OUTPUT_CHILD";
+    if std::env::var_os(CHILD_ENV).is_some() {
+      println!("<layout>");
+      eprintln!("<layout>");
+      with_layout(r#"<div><p>hello <span>world</span></p><div style="display: flex"><p>flex</p></div></div>"#, 800.0, 600.0, |_| {});
+      println!("</layout>");
+      eprintln!("</layout>");
+      return;
+    }
+    let output = std::process::Command::new(std::env::current_exe().unwrap())
+      .args(["--exact", "layout::tests::layout_prints_nothing_by_default", "--nocapture"])
+      .env(CHILD_ENV, "1")
+      .output()
+      .unwrap();
+    assert!(output.status.success());
+    for stream in [output.stdout, output.stderr] {
+      let stream = String::from_utf8_lossy(&stream).into_owned();
+      assert!(stream.contains("<layout>\n</layout>\n"), "{stream}");
+    }
+  }
+
+  #[test]
+  fn inline_size_sets_width() {
+    with_layout(r#"<div><div id="box" style="inline-size: 200px; block-size: 50px"></div></div>"#, 800.0, 600.0, |root| {
+      let content = find_box(root, "box").unwrap().box_model.content;
+      assert_eq!((content.width, content.height), (200.0, 50.0));
+    });
+  }
+
+  #[test]
+  fn negative_margin_shifts_box() {
+    with_layout(r#"<div style="padding-left: 20px"><div id="box" style="margin-left: -8px; height: 10px"></div></div>"#, 800.0, 600.0, |root| {
+      let content = find_box(root, "box").unwrap().box_model.content;
+      assert_eq!(content.x, 12.0);
+      assert_eq!(content.width, 788.0);
+    });
+  }
+
+  #[test]
+  fn huge_grid_row_is_clamped() {
+    for row in ["1000000000", "span 1000000000", "1 / -1000000000"] {
+      let source = format!(r#"<div style="display: grid; grid-template-columns: 100px"><p id="item" style="grid-row: {row}; height: 10px"></p></div>"#);
+      with_layout(&source, 800.0, 600.0, |root| {
+        let area = find_box(root, "item").unwrap().grid_area.unwrap();
+        assert!(area.row_end <= 2 * MAX_GRID_LINE as usize, "{row}: {area:?}");
+      });
+    }
+  }
+
+  #[test]
+  fn justify_content_distributes_items() {
+    let cases = [
+      ("flex-start", [0.0, 100.0, 200.0]),
+      ("flex-end", [300.0, 400.0, 500.0]),
+      ("center", [150.0, 250.0, 350.0]),
+      ("space-between", [0.0, 250.0, 500.0]),
+      ("space-around", [50.0, 250.0, 450.0]),
+      ("space-evenly", [75.0, 250.0, 425.0])
+    ];
+    for (justify_content, expected) in cases {
+      let source = format!(r#"<div style="display: flex; justify-content: {justify_content}; width: 600px"><div id="a" style="width: 100px"></div><div id="b" style="width: 100px"></div><div id="c" style="width: 100px"></div></div>"#);
+      with_layout(&source, 800.0, 600.0, |root| {
+        let xs: Vec<f32> = ["a", "b", "c"].iter().map(|id| find_box(root, id).unwrap().box_model.content.x).collect();
+        assert_eq!(xs, expected, "{justify_content}");
+      });
+    }
+  }
+}
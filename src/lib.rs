@@ -0,0 +1,29 @@
+//! 玩具浏览器引擎：解析`html`及`css`，计算样式与布局，再光栅化为图片或者在窗口中显示
+//!
+//! 流水线依次为`parse_html` → `StyleTree` → `LayoutTree` → 光栅化；不需要窗口时可以直接用`render_html_to_image`得到整个页面的图片
+//!
+//! ```
+//! let document = toy_browser::parse_html(String::from("<div><p>hello</p></div>")).unwrap();
+//! assert_eq!(document.root.text_content(), "hello");
+//! ```
+
+pub mod dom;
+pub mod html;
+pub mod css;
+pub mod style;
+pub mod layout;
+pub mod raster;
+pub mod font;
+pub mod fetch;
+pub mod thread;
+// 脚本只在流水线内部执行，不对外暴露
+mod script;
+
+pub use dom::Document;
+pub use css::Stylesheet;
+pub use style::StyleTree;
+pub use layout::{LayoutTree, LayoutBox};
+pub use raster::{RasterWindow, RenderOptions, render_html_to_image, render_html_to_image_with};
+pub use thread::PageThread;
+pub use html::{parse as parse_html, parse_with_base as parse_html_with_base, HtmlParseError};
+pub use css::parse as parse_css;
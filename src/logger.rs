@@ -0,0 +1,27 @@
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// 将本程序的日志输出到标准输出，依赖库的日志不输出
+struct StdoutLogger;
+
+impl Log for StdoutLogger {
+  fn enabled(&self, metadata: &Metadata) -> bool {
+    metadata.level() <= log::max_level() && metadata.target().starts_with(env!("CARGO_CRATE_NAME"))
+  }
+
+  fn log(&self, record: &Record) {
+    if self.enabled(record.metadata()) {
+      println!("[{}] {}", record.level(), record.args());
+    }
+  }
+
+  fn flush(&self) {}
+}
+
+static LOGGER: StdoutLogger = StdoutLogger;
+
+/// 初始化日志，默认只输出警告和错误；`verbose`时额外输出调试日志（如布局过程中各个盒子的尺寸）
+pub fn init(verbose: bool) {
+  if log::set_logger(&LOGGER).is_ok() {
+    log::set_max_level(if verbose { LevelFilter::Debug } else { LevelFilter::Warn });
+  }
+}
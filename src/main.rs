@@ -1,34 +1,78 @@
-mod dom;
-mod html;
-mod css;
-mod style;
-mod layout;
-mod raster;
-mod font;
-mod thread;
+mod cli;
+mod logger;
 // use std::io::Read; // 使用read_to_string方法必须引入这个
 // use std::fs::File;
-use std::fs;
-use std::io::Error;
+use std::error::Error;
 use std::path::PathBuf;
+use std::sync::Mutex;
 use std::time::{Duration};
-use regex::Regex;
 use tokio::runtime::Runtime;
 use tokio::time::{self, Instant};
+use toy_browser::{html, style, layout, raster, font, fetch, thread};
 
-fn painting_test() -> Result<(), Error> {
+/// 页面中动态计数的占位符前缀，形如`there:{???}`
+const COUNTER_PLACEHOLDER: &str = "there:{";
+
+/// 将第一个计数占位符花括号内的内容替换为`num`，没有占位符时原样返回
+///
+/// `find`返回的都是字符边界上的字节下标，因此占位符前后是中文等多字节字符时也能正确切分；只匹配到占位符后的第一个`}`，不会吞掉同一行后面的内容
+fn replace_counter(content: &str, num: usize) -> String {
+  let Some(start) = content.find(COUNTER_PLACEHOLDER).map(|idx| idx + COUNTER_PLACEHOLDER.len()) else {
+    return content.to_string();
+  };
+  let Some(len) = content[start..].find('}') else {
+    return content.to_string();
+  };
+  format!("{}{}{}", &content[..start], num, &content[start + len..])
+}
+
+fn painting_test(config: cli::Config) -> Result<(), Box<dyn Error>> {
   let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
   file_path.push("src/demo/text-test.html");
   let file_path_url = file_path.to_str().unwrap_or("");
-  println!("{}", file_path_url);
-  let mut content = fs::read_to_string(file_path_url).unwrap();
+  // 页面地址为http(s)地址或本地路径，缺省时加载本地的示例页面
+  let url = config.url.unwrap_or(file_path_url.to_string());
+  println!("{}", url);
+  // http(s)页面在tokio运行时中异步下载，超时或非200的响应都会返回错误
+  let page = Runtime::new()?.block_on(fetch::fetch_async(url))?;
+  let mut content = page.html;
   // 模拟视窗
   let mut viewport = layout::Box::default();
-  viewport.content.width = 1280.0;
-  let mut save_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-  save_path.push("result.png");
-  let page_thread = thread::PageThread::new(viewport, String::from("test window"));
-  let content_reg = Regex::new(r"(there:)\{.+\}").unwrap(); // FIXME: regex真的不支持中文字符匹配？
+  viewport.content.width = config.width;
+  viewport.content.height = config.height;
+  // 不打开窗口时同窗口中的流水线：相对地址基于页面地址解析，页面本身记为访问过
+  let mut options = raster::RenderOptions { base_url: Some(page.url.clone()), ..Default::default() };
+  options.history.visit(&page.url);
+  // 只输出布局树，用于调试布局
+  if let Some(path) = config.layout_json {
+    let layout_tree = layout::LayoutTree {
+      style_tree: style::StyleTree {
+        document: html::parse_with_base(content, options.base_url)?,
+        history: options.history,
+        focused: None
+      }
+    };
+    let root_box = layout_tree.get_layout_tree(viewport, &Mutex::new(font::TextLayout::default()));
+    std::fs::write(&path, root_box.to_json())?;
+    println!("已保存到{}", path.display());
+    return Ok(());
+  }
+  // 指定了输出路径时不打开窗口，直接渲染为图片
+  if let Some(output) = config.output {
+    match config.selector {
+      Some(selector) => raster::render_subtree_with(content, &selector, viewport, &options)?
+        .ok_or(format!("没有找到要渲染的元素：{selector}"))?
+        .save(&output)?,
+      None if output.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("svg")) => {
+        std::fs::write(&output, raster::render_to_svg_with(content, viewport, &options)?)?
+      },
+      None => raster::render_to_png_with(content, viewport, &output, &options)?
+    }
+    println!("已保存到{}", output.display());
+    return Ok(());
+  }
+  let page_thread = thread::PageThread::new(viewport, String::from("test window"), Some(page.url.clone()));
+  page_thread.history.lock().unwrap().visit(&page.url);
   let window_store = page_thread.raster_window.clone();
   let tab = std::thread::spawn(move || {
     let rt = Runtime::new().unwrap();
@@ -42,7 +86,7 @@ fn painting_test() -> Result<(), Error> {
       // let start_t = Instant::now();
       loop {
         intv.tick().await;
-        content = content_reg.replace(content.as_str(), format!("$1{{{}}}", num)).to_string();
+        content = replace_counter(&content, num);
         page_thread.html_sender.send(content.clone()).unwrap();
         num += 1;
       }
@@ -55,5 +99,37 @@ fn painting_test() -> Result<(), Error> {
 }
 
 fn main() {
-  painting_test().unwrap();
+  let config = match cli::parse_args(std::env::args().skip(1)) {
+    Ok(config) => config,
+    Err(err) => {
+      eprintln!("{err}\n\n{}", cli::USAGE);
+      std::process::exit(2);
+    }
+  };
+  if config.help {
+    println!("{}", cli::USAGE);
+    return;
+  }
+  logger::init(config.verbose);
+  if let Err(err) = painting_test(config) {
+    eprintln!("{err}");
+    // 页面加载失败通常是地址或路径写错了，附上用法说明
+    if err.is::<fetch::FetchError>() {
+      eprintln!("\n{}", cli::USAGE);
+    }
+    std::process::exit(1);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn replace_counter_with_multibyte_text() {
+    assert_eq!(replace_counter("你好there:{0}次，hello", 12), "你好there:{12}次，hello");
+    assert_eq!(replace_counter("计数there:{3} {other}", 4), "计数there:{4} {other}");
+    assert_eq!(replace_counter("没有占位符", 1), "没有占位符");
+    assert_eq!(replace_counter("未闭合there:{1", 2), "未闭合there:{1");
+  }
 }
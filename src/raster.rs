@@ -1,21 +1,36 @@
+use std::error::Error;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, SendError, Sender};
 
 use crate::css::{
   CSSColor,
-  CSSValue
+  CSSValue,
+  Filter
 };
+use crate::dom::{Node, NodeType};
+use crate::fetch::History;
+use crate::font::TextLayout;
+use crate::{html, layout};
+use crate::html::HtmlParseError;
 use crate::layout::{
   RectArea,
   LayoutBox,
+  LayoutTree,
   BoxType,
-  get_text_layout
+  StickyOffsets
 };
+use crate::style::{Overflow, StyleTree};
+use fontdue::Metrics;
 use fontdue::layout::GlyphPosition;
+use image::{Rgba, RgbaImage};
+use url::Url;
 use ggez::mint::Vector2;
 use ggez::{
   event,
   glam::*,
   graphics::{self, Color},
+  input::keyboard::{KeyCode, KeyInput, KeyMods},
   Context, GameResult,
 };
 
@@ -33,6 +48,41 @@ static TRANSPARENT: CSSColor = CSSColor {
   a: 0
 };
 
+/// 默认的文字gamma值，即不做gamma校正
+pub const DEFAULT_GAMMA: f32 = 1.0;
+
+/// 光标宽度
+const CARET_WIDTH: f32 = 1.0;
+
+/// 光标闪烁的间隔（毫秒）
+const CARET_BLINK_INTERVAL: u128 = 500;
+
+/// 文本装饰线（下划线、删除线）宽度与字号的比例，宽度至少为1px
+const TEXT_DECORATION_RATIO: f32 = 1.0 / 16.0;
+
+/// 鼠标滚轮每一格滚动的距离
+const SCROLL_STEP: f32 = 40.0;
+
+/// 焦点轮廓的宽度
+const FOCUS_OUTLINE_WIDTH: f32 = 2.0;
+
+/// 焦点轮廓的颜色
+static FOCUS_OUTLINE_COLOR: CSSColor = CSSColor {
+  r: 0,
+  g: 95,
+  b: 204,
+  a: 255
+};
+
+/// 可聚焦的元素
+#[derive(Debug, Clone, Copy)]
+pub struct Focusable {
+  /// 元素的焦点序号（见`Node::focusable_elements`）
+  pub index: usize,
+  /// 元素所有盒子的`border-box`合并后的区域
+  pub area: RectArea
+}
+
 /// 文本渲染信息
 #[derive(Debug)]
 pub struct TextRenderInfo {
@@ -40,6 +90,8 @@ pub struct TextRenderInfo {
   color: CSSColor,
   /// 文本占据的矩形区域
   area: RectArea,
+  /// 基线到`area`顶部的距离，所有字符都按照这条基线对齐
+  baseline: f32,
   /// 文本光栅化后的字符信息
   glyphs: Arc<Mutex<Vec<GlyphPosition>>>
 }
@@ -49,30 +101,175 @@ pub struct TextRenderInfo {
 pub enum DisplayCommand {
   /// 单纯矩形区域色块
   Rectangle(CSSColor, RectArea),
+  /// 圆角矩形区域色块，最后一项为圆角半径
+  RoundedRectangle(CSSColor, RectArea, f32),
   /// 文本
-  Text(TextRenderInfo)
+  Text(TextRenderInfo),
+  /// 应用了`filter`的一组命令（元素及其子孙），需要先绘制到中间图像，处理后再整体合成
+  Filter(Vec<Filter>, Vec<DisplayCommand>),
+  /// 开始裁剪：之后的命令只绘制在该区域（与外层裁剪区域的重叠部分）内
+  PushClip(RectArea),
+  /// 结束最近一次的裁剪
+  PopClip,
+  /// 固定定位元素的一组命令，坐标相对于视窗，不随页面滚动
+  Fixed(Vec<DisplayCommand>),
+  /// 粘性定位元素的一组命令，第一项为盒子的`border-box`，绘制时根据滚动距离整体平移
+  Sticky(RectArea, StickyOffsets, Vec<DisplayCommand>)
+}
+
+impl DisplayCommand {
+  /// 按滤镜逐个变换命令中的颜色，得到新的命令；模糊无法逐个颜色处理，会被忽略
+  fn with_filters(&self, filters: &[Filter]) -> DisplayCommand {
+    let map = |color: CSSColor| filters.iter().fold(color, |color, filter| filter.apply(color));
+    match self {
+      DisplayCommand::Rectangle(color, rect) => DisplayCommand::Rectangle(map(*color), *rect),
+      DisplayCommand::RoundedRectangle(color, rect, radius) => DisplayCommand::RoundedRectangle(map(*color), *rect, *radius),
+      DisplayCommand::Text(info) => DisplayCommand::Text(TextRenderInfo {
+        color: map(info.color),
+        area: info.area,
+        baseline: info.baseline,
+        glyphs: info.glyphs.clone()
+      }),
+      DisplayCommand::Filter(inner, commands) => DisplayCommand::Filter(
+        inner.clone(),
+        commands.iter().map(|command| command.with_filters(filters)).collect()
+      ),
+      DisplayCommand::PushClip(rect) => DisplayCommand::PushClip(*rect),
+      DisplayCommand::PopClip => DisplayCommand::PopClip,
+      DisplayCommand::Fixed(commands) => DisplayCommand::Fixed(
+        commands.iter().map(|command| command.with_filters(filters)).collect()
+      ),
+      DisplayCommand::Sticky(area, offsets, commands) => DisplayCommand::Sticky(
+        *area,
+        *offsets,
+        commands.iter().map(|command| command.with_filters(filters)).collect()
+      )
+    }
+  }
+
+  /// 将命令竖直平移`dy`，得到新的命令
+  fn with_offset(&self, dy: f32) -> DisplayCommand {
+    let offset = |rect: RectArea| RectArea { y: rect.y + dy, ..rect };
+    match self {
+      DisplayCommand::Rectangle(color, rect) => DisplayCommand::Rectangle(*color, offset(*rect)),
+      DisplayCommand::RoundedRectangle(color, rect, radius) => DisplayCommand::RoundedRectangle(*color, offset(*rect), *radius),
+      DisplayCommand::Text(info) => DisplayCommand::Text(TextRenderInfo {
+        color: info.color,
+        area: offset(info.area),
+        baseline: info.baseline,
+        glyphs: info.glyphs.clone()
+      }),
+      DisplayCommand::Filter(filters, commands) => DisplayCommand::Filter(
+        filters.clone(),
+        commands.iter().map(|command| command.with_offset(dy)).collect()
+      ),
+      DisplayCommand::PushClip(rect) => DisplayCommand::PushClip(offset(*rect)),
+      DisplayCommand::PopClip => DisplayCommand::PopClip,
+      DisplayCommand::Fixed(commands) => DisplayCommand::Fixed(
+        commands.iter().map(|command| command.with_offset(dy)).collect()
+      ),
+      DisplayCommand::Sticky(area, offsets, commands) => DisplayCommand::Sticky(
+        offset(*area),
+        StickyOffsets { limit: offset(offsets.limit), ..*offsets },
+        commands.iter().map(|command| command.with_offset(dy)).collect()
+      )
+    }
+  }
+}
+
+/// 发送给窗口的消息
+#[derive(Debug)]
+pub enum WindowMessage {
+  /// 替换当前的绘制命令列表
+  DisplayList(Vec<DisplayCommand>),
+  /// 替换当前的光标，`None`表示没有可编辑的元素
+  Caret(Option<DisplayCommand>),
+  /// 替换当前页面中可聚焦的元素
+  Focusables(Vec<Focusable>),
+  /// 页面内容的高度，用于限制滚动范围
+  ContentHeight(f32),
+  /// 修改窗口标题
+  Title(String)
+}
+
+/// 窗口句柄，流水线线程通过它向窗口发送消息
+///
+/// 窗口的`event loop`只能运行在主线程，其余线程不直接访问窗口状态，而是经由通道发送消息，
+/// 窗口在每一帧的`update`中取出所有待处理的消息
+#[derive(Debug, Clone)]
+pub struct WindowHandle {
+  sender: Sender<WindowMessage>
 }
 
 /// ggez绘制状态信息
 struct WindowState {
-  display_commands: Arc<Mutex<Vec<DisplayCommand>>>,
+  /// 接收窗口句柄发来的消息
+  receiver: Receiver<WindowMessage>,
+  display_commands: Vec<DisplayCommand>,
+  /// 可编辑元素的光标，按照`CARET_BLINK_INTERVAL`闪烁
+  caret: Option<DisplayCommand>,
+  /// 页面中可聚焦的元素，按文档顺序排列
+  focusables: Vec<Focusable>,
+  /// 获得焦点的元素序号，与流水线共享，用于匹配`:focus`
+  focus: Arc<Mutex<Option<usize>>>,
+  /// 文字布局模块，光栅化文字时需要用到其中的字体
+  text_layout: Arc<Mutex<TextLayout>>,
   /// device pixel ratio
-  dpr: f32
+  dpr: f32,
+  /// 文字混合使用的gamma值
+  gamma: f32,
+  /// 视窗高度（逻辑像素）
+  viewport_height: f32,
+  /// 页面内容高度
+  content_height: f32,
+  /// 窗口的宽高（逻辑像素），与流水线共享
+  size: Arc<Mutex<(f32, f32)>>,
+  /// 竖直方向的滚动距离
+  scroll_y: f32
 }
 
 /// 光栅化输出窗口
 pub struct RasterWindow {
   /// 窗口id，也是标题
   id: String,
-  pub display_commands: Arc<Mutex<Vec<DisplayCommand>>>
+  handle: WindowHandle,
+  /// 消息接收端，启动窗口时被取走
+  receiver: Option<Receiver<WindowMessage>>,
+  /// 与布局阶段共享的文字布局模块
+  pub text_layout: Arc<Mutex<TextLayout>>,
+  /// 文字混合使用的gamma值，大于1时文字更粗重，小于1时更纤细
+  pub gamma: f32,
+  /// 获得焦点的元素序号，由窗口响应`Tab`键更新，样式计算时读取
+  pub focus: Arc<Mutex<Option<usize>>>,
+  /// 窗口的宽高（逻辑像素），即布局时的视窗；窗口缩放时更新，流水线据此重新布局
+  pub size: Arc<Mutex<(f32, f32)>>
+}
+
+/// 对字形覆盖率（0~255）做gamma校正，得到写入的alpha值
+pub fn apply_gamma(mask: u8, gamma: f32) -> u8 {
+  if gamma == DEFAULT_GAMMA {
+    return mask;
+  }
+  ((mask as f32 / 255.0).powf(1.0 / gamma) * 255.0).round() as u8
 }
 
 impl TextRenderInfo {
+  /// 字符位图顶部到`area`顶部的距离：基线以上为轮廓高度加上`ymin`（轮廓底部相对基线的偏移，下沉的部分为负）
+  ///
+  /// 与fontdue排版时的取整方式一致
+  fn get_glyph_top(&self, metrics: &Metrics) -> f32 {
+    self.baseline + (-metrics.bounds.height - metrics.bounds.ymin).floor()
+  }
+
   /// 将当前文本光栅化信息转为ggez image，方便绘制
-  fn to_image(&self, ctx: &Context) -> graphics::Image {
+  ///
+  /// 宽或高不足1像素时无法创建纹理，返回`None`
+  fn to_image(&self, ctx: &Context, text_layout: &TextLayout, gamma: f32) -> Option<graphics::Image> {
+    if !(self.area.width >= 1.0 && self.area.height >= 1.0) {
+      return None;
+    }
     let w = self.area.width as usize;
     let h = self.area.height as usize;
-    let text_layout = get_text_layout();
     let glyphs = self.glyphs.lock().unwrap();
     let pixel_num = w * h * 4;
     let mut pixels: Vec<u8> = vec![0; pixel_num];
@@ -80,63 +277,166 @@ impl TextRenderInfo {
 
     // 逐字符填充光栅化信息
     for glyph in &*glyphs {
-      let (_, bitmap) = text_layout.fonts[glyph.font_index].rasterize_config(glyph.key);
+      let (metrics, bitmap) = text_layout.fonts[glyph.font_index].rasterize_config(glyph.key);
+      let top = self.get_glyph_top(&metrics);
       for (idx, mask) in bitmap.iter().enumerate() {
         if glyph.width == 0 || glyph.height == 0 {
           continue;
         }
         let dx = idx % glyph.width;
-        let dy = (idx as f32 / glyph.width as f32).floor() as usize;
+        let dy = (idx as f32 / glyph.width as f32).floor();
         let x = glyph.x as usize + dx;
-        let y = glyph.y as usize + dy;
-        if x >= w || y >= h {
+        let y = top + dy;
+        if x >= w || y < 0.0 || y as usize >= h {
           continue;
         }
+        let y = y as usize;
         let start_idx = (y * w + x) * 4; // NOTICE: 按行优先排列的索引
         pixels[start_idx] = font_color.r;
         pixels[start_idx + 1] = font_color.g;
         pixels[start_idx + 2] = font_color.b;
-        pixels[start_idx + 3] = *mask;
+        pixels[start_idx + 3] = apply_gamma(*mask, gamma);
       }
     }
 
     // NOTICE: 这里绘制的像素必须转换为浮点数[0, 1]（Rgba8UnormSrgb格式会自动将u8转为0到1的浮点数），不然会报错！
-    graphics::Image::from_pixels(ctx, pixels.as_slice(), graphics::ImageFormat::Rgba8UnormSrgb, w as u32, h as u32)
+    Some(graphics::Image::from_pixels(ctx, pixels.as_slice(), graphics::ImageFormat::Rgba8UnormSrgb, w as u32, h as u32))
   }
 }
 
 impl WindowState {
-  /// 在ggez画布上绘制命令列表
+  /// 处理所有待处理的消息，返回最新的窗口标题（如果有的话）
+  fn receive_messages(&mut self) -> Option<String> {
+    let mut title = None;
+    for msg in self.receiver.try_iter() {
+      match msg {
+        WindowMessage::DisplayList(display_list) => self.display_commands = display_list,
+        WindowMessage::Caret(caret) => self.caret = caret,
+        WindowMessage::Focusables(focusables) => self.focusables = focusables,
+        WindowMessage::ContentHeight(height) => self.content_height = height,
+        WindowMessage::Title(new_title) => title = Some(new_title)
+      }
+    }
+    // 内容变少时原来的滚动距离可能超出新的范围
+    self.scroll_y = clamp_scroll(self.scroll_y, self.content_height, self.viewport_height);
+    title
+  }
+
+  /// 在ggez画布上绘制命令列表，光标处于闪烁的可见阶段时绘制在最上层
   fn draw_commands(&self, ctx: &mut Context, canvas: &mut graphics::Canvas) {
-    let display_list = self.display_commands.lock().unwrap();
-    println!("display list len: {}", display_list.len());
-    for command in &*display_list {
-      match command {
-        DisplayCommand::Rectangle(color, rect) => {
-          let mut mb = graphics::MeshBuilder::new();
-          let mut ggez_rect = rect.to_ggez_rect();
-          // 考虑到dpr，所以需要的矩形区域进行相应的放大，且起点也要偏移
-          ggez_rect.x *= self.dpr;
-          ggez_rect.y *= self.dpr;
-          ggez_rect.scale(self.dpr, self.dpr);
-          mb.rectangle(graphics::DrawMode::fill(), ggez_rect, color.to_ggez_color()).unwrap();
-          let mesh = graphics::Mesh::from_data(ctx, mb.build());
-          let draw_param = graphics::DrawParam::new();
-          canvas.draw(&mesh, draw_param);
-        },
-        DisplayCommand::Text(info) => {
-          // 要么跟之前类似把以前的字体光栅化信息直接写入到纹理（图像像素），要么基于ggez自带的text系统重写从字体布局开始写一遍……
-          let text_image = info.to_image(ctx);
-          let draw_param = graphics::DrawParam::new()
-            .dest(Vector2 {
-              x: info.area.x * self.dpr,
-              y: info.area.y * self.dpr
-            })
-            .scale(Vector2 {
-              x: self.dpr,
-              y: self.dpr
-            }); // TODO: 同理这里也要考虑dpr，不过单纯地使用scale进行放大会使字体看起来很模糊
-          canvas.draw(&text_image, draw_param);
+    let text_layout = self.text_layout.lock().unwrap();
+    log::debug!("display list len: {}", self.display_commands.len());
+    let mut clips = Vec::new();
+    for command in &self.display_commands {
+      self.draw_command(ctx, canvas, command, &mut clips, &text_layout);
+    }
+    // 焦点轮廓和光标不受裁剪影响
+    canvas.set_default_scissor_rect();
+    let mut clips = Vec::new();
+    let focused = *self.focus.lock().unwrap();
+    if let Some(focusable) = self.focusables.iter().find(|focusable| Some(focusable.index) == focused) {
+      for command in get_focus_outline(focusable.area) {
+        self.draw_command(ctx, canvas, &command, &mut clips, &text_layout);
+      }
+    }
+    let is_caret_visible = (ctx.time.time_since_start().as_millis() / CARET_BLINK_INTERVAL).is_multiple_of(2);
+    if let (Some(caret), true) = (&self.caret, is_caret_visible) {
+      self.draw_command(ctx, canvas, caret, &mut clips, &text_layout);
+    }
+  }
+
+  /// 将裁剪区域设置为画布的scissor rect，返回裁剪区域在画布上是否可见
+  ///
+  /// scissor rect使用的是物理像素坐标，不受`set_screen_coordinates`影响，因此需要自行处理dpr和滚动距离
+  fn set_clip(&self, ctx: &Context, canvas: &mut graphics::Canvas, clip: Option<&RectArea>) -> bool {
+    let Some(clip) = clip else {
+      canvas.set_default_scissor_rect();
+      return true;
+    };
+    let (drawable_width, drawable_height) = ctx.gfx.drawable_size();
+    let left = (clip.x * self.dpr).max(0.0);
+    let top = ((clip.y - self.scroll_y) * self.dpr).max(0.0);
+    let right = ((clip.x + clip.width) * self.dpr).min(drawable_width);
+    let bottom = ((clip.y + clip.height - self.scroll_y) * self.dpr).min(drawable_height);
+    // NOTICE: 宽高为0或者起点在画布外时设置scissor rect会报错
+    if right - left < 1.0 || bottom - top < 1.0 {
+      return false;
+    }
+    canvas.set_scissor_rect(graphics::Rect::new(left, top, right - left, bottom - top)).is_ok()
+  }
+
+  /// 在ggez画布上绘制单个命令，`clips`为当前的裁剪区域栈，记录了区域以及该区域是否可见
+  fn draw_command(&self, ctx: &mut Context, canvas: &mut graphics::Canvas, command: &DisplayCommand, clips: &mut Vec<(RectArea, bool)>, text_layout: &TextLayout) {
+    let is_clip_command = matches!(command, DisplayCommand::PushClip(_) | DisplayCommand::PopClip);
+    if !is_clip_command && clips.last().is_some_and(|(_, is_visible)| !is_visible) {
+      return;
+    }
+    match command {
+      DisplayCommand::Rectangle(color, rect) => {
+        let mut mb = graphics::MeshBuilder::new();
+        let mut ggez_rect = rect.to_ggez_rect();
+        // 考虑到dpr，所以需要的矩形区域进行相应的放大，且起点也要偏移
+        ggez_rect.x *= self.dpr;
+        ggez_rect.y *= self.dpr;
+        ggez_rect.scale(self.dpr, self.dpr);
+        mb.rectangle(graphics::DrawMode::fill(), ggez_rect, color.to_ggez_color()).unwrap();
+        let mesh = graphics::Mesh::from_data(ctx, mb.build());
+        let draw_param = graphics::DrawParam::new();
+        canvas.draw(&mesh, draw_param);
+      },
+      DisplayCommand::RoundedRectangle(color, rect, radius) => {
+        let mut mb = graphics::MeshBuilder::new();
+        let mut ggez_rect = rect.to_ggez_rect();
+        ggez_rect.x *= self.dpr;
+        ggez_rect.y *= self.dpr;
+        ggez_rect.scale(self.dpr, self.dpr);
+        mb.rounded_rectangle(graphics::DrawMode::fill(), ggez_rect, radius * self.dpr, color.to_ggez_color()).unwrap();
+        let mesh = graphics::Mesh::from_data(ctx, mb.build());
+        canvas.draw(&mesh, graphics::DrawParam::new());
+      },
+      DisplayCommand::Text(info) => {
+        // 要么跟之前类似把以前的字体光栅化信息直接写入到纹理（图像像素），要么基于ggez自带的text系统重写从字体布局开始写一遍……
+        let Some(text_image) = info.to_image(ctx, text_layout, self.gamma) else {
+          return;
+        };
+        let draw_param = graphics::DrawParam::new()
+          .dest(Vector2 {
+            x: info.area.x * self.dpr,
+            y: info.area.y * self.dpr
+          })
+          .scale(Vector2 {
+            x: self.dpr,
+            y: self.dpr
+          }); // TODO: 同理这里也要考虑dpr，不过单纯地使用scale进行放大会使字体看起来很模糊
+        canvas.draw(&text_image, draw_param);
+      },
+      // 窗口中没有中间图像，只能逐个变换颜色后绘制，因此暂不支持`blur`
+      DisplayCommand::Filter(filters, commands) => {
+        for command in commands {
+          let command = command.with_filters(filters);
+          self.draw_command(ctx, canvas, &command, clips, text_layout);
+        }
+      },
+      DisplayCommand::PushClip(rect) => {
+        let clip = clips.last().map_or(*rect, |(clip, _)| clip.intersect(*rect));
+        let is_visible = self.set_clip(ctx, canvas, Some(&clip));
+        clips.push((clip, is_visible));
+      },
+      DisplayCommand::PopClip => {
+        clips.pop();
+        let clip = clips.last().map(|(clip, _)| *clip);
+        self.set_clip(ctx, canvas, clip.as_ref());
+      },
+      // 画布的可见区域随滚动移动，固定定位的内容需要反向平移才能停留在视窗中的同一位置
+      DisplayCommand::Fixed(commands) => {
+        for command in commands {
+          self.draw_command(ctx, canvas, &command.with_offset(self.scroll_y), clips, text_layout);
+        }
+      },
+      DisplayCommand::Sticky(area, offsets, commands) => {
+        let dy = get_sticky_offset(*area, offsets, self.scroll_y, self.viewport_height);
+        for command in commands {
+          self.draw_command(ctx, canvas, &command.with_offset(dy), clips, text_layout);
         }
       }
     }
@@ -144,55 +444,398 @@ impl WindowState {
 }
 
 impl event::EventHandler<ggez::GameError> for WindowState {
-  fn update(&mut self, _ctx: &mut Context) -> GameResult {
+  fn update(&mut self, ctx: &mut Context) -> GameResult {
+    if let Some(title) = self.receive_messages() {
+      ctx.gfx.set_window_title(title.as_str());
+    }
+    Ok(())
+  }
+
+  fn key_down_event(&mut self, ctx: &mut Context, input: KeyInput, _repeated: bool) -> GameResult {
+    match input.keycode {
+      Some(KeyCode::Escape) => ctx.request_quit(),
+      // `Tab`聚焦下一个元素，`Shift + Tab`聚焦上一个元素；`:focus`样式在流水线下一次计算样式时生效
+      Some(KeyCode::Tab) => {
+        let mut focus = self.focus.lock().unwrap();
+        *focus = next_focus(&self.focusables, *focus, input.mods.contains(KeyMods::SHIFT));
+      },
+      _ => {}
+    }
+    Ok(())
+  }
+
+  /// 窗口缩放后更新视窗尺寸，流水线下一次处理页面时按新的尺寸重新布局
+  fn resize_event(&mut self, _ctx: &mut Context, width: f32, height: f32) -> GameResult {
+    let size = (width / self.dpr, height / self.dpr);
+    *self.size.lock().unwrap() = size;
+    self.viewport_height = size.1;
+    self.scroll_y = clamp_scroll(self.scroll_y, self.content_height, self.viewport_height);
+    Ok(())
+  }
+
+  fn mouse_wheel_event(&mut self, _ctx: &mut Context, _x: f32, y: f32) -> GameResult {
+    self.scroll_y = clamp_scroll(self.scroll_y - y * SCROLL_STEP, self.content_height, self.viewport_height);
     Ok(())
   }
 
   fn draw(&mut self, ctx: &mut Context) -> GameResult {
     let mut canvas = graphics::Canvas::from_frame(ctx, Color::WHITE);
+    // 滚动通过移动画布的可见区域实现，绘制命令本身的坐标不变
+    let (width, height) = ctx.gfx.drawable_size();
+    canvas.set_screen_coordinates(graphics::Rect::new(0.0, self.scroll_y * self.dpr, width, height));
+    // 半透明的色块按照alpha与已绘制的内容混合，而不是直接覆盖
+    canvas.set_blend_mode(graphics::BlendMode::ALPHA);
     self.draw_commands(ctx, &mut canvas);
     canvas.finish(ctx)?;
-    println!("===================draw=============");
+    log::debug!("===================draw=============");
     Ok(())
   }
 }
 
+impl WindowHandle {
+  /// 发送新的绘制命令列表；窗口关闭后返回错误
+  pub fn send_display_list(&self, display_list: Vec<DisplayCommand>) -> Result<(), SendError<WindowMessage>> {
+    self.sender.send(WindowMessage::DisplayList(display_list))
+  }
+
+  /// 请求修改窗口标题
+  pub fn set_title(&self, title: String) -> Result<(), SendError<WindowMessage>> {
+    self.sender.send(WindowMessage::Title(title))
+  }
+
+  /// 光栅化布局树，并将绘制命令列表、光标及可聚焦的元素发送给窗口
+  pub fn raster(&self, layout_tree: &LayoutBox) -> Result<(), SendError<WindowMessage>> {
+    self.send_display_list(get_display_list(layout_tree))?;
+    self.sender.send(WindowMessage::Caret(get_caret(layout_tree)))?;
+    self.sender.send(WindowMessage::Focusables(get_focusables(layout_tree)))?;
+    self.sender.send(WindowMessage::ContentHeight(layout_tree.box_model.margin_box().height))
+  }
+}
+
 impl RasterWindow {
-  pub fn new(id: String) -> Self {
-    let display_commands: Arc<Mutex<Vec<DisplayCommand>>> = Arc::new(Mutex::new(Vec::new()));
-    Self { id, display_commands }
+  pub fn new(id: String, text_layout: Arc<Mutex<TextLayout>>) -> Self {
+    let (sender, receiver) = mpsc::channel::<WindowMessage>();
+    Self {
+      id,
+      handle: WindowHandle { sender },
+      receiver: Some(receiver),
+      text_layout,
+      gamma: DEFAULT_GAMMA,
+      focus: Arc::new(Mutex::new(None)),
+      size: Arc::new(Mutex::new((1280.0, 480.0)))
+    }
+  }
+
+  /// 获取窗口句柄，可以在任意线程中使用
+  pub fn handle(&self) -> WindowHandle {
+    self.handle.clone()
   }
+}
 
-  pub fn raster(&mut self, layout_tree: &LayoutBox) {
-    let mut display_list = self.display_commands.lock().unwrap();
-    *display_list = get_display_list(layout_tree);
+/// 计算粘性定位的盒子在当前滚动距离下需要竖直平移的距离
+///
+/// 盒子顶部越过`视窗顶部 + top`时向下平移，底部越过`视窗底部 - bottom`时向上平移，两者都不能让盒子移出包含块
+pub fn get_sticky_offset(area: RectArea, offsets: &StickyOffsets, scroll_y: f32, viewport_height: f32) -> f32 {
+  let limit = offsets.limit;
+  let mut dy = 0.0;
+  if let Some(top) = offsets.top {
+    let max_dy = (limit.y + limit.height - area.y - area.height).max(0.0);
+    dy = (scroll_y + top - area.y).clamp(0.0, max_dy);
   }
+  if let (Some(bottom), true) = (offsets.bottom, dy == 0.0) {
+    let min_dy = (limit.y - area.y).min(0.0);
+    dy = (scroll_y + viewport_height - bottom - area.y - area.height).clamp(min_dy, 0.0);
+  }
+  dy
 }
 
+/// 将滚动距离限制在`[0, 内容高度 - 视窗高度]`之间，内容不足一屏时不能滚动
+fn clamp_scroll(scroll_y: f32, content_height: f32, viewport_height: f32) -> f32 {
+  scroll_y.min(content_height - viewport_height).max(0.0)
+}
+
+/// 层叠上下文中的一层：`z-index`及该层的绘制命令
+type Layer = (i32, Vec<DisplayCommand>);
+
 /// 获取布局树的`display list`（绘制命令列表）
-fn get_display_list<'a>(layout_tree: &'a LayoutBox) -> Vec<DisplayCommand> {
+///
+/// 定位盒子各自作为一层，按照`z-index`排序后与文档流内容合并（见`draw_stacking_context`）：
+/// 固定定位的盒子在布局时已追加到根节点子级的末尾，在根节点的层叠上下文中排序；
+/// 粘性定位的盒子在最近的层叠上下文（根节点、定位盒子或带有`filter`的盒子）中排序
+pub fn get_display_list<'a>(layout_tree: &'a LayoutBox) -> Vec<DisplayCommand> {
+  let fixed_layers: Vec<Layer> = layout_tree.children
+    .iter()
+    .filter(|child| child.is_fixed())
+    .filter_map(|fixed_box| {
+      let mut commands = Vec::new();
+      draw_filtered_context(fixed_box, Vec::new(), &mut commands);
+      (!commands.is_empty()).then(|| (fixed_box.get_z_index(), vec![DisplayCommand::Fixed(commands)]))
+    })
+    .collect();
   let mut display_list: Vec<DisplayCommand> = vec!();
-  get_display_command(layout_tree, &mut display_list);
+  draw_filtered_context(layout_tree, fixed_layers, &mut display_list);
   display_list
 }
 
-/// 获取单个布局结点的`display list`
-fn get_display_command<'a, 'b>(layout_box: &'a LayoutBox, display_list: &'b mut Vec<DisplayCommand>) {
-  draw_border(layout_box, display_list);
-  draw_background(layout_box, display_list);
-  draw_content(layout_box, display_list);
+/// 计算文本盒子中第`char_idx`个字符前的光标区域，超出文本长度时位于文本末尾
+pub fn get_caret_area(layout_box: &LayoutBox, char_idx: usize) -> RectArea {
+  let content = layout_box.box_model.content;
+  let glyphs = layout_box.glyphs.lock().unwrap();
+  let byte_offset = match &layout_box.box_type {
+    BoxType::AnonymousInline(text, _) => text.char_indices().nth(char_idx).map(|(offset, _)| offset),
+    _ => None
+  };
+  // 光标位于对应字符的左侧，第一个字符之前直接取内容区域的左边缘
+  let x = match byte_offset {
+    Some(0) => 0.0,
+    Some(offset) => glyphs
+      .iter()
+      .find(|glyph| glyph.byte_offset >= offset)
+      .map(|glyph| glyph.x)
+      .unwrap_or(content.width),
+    None => content.width
+  };
+  RectArea {
+    x: content.x + x,
+    y: content.y,
+    width: CARET_WIDTH,
+    height: content.height
+  }
+}
+
+/// 获取可编辑元素（带有`contenteditable`属性）的光标，光标位于第一个可编辑元素内最后一段文本的末尾
+fn get_caret(layout_tree: &LayoutBox) -> Option<DisplayCommand> {
+  let editable = find_editable_box(layout_tree)?;
+  let text_box = find_last_text_box(editable)?;
+  let color = get_color(text_box, "color").unwrap_or(DEFAULT_FONT_COLOR);
+  Some(DisplayCommand::Rectangle(color, get_caret_area(text_box, usize::MAX)))
+}
+
+/// 深度优先查找第一个可编辑元素的布局结点
+fn find_editable_box<'a, 'b>(layout_box: &'b LayoutBox<'a>) -> Option<&'b LayoutBox<'a>> {
+  if let BoxType::Block(style_node) | BoxType::Inline(style_node) | BoxType::InlineBlock(style_node) | BoxType::Flex(style_node) | BoxType::Grid(style_node) = &layout_box.box_type {
+    if let NodeType::Element(elem) = &style_node.node.node_type {
+      if elem.attrs.contains_key("contenteditable") {
+        return Some(layout_box);
+      }
+    }
+  }
+  layout_box.children
+    .iter()
+    .find_map(find_editable_box)
+}
+
+/// 查找布局结点内最后一个文本盒子
+fn find_last_text_box<'a, 'b>(layout_box: &'b LayoutBox<'a>) -> Option<&'b LayoutBox<'a>> {
+  if let BoxType::AnonymousInline(..) = layout_box.box_type {
+    return Some(layout_box);
+  }
+  layout_box.children
+    .iter()
+    .rev()
+    .find_map(find_last_text_box)
+}
+
+/// 获取布局树中所有可聚焦的元素，按焦点序号（即文档顺序）排列；`display: none`的元素没有盒子，不会被聚焦
+///
+/// 行内元素在布局时已经被拆分到各个line box中，因此按照盒子所属的可聚焦元素合并区域
+pub fn get_focusables(layout_tree: &LayoutBox) -> Vec<Focusable> {
+  let root = match &layout_tree.box_type {
+    BoxType::Block(style_node) | BoxType::Flex(style_node) | BoxType::Grid(style_node) => style_node.node,
+    _ => return vec![]
+  };
+  let elements = root.focusable_elements();
+  let mut focusables: Vec<Focusable> = vec![];
+  collect_focusables(layout_tree, &elements, &mut focusables);
+  focusables.sort_by_key(|focusable| focusable.index);
+  focusables
+}
+
+/// 查找盒子所属的可聚焦元素（盒子自身或最近的可聚焦祖先元素）的焦点序号
+fn get_focus_index(layout_box: &LayoutBox, elements: &[&Node]) -> Option<usize> {
+  let mut style_node = match &layout_box.box_type {
+    BoxType::Block(style_node) | BoxType::InlineBlock(style_node) | BoxType::Flex(style_node) | BoxType::Grid(style_node) | BoxType::AnonymousInline(_, style_node) => Some(style_node.clone()),
+    _ => None
+  };
+  while let Some(node) = style_node {
+    if let Some(index) = elements.iter().position(|element| std::ptr::eq(*element, node.node)) {
+      return Some(index);
+    }
+    style_node = node.parent.as_ref().and_then(|parent| parent.upgrade());
+  }
+  None
+}
+
+fn collect_focusables(layout_box: &LayoutBox, elements: &[&Node], focusables: &mut Vec<Focusable>) {
+  if let Some(index) = get_focus_index(layout_box, elements) {
+    let area = layout_box.box_model.border_box();
+    match focusables.iter_mut().find(|focusable| focusable.index == index) {
+      Some(focusable) => focusable.area = focusable.area.union(area),
+      None => focusables.push(Focusable { index, area })
+    }
+  }
   for child in &layout_box.children {
-    get_display_command(child, display_list);
+    collect_focusables(child, elements, focusables);
+  }
+}
+
+/// 计算按下`Tab`后获得焦点的元素序号，`backward`为真时（`Shift + Tab`）反向查找；到达末尾后回到开头
+pub fn next_focus(focusables: &[Focusable], current: Option<usize>, backward: bool) -> Option<usize> {
+  let mut indexes = focusables.iter().map(|focusable| focusable.index);
+  match (current, backward) {
+    (None, false) => indexes.next(),
+    (None, true) => indexes.next_back(),
+    (Some(current), false) => indexes.clone().find(|index| *index > current).or_else(|| indexes.next()),
+    (Some(current), true) => indexes.clone().rfind(|index| *index < current).or_else(|| indexes.next_back())
+  }
+}
+
+/// 获取焦点轮廓的绘制命令，轮廓位于区域外侧
+fn get_focus_outline(area: RectArea) -> Vec<DisplayCommand> {
+  let width = FOCUS_OUTLINE_WIDTH;
+  let outer = RectArea {
+    x: area.x - width,
+    y: area.y - width,
+    width: area.width + width * 2.0,
+    height: area.height + width * 2.0
+  };
+  [
+    RectArea { height: width, ..outer },
+    RectArea { y: outer.y + outer.height - width, height: width, ..outer },
+    RectArea { width, ..outer },
+    RectArea { x: outer.x + outer.width - width, width, ..outer }
+  ]
+    .into_iter()
+    .map(|rect| DisplayCommand::Rectangle(FOCUS_OUTLINE_COLOR, rect))
+    .collect()
+}
+
+/// 获取单个布局结点的`display list`，粘性定位的盒子不直接绘制，而是作为一层放入`layers`
+///
+/// 绘制顺序依次为背景、边框、文本，子级在父级之后绘制；半透明的内容依赖这个顺序与下层内容混合
+///
+/// 不可见的盒子自身不绘制，但可见的子级仍然需要绘制
+///
+/// 带有`filter`的盒子，其自身及子孙的命令会合并为一个`DisplayCommand::Filter`
+///
+/// 粘性定位的盒子，其自身及子孙的命令会合并为一个`DisplayCommand::Sticky`
+fn get_display_command<'a, 'b>(layout_box: &'a LayoutBox, display_list: &'b mut Vec<DisplayCommand>, layers: &mut Vec<Layer>) {
+  if let Some(offsets) = layout_box.sticky {
+    let mut commands = Vec::new();
+    draw_filtered_context(layout_box, Vec::new(), &mut commands);
+    if !commands.is_empty() {
+      layers.push((layout_box.get_z_index(), vec![DisplayCommand::Sticky(layout_box.box_model.border_box(), offsets, commands)]));
+    }
+  } else if !get_filters(layout_box).is_empty() {
+    draw_filtered_context(layout_box, Vec::new(), display_list);
+  } else {
+    draw_box_background(layout_box, display_list);
+    draw_box_content(layout_box, display_list, layers);
+  }
+}
+
+/// 绘制层叠上下文，带有`filter`时合并为一个`DisplayCommand::Filter`
+fn draw_filtered_context(layout_box: &LayoutBox, layers: Vec<Layer>, display_list: &mut Vec<DisplayCommand>) {
+  let filters = get_filters(layout_box);
+  if !filters.is_empty() {
+    let mut commands = Vec::new();
+    draw_stacking_context(layout_box, layers, &mut commands);
+    if !commands.is_empty() {
+      display_list.push(DisplayCommand::Filter(filters, commands));
+    }
+  } else {
+    draw_stacking_context(layout_box, layers, display_list);
+  }
+}
+
+/// 绘制以`layout_box`为根的层叠上下文，文档流中的粘性定位盒子与`layers`（排在其后）一起按照`z-index`排序：
+/// 根盒子的背景和边框最先绘制，其次是`z-index`为负数的层，然后是文档流内容，最后是`z-index`为`auto`/0及正数的层；
+/// `z-index`相同的层按文档顺序绘制
+fn draw_stacking_context(layout_box: &LayoutBox, layers: Vec<Layer>, display_list: &mut Vec<DisplayCommand>) {
+  let mut content = Vec::new();
+  let mut below = Vec::new();
+  draw_box_content(layout_box, &mut content, &mut below);
+  below.extend(layers);
+  below.sort_by_key(|(z_index, _)| *z_index); // 稳定排序，保留文档顺序
+  let split = below.partition_point(|(z_index, _)| *z_index < 0);
+  let above = below.split_off(split);
+  draw_box_background(layout_box, display_list);
+  display_list.extend(below.into_iter().flat_map(|(_, commands)| commands));
+  display_list.extend(content);
+  display_list.extend(above.into_iter().flat_map(|(_, commands)| commands));
+}
+
+/// 绘制盒子自身的背景和边框
+fn draw_box_background(layout_box: &LayoutBox, display_list: &mut Vec<DisplayCommand>) {
+  if is_visible(layout_box) {
+    draw_background(layout_box, display_list);
+    draw_border(layout_box, display_list);
+  }
+}
+
+/// 绘制盒子自身的文本及子级，固定定位的盒子不在这里绘制，粘性定位的盒子放入`layers`
+///
+/// `overflow: hidden`的盒子，其内容及子孙裁剪到自身的`padding-box`；子孙中的层脱离了这里的绘制顺序，各自带上裁剪区域
+fn draw_box_content(layout_box: &LayoutBox, display_list: &mut Vec<DisplayCommand>, layers: &mut Vec<Layer>) {
+  let is_clipped = is_overflow_hidden(layout_box);
+  let clip = layout_box.box_model.padding_box();
+  if is_clipped {
+    display_list.push(DisplayCommand::PushClip(clip));
+  }
+  if is_visible(layout_box) {
+    draw_content(layout_box, display_list);
+  }
+  let mut child_layers = Vec::new();
+  for child in layout_box.children.iter().filter(|child| !child.is_fixed()) {
+    get_display_command(child, display_list, &mut child_layers);
+  }
+  if is_clipped {
+    display_list.push(DisplayCommand::PopClip);
+    for (_, commands) in &mut child_layers {
+      commands.insert(0, DisplayCommand::PushClip(clip));
+      commands.push(DisplayCommand::PopClip);
+    }
+  }
+  layers.extend(child_layers);
+}
+
+/// 布局结点是否裁剪溢出的内容（`overflow: hidden`），只有元素产生的盒子才会裁剪
+fn is_overflow_hidden(layout_box: &LayoutBox) -> bool {
+  match &layout_box.box_type {
+    BoxType::Block(style_node) | BoxType::InlineBlock(style_node) | BoxType::Flex(style_node) | BoxType::Grid(style_node) => style_node.get_overflow() == Overflow::Hidden,
+    _ => false
+  }
+}
+
+/// 布局结点是否可见（`visibility`），没有样式的结点视为可见
+fn is_visible(layout_box: &LayoutBox) -> bool {
+  match &layout_box.box_type {
+    BoxType::Block(style_node) | BoxType::Inline(style_node) | BoxType::InlineBlock(style_node) | BoxType::Flex(style_node) | BoxType::Grid(style_node) | BoxType::AnonymousInline(_, style_node) => style_node.is_visible(),
+    _ => true
+  }
+}
+
+/// 获取布局结点的`filter`滤镜列表，匿名盒子没有自己的滤镜
+fn get_filters(layout_box: &LayoutBox) -> Vec<Filter> {
+  match &layout_box.box_type {
+    BoxType::Block(style_node) | BoxType::Inline(style_node) | BoxType::InlineBlock(style_node) | BoxType::Flex(style_node) | BoxType::Grid(style_node) => style_node.get_filters(),
+    _ => Vec::new()
   }
 }
 
 /// 获取布局结点的某个样式颜色
+///
+/// `currentColor`取同一结点的`color`；`color`本身为`currentColor`时无法解析，视为黑色
 fn get_color(layout_box: &LayoutBox, color_name: &str) -> Option<CSSColor> {
-  if let BoxType::Block(style_node) | BoxType::Inline(style_node) | BoxType::AnonymousInline(_, style_node) = &layout_box.box_type {
-    if let Some(CSSValue::Color(color)) = style_node.get_val(color_name) {
-      Some(color)
-    } else {
-      None
+  if let BoxType::Block(style_node) | BoxType::Inline(style_node) | BoxType::InlineBlock(style_node) | BoxType::Flex(style_node) | BoxType::Grid(style_node) | BoxType::AnonymousInline(_, style_node) = &layout_box.box_type {
+    match style_node.get_val(color_name) {
+      Some(CSSValue::Color(color)) => Some(color),
+      Some(CSSValue::Keyword(keyword)) if keyword == "currentColor" => match (color_name, style_node.get_val("color")) {
+        ("color", _) => Some(DEFAULT_FONT_COLOR),
+        (_, Some(CSSValue::Color(color))) => Some(color),
+        _ => Some(DEFAULT_FONT_COLOR)
+      },
+      _ => None
     }
   } else {
     None
@@ -204,7 +847,8 @@ fn draw_border(layout_box: &LayoutBox, display_list: &mut Vec<DisplayCommand>) {
   let mut draw_one_border = |name: &str, rect: RectArea| {
     let color = get_color(layout_box, name)
       .unwrap_or(get_color(layout_box, "border-color").unwrap_or(TRANSPARENT.clone()));
-    if color != TRANSPARENT {
+    // 完全透明或宽度为0的边框无需绘制
+    if color.a > 0 && rect.width > 0.0 && rect.height > 0.0 {
       display_list.push(DisplayCommand::Rectangle(color, rect))
     }
   };
@@ -236,42 +880,720 @@ fn draw_border(layout_box: &LayoutBox, display_list: &mut Vec<DisplayCommand>) {
   });
 }
 
+/// 获取布局结点的`border-radius`像素值
+///
+/// `em`相对于元素字号，百分比相对于`border-box`的短边；半径最大为短边的一半
+fn get_border_radius(layout_box: &LayoutBox) -> f32 {
+  if let BoxType::Block(style_node) | BoxType::Inline(style_node) | BoxType::InlineBlock(style_node) | BoxType::Flex(style_node) | BoxType::Grid(style_node) = &layout_box.box_type {
+    let border_box = layout_box.box_model.border_box();
+    let short_side = border_box.width.min(border_box.height);
+    style_node.get_val("border-radius")
+      .map(|radius| radius.to_px_with(style_node.get_font_size(), short_side))
+      .unwrap_or(0.0)
+      .min(short_side / 2.0)
+      .max(0.0)
+  } else {
+    0.0
+  }
+}
+
 /// 绘制元素背景区域（目前是`padding-box`区域）
 fn draw_background(layout_box: &LayoutBox, display_list: &mut Vec<DisplayCommand>) {
-  if let Some(color) = get_color(layout_box, "background-color") {
-    display_list.push(DisplayCommand::Rectangle(color, layout_box.box_model.padding_box()))
+  // 完全透明的背景无需绘制
+  if let Some(color) = get_color(layout_box, "background-color").filter(|color| color.a > 0) {
+    // 背景位于边框内侧，因此圆角半径需要减去边框宽度
+    let radius = get_border_radius(layout_box) - layout_box.box_model.border.left;
+    if radius > 0.0 {
+      display_list.push(DisplayCommand::RoundedRectangle(color, layout_box.box_model.padding_box(), radius))
+    } else {
+      display_list.push(DisplayCommand::Rectangle(color, layout_box.box_model.padding_box()))
+    }
   }
 }
 
 /// 绘制纯文本内容
+///
+/// 文本之后绘制`text-decoration`：下划线位于基线下方一两个像素处，删除线位于文本中部，均与文本同色、横跨文本宽度；
+/// 线宽随字号缩放（16px的文本为1px）
 fn draw_content<'a, 'b>(layout_box: &'a LayoutBox, display_list: &'b mut Vec<DisplayCommand>) {
-  match layout_box.box_type {
-    BoxType::AnonymousInline(..) => {
+  match &layout_box.box_type {
+    // 没有字符（如空白文本）时无需绘制，也不绘制装饰线
+    BoxType::AnonymousInline(_, style_node) if !layout_box.glyphs.lock().unwrap().is_empty() => {
       let color = get_color(layout_box, "color").unwrap_or(DEFAULT_FONT_COLOR);
+      let content = layout_box.box_model.content;
       display_list.push(DisplayCommand::Text(TextRenderInfo {
         color,
-        area: layout_box.box_model.content,
+        area: content,
+        baseline: layout_box.baseline,
         glyphs: layout_box.glyphs.clone()
-      }))
+      }));
+      let decoration = style_node.get_text_decoration();
+      let thickness = (style_node.get_font_size() * TEXT_DECORATION_RATIO).round().max(1.0);
+      // y为线条中心的位置
+      let mut draw_line = |y: f32| display_list.push(DisplayCommand::Rectangle(color, RectArea {
+        x: content.x,
+        y: (y - thickness / 2.0).round(),
+        width: content.width,
+        height: thickness
+      }));
+      if decoration.underline {
+        // 与基线之间留出与线宽相当的间隙，避免压住字形
+        draw_line(content.y + layout_box.baseline + thickness * 1.5 + 0.5);
+      }
+      if decoration.line_through {
+        draw_line(content.y + content.height / 2.0);
+      }
     },
     _ => {}
   }
 }
 
+/// 将颜色按照`alpha`（0~255）覆盖（`source-over`）到画布上已有的像素，超出画布的像素直接忽略
+///
+/// 画布本身可以是半透明的（滤镜的中间图像）
+fn blend_pixel(img: &mut RgbaImage, x: i64, y: i64, color: CSSColor, alpha: u8) {
+  if x < 0 || y < 0 || x >= img.width() as i64 || y >= img.height() as i64 || alpha == 0 {
+    return;
+  }
+  let src_alpha = alpha as f32 / 255.0;
+  let pixel = img.get_pixel_mut(x as u32, y as u32);
+  let dst_alpha = pixel[3] as f32 / 255.0 * (1.0 - src_alpha);
+  let out_alpha = src_alpha + dst_alpha;
+  for idx in 0..3 {
+    pixel[idx] = ((color[idx] as f32 * src_alpha + pixel[idx] as f32 * dst_alpha) / out_alpha).round() as u8;
+  }
+  pixel[3] = (out_alpha * 255.0).round() as u8;
+}
+
+/// 对中间图像应用滤镜：颜色类滤镜逐像素处理，`blur`按半径（标准差）做高斯模糊
+fn apply_filters(mut layer: RgbaImage, filters: &[Filter]) -> RgbaImage {
+  for filter in filters {
+    match filter {
+      Filter::Blur(radius) => {
+        if *radius <= 0.0 {
+          continue;
+        }
+        // 先预乘alpha再模糊，避免透明区域的黑色渗入边缘
+        for pixel in layer.pixels_mut() {
+          for idx in 0..3 {
+            pixel[idx] = (pixel[idx] as u32 * pixel[3] as u32 / 255) as u8;
+          }
+        }
+        layer = image::imageops::blur(&layer, *radius);
+        for pixel in layer.pixels_mut() {
+          if pixel[3] > 0 {
+            for idx in 0..3 {
+              pixel[idx] = (pixel[idx] as u32 * 255 / pixel[3] as u32).min(255) as u8;
+            }
+          }
+        }
+      },
+      _ => {
+        for pixel in layer.pixels_mut() {
+          let color = filter.apply(CSSColor { r: pixel[0], g: pixel[1], b: pixel[2], a: pixel[3] });
+          *pixel = Rgba(color.to_vec());
+        }
+      }
+    }
+  }
+  layer
+}
+
+/// 在画布上填充矩形区域，`radius`大于0时四角为圆角；以像素中心是否落在区域内判断是否填充，`clip`之外的像素不填充
+fn fill_rect(img: &mut RgbaImage, color: CSSColor, rect: RectArea, radius: f32, clip: Option<&RectArea>) {
+  let (left, top) = (rect.x, rect.y);
+  let (right, bottom) = (rect.x + rect.width, rect.y + rect.height);
+  for y in top.round() as i64..bottom.round() as i64 {
+    for x in left.round() as i64..right.round() as i64 {
+      let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+      // 位于圆角区域内的像素需要判断是否在圆弧内
+      let cx = px.clamp(left + radius, right - radius);
+      let cy = py.clamp(top + radius, bottom - radius);
+      if radius > 0.0 && (px - cx).powi(2) + (py - cy).powi(2) > radius * radius {
+        continue;
+      }
+      if clip.is_some_and(|clip| !clip.contains_pixel(x, y)) {
+        continue;
+      }
+      blend_pixel(img, x, y, color, color.a);
+    }
+  }
+}
+
+/// 在画布上绘制文本，字形的覆盖率经过gamma校正后作为alpha与底色混合，`clip`之外的像素不绘制
+fn fill_text(img: &mut RgbaImage, info: &TextRenderInfo, clip: Option<&RectArea>, text_layout: &TextLayout, gamma: f32) {
+  let glyphs = info.glyphs.lock().unwrap();
+  for glyph in &*glyphs {
+    if glyph.width == 0 || glyph.height == 0 {
+      continue;
+    }
+    let (metrics, bitmap) = text_layout.fonts[glyph.font_index].rasterize_config(glyph.key);
+    let x = (info.area.x + glyph.x) as i64;
+    let y = (info.area.y + info.get_glyph_top(&metrics)) as i64;
+    for (idx, mask) in bitmap.iter().enumerate() {
+      let dx = (idx % glyph.width) as i64;
+      let dy = (idx / glyph.width) as i64;
+      if clip.is_some_and(|clip| !clip.contains_pixel(x + dx, y + dy)) {
+        continue;
+      }
+      let alpha = (apply_gamma(*mask, gamma) as u32 * info.color.a as u32 / 255) as u8;
+      blend_pixel(img, x + dx, y + dy, info.color, alpha);
+    }
+  }
+}
+
+/// 在画布上依次执行绘制命令
+///
+/// 滤镜命令先绘制到同样大小的透明中间图像，应用滤镜后再覆盖到画布上；`clips`为当前的裁剪区域栈，栈顶已经与外层区域求过交集
+fn paint_commands(img: &mut RgbaImage, display_list: &[DisplayCommand], clips: &mut Vec<RectArea>, text_layout: &TextLayout, gamma: f32) {
+  for command in display_list {
+    match command {
+      DisplayCommand::Rectangle(color, rect) => fill_rect(img, *color, *rect, 0.0, clips.last()),
+      DisplayCommand::RoundedRectangle(color, rect, radius) => fill_rect(img, *color, *rect, *radius, clips.last()),
+      DisplayCommand::Text(info) => fill_text(img, info, clips.last(), text_layout, gamma),
+      DisplayCommand::PushClip(rect) => clips.push(clips.last().map_or(*rect, |clip| clip.intersect(*rect))),
+      DisplayCommand::PopClip => {
+        clips.pop();
+      },
+      DisplayCommand::Fixed(commands) => paint_commands(img, commands, clips, text_layout, gamma),
+      // 图片不能滚动，按照滚动距离为0、视窗即整张图片处理
+      DisplayCommand::Sticky(area, offsets, commands) => {
+        let dy = get_sticky_offset(*area, offsets, 0.0, img.height() as f32);
+        let commands: Vec<DisplayCommand> = commands.iter().map(|command| command.with_offset(dy)).collect();
+        paint_commands(img, &commands, clips, text_layout, gamma);
+      },
+      DisplayCommand::Filter(filters, commands) => {
+        let mut layer = RgbaImage::new(img.width(), img.height());
+        paint_commands(&mut layer, commands, clips, text_layout, gamma);
+        let layer = apply_filters(layer, filters);
+        for (x, y, pixel) in layer.enumerate_pixels() {
+          let color = CSSColor { r: pixel[0], g: pixel[1], b: pixel[2], a: pixel[3] };
+          blend_pixel(img, x as i64, y as i64, color, pixel[3]);
+        }
+      }
+    }
+  }
+}
+
+/// 在白色背景的画布上依次执行绘制命令，不依赖窗口
+///
+/// 几何与窗口中的绘制一致（不考虑dpr），文本按字形覆盖率与底色混合（同`example/font`中的`get_font_color`）
+pub fn paint_display_list(display_list: &[DisplayCommand], width: u32, height: u32) -> RgbaImage {
+  // 字体是固定内置的，新建的字体布局与生成绘制命令时的字体序号一致
+  paint_display_list_with(display_list, width, height, &TextLayout::default(), DEFAULT_GAMMA)
+}
+
+/// 同`paint_display_list`，使用已有的字体布局及指定的gamma
+fn paint_display_list_with(display_list: &[DisplayCommand], width: u32, height: u32, text_layout: &TextLayout, gamma: f32) -> RgbaImage {
+  let mut img = RgbaImage::from_pixel(width, height, Rgba([255, 255, 255, 255]));
+  paint_commands(&mut img, display_list, &mut Vec::new(), text_layout, gamma);
+  img
+}
+
+/// SVG中的颜色，半透明时另外输出`opacity`属性（如`fill-opacity`）
+fn svg_color(color: CSSColor, opacity_attr: &str) -> String {
+  let hex = format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b);
+  if color.a == 255 {
+    format!("\"{hex}\"")
+  } else {
+    format!("\"{hex}\" {opacity_attr}=\"{}\"", color.a as f32 / 255.0)
+  }
+}
+
+/// 转义SVG文本中的特殊字符
+fn svg_escape(text: &str) -> String {
+  text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// 将绘制命令逐行写入SVG，`next_id`用于生成裁剪区域及滤镜的id；`height`为视窗高度，用于计算粘性定位的偏移
+fn write_svg_commands(svg: &mut Vec<String>, commands: &[DisplayCommand], height: f32, next_id: &mut usize) {
+  let rect = |rect: &RectArea| format!("x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"", rect.x, rect.y, rect.width, rect.height);
+  for command in commands {
+    match command {
+      DisplayCommand::Rectangle(color, area) => svg.push(format!("<rect {} fill={}/>", rect(area), svg_color(*color, "fill-opacity"))),
+      DisplayCommand::RoundedRectangle(color, area, radius) => {
+        svg.push(format!("<rect {} rx=\"{radius}\" fill={}/>", rect(area), svg_color(*color, "fill-opacity")))
+      },
+      DisplayCommand::Text(info) => {
+        // 每个字符单独定位，与光栅化的结果一致；空白字符不可见，直接跳过
+        let glyphs = info.glyphs.lock().unwrap();
+        let glyphs: Vec<&GlyphPosition> = glyphs.iter().filter(|glyph| !glyph.parent.is_whitespace()).collect();
+        let Some(first) = glyphs.first() else {
+          continue;
+        };
+        let xs: Vec<String> = glyphs.iter().map(|glyph| (info.area.x + glyph.x).to_string()).collect();
+        let text: String = glyphs.iter().map(|glyph| glyph.parent).collect();
+        svg.push(format!(
+          "<text x=\"{}\" y=\"{}\" font-size=\"{}\" fill={}>{}</text>",
+          xs.join(" "),
+          info.area.y + info.baseline,
+          first.key.px,
+          svg_color(info.color, "fill-opacity"),
+          svg_escape(&text)
+        ));
+      },
+      DisplayCommand::PushClip(area) => {
+        *next_id += 1;
+        svg.push(format!("<clipPath id=\"clip{next_id}\"><rect {}/></clipPath>", rect(area)));
+        svg.push(format!("<g clip-path=\"url(#clip{next_id})\">"));
+      },
+      DisplayCommand::PopClip => svg.push(String::from("</g>")),
+      DisplayCommand::Fixed(commands) => write_svg_commands(svg, commands, height, next_id),
+      // 同光栅化为图片，按照滚动距离为0处理
+      DisplayCommand::Sticky(area, offsets, commands) => {
+        let dy = get_sticky_offset(*area, offsets, 0.0, height);
+        let commands: Vec<DisplayCommand> = commands.iter().map(|command| command.with_offset(dy)).collect();
+        write_svg_commands(svg, &commands, height, next_id);
+      },
+      // 颜色滤镜直接作用于命令的颜色，模糊使用SVG的高斯模糊滤镜
+      DisplayCommand::Filter(filters, commands) => {
+        let commands: Vec<DisplayCommand> = commands.iter().map(|command| command.with_filters(filters)).collect();
+        let blur: f32 = filters.iter().map(|filter| match filter {
+          Filter::Blur(radius) => *radius,
+          _ => 0.0
+        }).sum();
+        if blur > 0.0 {
+          *next_id += 1;
+          svg.push(format!("<filter id=\"filter{next_id}\"><feGaussianBlur stdDeviation=\"{blur}\"/></filter>"));
+          svg.push(format!("<g filter=\"url(#filter{next_id})\">"));
+          write_svg_commands(svg, &commands, height, next_id);
+          svg.push(String::from("</g>"));
+        } else {
+          write_svg_commands(svg, &commands, height, next_id);
+        }
+      }
+    }
+  }
+}
+
+/// 将绘制命令转为SVG（白色背景，每个元素占一行），不依赖窗口及字体光栅化，便于对比渲染结果
+///
+/// 色块转为`<rect>`，文本转为逐个字符定位的`<text>`，裁剪区域转为`<clipPath>`
+pub fn display_list_to_svg(commands: &[DisplayCommand], width: u32, height: u32) -> String {
+  let mut svg = vec![
+    format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" viewBox=\"0 0 {width} {height}\">"),
+    format!("<rect x=\"0\" y=\"0\" width=\"{width}\" height=\"{height}\" fill=\"#ffffff\"/>")
+  ];
+  write_svg_commands(&mut svg, commands, height as f32, &mut 0);
+  svg.push(String::from("</svg>"));
+  svg.join("\n")
+}
+
+/// 不打开窗口渲染时的选项，缺省时与直接渲染一段没有地址的`html`相同
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+  /// 页面地址，外部样式表、`@import`及链接的相对地址基于此解析（同窗口中的流水线）
+  pub base_url: Option<Url>,
+  /// 浏览记录，用于匹配`:visited`
+  pub history: History,
+  /// 文字混合使用的gamma值（同`RasterWindow::gamma`）
+  pub gamma: f32
+}
+
+impl Default for RenderOptions {
+  fn default() -> Self {
+    Self {
+      base_url: None,
+      history: History::default(),
+      gamma: DEFAULT_GAMMA
+    }
+  }
+}
+
+/// 按渲染选项解析`html`，得到还未布局的`layout tree`
+fn parse_layout_tree(html: String, options: &RenderOptions) -> Result<LayoutTree, HtmlParseError> {
+  Ok(LayoutTree {
+    style_tree: StyleTree {
+      document: html::parse_with_base(html, options.base_url.clone())?,
+      history: options.history.clone(),
+      focused: None
+    }
+  })
+}
+
+/// 解析`html`并生成整个页面的绘制命令，同时返回图片的宽高；视窗高度为0时图片高度取文档的高度
+fn get_document_display_list(html: String, viewport: layout::Box, options: &RenderOptions, text_layout: &Mutex<TextLayout>) -> Result<(Vec<DisplayCommand>, u32, u32), HtmlParseError> {
+  let layout_tree = parse_layout_tree(html, options)?;
+  let root_box = layout_tree.get_layout_tree(viewport, text_layout);
+  let width = viewport.content.width.ceil() as u32;
+  let height = if viewport.content.height > 0.0 {
+    viewport.content.height
+  } else {
+    root_box.box_model.margin_box().height
+  }.ceil() as u32;
+  Ok((get_display_list(&root_box), width.max(1), height.max(1)))
+}
+
+/// 在当前线程中依次执行解析、样式计算、布局及光栅化，得到整个页面的图片；视窗高度为0时图片高度取文档的高度
+fn render_document(html: String, viewport: layout::Box, options: &RenderOptions) -> Result<RgbaImage, HtmlParseError> {
+  let text_layout = Mutex::new(TextLayout::default());
+  let (display_list, width, height) = get_document_display_list(html, viewport, options, &text_layout)?;
+  let text_layout = text_layout.lock().unwrap();
+  Ok(paint_display_list_with(&display_list, width, height, &text_layout, options.gamma))
+}
+
+/// 不打开窗口，返回渲染得到的图片，便于嵌入或检查像素
+///
+/// 视窗大小为`width`x`height`，高度为0时图片高度取文档的高度
+pub fn render_html_to_image(html: &str, width: u32, height: u32) -> Result<RgbaImage, HtmlParseError> {
+  render_html_to_image_with(html, width, height, &RenderOptions::default())
+}
+
+/// 同`render_html_to_image`，按`options`解析页面及混合文字
+pub fn render_html_to_image_with(html: &str, width: u32, height: u32, options: &RenderOptions) -> Result<RgbaImage, HtmlParseError> {
+  let mut viewport = layout::Box::default();
+  viewport.content.width = width as f32;
+  viewport.content.height = height as f32;
+  render_document(html.to_string(), viewport, options)
+}
+
+/// 不打开窗口，直接将`html`渲染为`png`图片保存到`path`；视窗高度为0时图片高度取文档的高度
+pub fn render_to_png(html: String, viewport: layout::Box, path: &Path) -> Result<(), Box<dyn Error>> {
+  render_to_png_with(html, viewport, path, &RenderOptions::default())
+}
+
+/// 同`render_to_png`，按`options`解析页面及混合文字
+pub fn render_to_png_with(html: String, viewport: layout::Box, path: &Path, options: &RenderOptions) -> Result<(), Box<dyn Error>> {
+  render_document(html, viewport, options)?.save(path)?;
+  Ok(())
+}
+
+/// 不打开窗口，将`html`渲染为SVG（见`display_list_to_svg`）
+pub fn render_to_svg(html: String, viewport: layout::Box) -> Result<String, HtmlParseError> {
+  render_to_svg_with(html, viewport, &RenderOptions::default())
+}
+
+/// 同`render_to_svg`，按`options`解析页面
+pub fn render_to_svg_with(html: String, viewport: layout::Box, options: &RenderOptions) -> Result<String, HtmlParseError> {
+  let text_layout = Mutex::new(TextLayout::default());
+  let (display_list, width, height) = get_document_display_list(html, viewport, options, &text_layout)?;
+  Ok(display_list_to_svg(&display_list, width, height))
+}
+
+/// 不打开窗口，只渲染第一个命中`selector`的元素及其子孙（如组件测试），没有命中时返回`Ok(None)`
+///
+/// 元素以`containing_block`为包含块布局，图片与包含块的`content-box`一样大；包含块高度为0时图片高度取元素的高度
+pub fn render_subtree(html: String, selector: &str, containing_block: layout::Box) -> Result<Option<RgbaImage>, HtmlParseError> {
+  render_subtree_with(html, selector, containing_block, &RenderOptions::default())
+}
+
+/// 同`render_subtree`，按`options`解析页面及混合文字
+pub fn render_subtree_with(html: String, selector: &str, containing_block: layout::Box, options: &RenderOptions) -> Result<Option<RgbaImage>, HtmlParseError> {
+  let layout_tree = parse_layout_tree(html, options)?;
+  let text_layout = Mutex::new(TextLayout::default());
+  let Some(subtree) = layout_tree.get_subtree_layout(selector, containing_block, &text_layout) else {
+    return Ok(None);
+  };
+  let display_list = get_display_list(&subtree.layout_box);
+  let area = containing_block.content;
+  let width = area.width.ceil() as u32;
+  let height = if area.height > 0.0 {
+    area.height
+  } else {
+    subtree.layout_box.box_model.margin_box().height
+  }.ceil() as u32;
+  // 图片顶部对应包含块的顶部
+  let display_list: Vec<DisplayCommand> = display_list
+    .iter()
+    .map(|command| command.with_offset(-area.y))
+    .collect();
+  let text_layout = text_layout.lock().unwrap();
+  Ok(Some(paint_display_list_with(&display_list, width.max(1), height.max(1), &text_layout, options.gamma)))
+}
+
 /// 启动一个窗口，需要注意的是event::run方法**必须要在主线程**执行（因为`event loop`的限制）
 /// 
-/// 启动窗口后该方法会**阻塞主线程**！
+/// 启动窗口后该方法会**阻塞主线程**！因此应当先通过`RasterWindow::handle`取得窗口句柄交给其它线程，
+/// 再在主线程调用该方法；之后的绘制命令、标题修改都通过句柄发送
 pub fn start_window(window_store: Arc<Mutex<RasterWindow>>) -> GameResult {
-  let window = window_store.lock().unwrap();
-  let cb = ggez::ContextBuilder::new(window.id.as_str(), "xxf");
+  let mut window = window_store.lock().unwrap();
+  let receiver = window.receiver.take().expect("窗口已经启动过了");
+  let cb = ggez::ContextBuilder::new(window.id.as_str(), "xxf")
+    .window_mode(ggez::conf::WindowMode::default().resizable(true));
   let (mut ctx, event_loop) = cb.build().unwrap();
   let dpr = ctx.gfx.window().scale_factor() as f32;
   let state = WindowState {
-    display_commands: window.display_commands.clone(),
-    dpr
+    receiver,
+    display_commands: Vec::new(),
+    caret: None,
+    focusables: Vec::new(),
+    focus: window.focus.clone(),
+    text_layout: window.text_layout.clone(),
+    dpr,
+    gamma: window.gamma,
+    viewport_height: window.size.lock().unwrap().1,
+    content_height: 0.0,
+    size: window.size.clone(),
+    scroll_y: 0.0
   };
   ctx.gfx.set_window_title(window.id.as_str());
-  ctx.gfx.set_drawable_size(1280.0 * dpr, 480.0 * dpr).unwrap();
+  let (width, height) = *window.size.lock().unwrap();
+  ctx.gfx.set_drawable_size(width * dpr, height * dpr).unwrap();
   drop(window);
   event::run(ctx, event_loop, state)
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const RED: CSSColor = CSSColor { r: 255, g: 0, b: 0, a: 255 };
+
+  fn get_layout_tree(source: &str) -> LayoutTree {
+    LayoutTree {
+      style_tree: StyleTree { document: html::parse(source.to_string()).unwrap(), history: Default::default(), focused: None }
+    }
+  }
+
+  fn get_viewport(width: f32, height: f32) -> layout::Box {
+    let mut viewport = layout::Box::default();
+    viewport.content.width = width;
+    viewport.content.height = height;
+    viewport
+  }
+
+  /// 在`width`x`height`的视窗中布局`source`，返回绘制命令列表
+  fn get_commands(source: &str, width: f32, height: f32) -> Vec<DisplayCommand> {
+    let text_layout = Mutex::new(TextLayout::default());
+    get_document_display_list(source.to_string(), get_viewport(width, height), &RenderOptions::default(), &text_layout).unwrap().0
+  }
+
+  /// 未启动窗口的窗口状态，以及向它发送消息的句柄
+  fn get_window_state(viewport_height: f32) -> (WindowHandle, WindowState) {
+    let window = RasterWindow::new(String::from("test"), Arc::new(Mutex::new(TextLayout::default())));
+    let handle = window.handle();
+    let state = WindowState {
+      receiver: window.receiver.unwrap(),
+      display_commands: Vec::new(),
+      caret: None,
+      focusables: Vec::new(),
+      focus: window.focus,
+      text_layout: window.text_layout,
+      dpr: 1.0,
+      gamma: window.gamma,
+      viewport_height,
+      content_height: 0.0,
+      size: window.size,
+      scroll_y: 0.0
+    };
+    (handle, state)
+  }
+
+  #[test]
+  fn percent_border_radius_makes_circle() {
+    let commands = get_commands(r#"<div style="width: 100px; height: 100px; border-radius: 50%; background-color: #ff0000"></div>"#, 200.0, 200.0);
+    assert!(matches!(commands[..], [DisplayCommand::RoundedRectangle(RED, _, radius)] if radius == 50.0));
+  }
+
+  #[test]
+  fn window_handle_updates_window_state() {
+    let (handle, mut state) = get_window_state(100.0);
+    let area = RectArea { x: 0.0, y: 0.0, width: 10.0, height: 10.0 };
+    handle.send_display_list(vec![DisplayCommand::Rectangle(RED, area)]).unwrap();
+    handle.set_title(String::from("page")).unwrap();
+    assert_eq!(state.receive_messages(), Some(String::from("page")));
+    assert!(matches!(state.display_commands[..], [DisplayCommand::Rectangle(RED, _)]));
+  }
+
+  #[test]
+  fn caret_follows_glyph_positions() {
+    let layout_tree = get_layout_tree("<p>hello</p>");
+    let root = layout_tree.get_layout_tree(get_viewport(400.0, 300.0), &Mutex::new(TextLayout::default()));
+    let text_box = find_last_text_box(&root).unwrap();
+    let content = text_box.box_model.content;
+    let glyph_x = text_box.glyphs.lock().unwrap()[2].x;
+    assert_eq!(get_caret_area(text_box, 0).x, content.x);
+    assert_eq!(get_caret_area(text_box, 2).x, content.x + glyph_x);
+    assert_eq!(get_caret_area(text_box, usize::MAX).x, content.x + content.width);
+    assert_eq!(get_caret_area(text_box, 2).height, content.height);
+  }
+
+  #[test]
+  fn translucent_boxes_blend() {
+    let source = r#"<div>
+      <div style="width: 100px; height: 100px; background-color: #ff000080"></div>
+      <div style="width: 100px; height: 100px; margin-top: -50px; background-color: #0000ff80"></div>
+    </div>"#;
+    let image = render_html_to_image(source, 200, 200).unwrap();
+    // 白色背景上先叠加半透明红色，再叠加半透明蓝色
+    let [r, g, b, _] = image.get_pixel(50, 75).0;
+    assert!((120..=136).contains(&r), "{r}");
+    assert!((56..=72).contains(&g), "{g}");
+    assert!((184..=200).contains(&b), "{b}");
+  }
+
+  #[test]
+  fn transparent_border_is_skipped() {
+    let source = r#"<div style="width: 100px; height: 100px; border: 5px solid transparent; background-color: transparent"></div>"#;
+    assert!(get_commands(source, 200.0, 200.0).is_empty());
+  }
+
+  #[test]
+  fn tab_moves_focus_in_document_order() {
+    let layout_tree = get_layout_tree(r#"<div><a href="/a">a</a><a>plain</a><input><a href="/b">b</a></div>"#);
+    let root = layout_tree.get_layout_tree(get_viewport(400.0, 300.0), &Mutex::new(TextLayout::default()));
+    let focusables = get_focusables(&root);
+    assert_eq!(focusables.len(), 3);
+    assert_eq!(next_focus(&focusables, None, false), Some(0));
+    assert_eq!(next_focus(&focusables, Some(0), false), Some(1));
+    assert_eq!(next_focus(&focusables, Some(2), false), Some(0));
+    assert_eq!(next_focus(&focusables, Some(0), true), Some(2));
+  }
+
+  #[test]
+  fn hidden_box_keeps_space() {
+    let source = r#"<div>
+      <div style="visibility: hidden; height: 50px; background-color: #ff0000">hidden</div>
+      <div style="height: 10px; background-color: #0000ff"></div>
+    </div>"#;
+    let commands = get_commands(source, 200.0, 200.0);
+    assert_eq!(commands.len(), 1);
+    assert!(matches!(commands[0], DisplayCommand::Rectangle(CSSColor { b: 255, .. }, area) if area.y == 50.0));
+  }
+
+  #[test]
+  fn grayscale_filter_removes_color() {
+    let source = r#"<div style="width: 100px; height: 100px; background-color: #ff0000; filter: grayscale(1)"></div>"#;
+    let [r, g, b, _] = render_html_to_image(source, 100, 100).unwrap().get_pixel(50, 50).0;
+    assert_eq!(r, g);
+    assert_eq!(g, b);
+    assert!(r < 255);
+  }
+
+  #[test]
+  fn underline_paints_below_glyphs() {
+    let source = r#"<p style="font-size: 20px; color: #ff0000; text-decoration: underline">hello</p>"#;
+    let commands = get_commands(source, 200.0, 100.0);
+    let Some(DisplayCommand::Text(info)) = commands.iter().find(|command| matches!(command, DisplayCommand::Text(_))) else {
+      panic!("没有文本命令");
+    };
+    let baseline = (info.area.y + info.baseline) as u32;
+    let x = (info.area.x + info.area.width / 2.0) as u32;
+    let image = render_html_to_image(source, 200, 100).unwrap();
+    // `hello`没有下沉的笔画，基线以下的红色像素只能来自下划线
+    assert!((baseline + 1..baseline + 6).any(|y| image.get_pixel(x, y).0 == [255, 0, 0, 255]));
+  }
+
+  #[test]
+  fn shrinking_content_clamps_scroll() {
+    let (handle, mut state) = get_window_state(100.0);
+    state.content_height = 1000.0;
+    state.scroll_y = 800.0;
+    handle.sender.send(WindowMessage::ContentHeight(300.0)).unwrap();
+    state.receive_messages();
+    assert_eq!(state.scroll_y, 200.0);
+  }
+
+  #[test]
+  fn overflow_hidden_clips_child() {
+    let source = r#"<div style="width: 50px; height: 50px; overflow: hidden"><div style="width: 100px; height: 100px; background-color: #ff0000"></div></div>"#;
+    let image = render_html_to_image(source, 200, 200).unwrap();
+    assert_eq!(image.get_pixel(25, 25).0, [255, 0, 0, 255]);
+    assert_eq!(image.get_pixel(75, 75).0, [255, 255, 255, 255]);
+  }
+
+  #[test]
+  fn lower_z_index_paints_behind() {
+    let source = r#"<div>
+      <div style="position: fixed; top: 0; left: 0; width: 100px; height: 100px; z-index: 2; background-color: #ff0000"></div>
+      <div style="position: fixed; top: 0; left: 0; width: 100px; height: 100px; z-index: 1; background-color: #0000ff"></div>
+    </div>"#;
+    let image = render_html_to_image(source, 200, 200).unwrap();
+    assert_eq!(image.get_pixel(50, 50).0, [255, 0, 0, 255]);
+  }
+
+  #[test]
+  fn subtree_excludes_rest_of_page() {
+    let source = r#"<div>
+      <p style="background-color: #0000ff">page</p>
+      <div class="card" style="height: 20px; background-color: #ff0000">card</div>
+    </div>"#;
+    let layout_tree = get_layout_tree(source);
+    let text_layout = Mutex::new(TextLayout::default());
+    let subtree = layout_tree.get_subtree_layout(".card", get_viewport(200.0, 0.0), &text_layout).unwrap();
+    let commands = get_display_list(&subtree.layout_box);
+    assert_eq!(commands.len(), 2);
+    assert!(matches!(commands[0], DisplayCommand::Rectangle(RED, area) if area.y == 0.0));
+    assert!(matches!(&commands[1], DisplayCommand::Text(info) if info.glyphs.lock().unwrap().len() == 4));
+  }
+
+  #[test]
+  fn bordered_box_to_svg() {
+    let commands = get_commands(r#"<div style="width: 100px; height: 50px; border: 2px solid #ff0000; background-color: #00ff00"></div>"#, 200.0, 100.0);
+    let svg = display_list_to_svg(&commands, 200, 100);
+    assert!(svg.contains(r##"<rect x="2" y="2" width="100" height="50" fill="#00ff00"/>"##));
+    assert!(svg.contains(r##"<rect x="0" y="0" width="104" height="2" fill="#ff0000"/>"##));
+    assert!(svg.contains(r##"<rect x="0" y="0" width="2" height="54" fill="#ff0000"/>"##));
+    assert_eq!(svg.matches("fill=\"#ff0000\"").count(), 4);
+  }
+
+  #[test]
+  fn render_red_box_to_image() {
+    let image = render_html_to_image(r#"<div style="background-color:#ff0000;width:100px;height:100px"></div>"#, 200, 200).unwrap();
+    assert_eq!(image.get_pixel(50, 50).0, [255, 0, 0, 255]);
+  }
+
+  #[test]
+  fn render_options_resolve_relative_stylesheet() {
+    let dir = std::env::temp_dir().join(format!("toy-browser-render-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("style.css"), "div { background-color: #ff0000; }").unwrap();
+    let source = r#"<html><head><link rel="stylesheet" href="style.css"></head><body><div style="width: 100px; height: 100px"></div></body></html>"#;
+    let options = RenderOptions { base_url: Url::from_file_path(dir.join("index.html")).ok(), ..Default::default() };
+    let viewport = get_viewport(200.0, 200.0);
+    let image = render_document(source.to_string(), viewport, &options).unwrap();
+    assert_eq!(image.get_pixel(50, 50).0, [255, 0, 0, 255]);
+    // 没有页面地址时相对地址的样式表无法加载
+    let image = render_document(source.to_string(), viewport, &RenderOptions::default()).unwrap();
+    assert_eq!(image.get_pixel(50, 50).0, [255, 255, 255, 255]);
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn sticky_boxes_follow_z_index() {
+    // 正数的层叠顺序盖住后面的文档流内容，负数的则位于父级背景之下
+    let source = r#"<div style="background-color: #ffffff">
+      <div style="position: sticky; top: 0; height: 100px; z-index: 5; background-color: #ff0000"></div>
+      <div style="margin-top: -50px; height: 100px; background-color: #0000ff"></div>
+    </div>"#;
+    let image = render_html_to_image(source, 200, 200).unwrap();
+    assert_eq!(image.get_pixel(50, 75).0, [255, 0, 0, 255]);
+    let source = r#"<div>
+      <div style="overflow: hidden; height: 150px; background-color: #0000ff">
+        <div style="position: sticky; top: 0; height: 100px; z-index: -1; background-color: #ff0000"></div>
+      </div>
+    </div>"#;
+    let image = render_html_to_image(source, 200, 200).unwrap();
+    assert_eq!(image.get_pixel(50, 50).0, [0, 0, 255, 255]);
+  }
+
+  #[test]
+  fn sticky_layer_keeps_ancestor_clip() {
+    let source = r#"<div style="overflow: hidden; width: 50px; height: 50px">
+      <div style="position: sticky; top: 0; width: 100px; height: 100px; background-color: #ff0000"></div>
+    </div>"#;
+    let image = render_html_to_image(source, 200, 200).unwrap();
+    assert_eq!(image.get_pixel(25, 25).0, [255, 0, 0, 255]);
+    assert_eq!(image.get_pixel(75, 75).0, [255, 255, 255, 255]);
+  }
+
+  #[test]
+  fn gamma_raises_mid_gray_alpha() {
+    assert_eq!(apply_gamma(128, 1.0), 128);
+    assert_eq!(apply_gamma(128, 2.2), 186);
+    // 完全覆盖及完全不覆盖的像素不受gamma影响
+    assert_eq!(apply_gamma(0, 2.2), 0);
+    assert_eq!(apply_gamma(255, 2.2), 255);
+  }
+
+  #[test]
+  fn render_options_apply_gamma_to_text() {
+    let source = r#"<p style="font-size: 24px">gamma</p>"#;
+    let get_ink = |gamma: f32| -> u32 {
+      let options = RenderOptions { gamma, ..Default::default() };
+      let image = render_html_to_image_with(source, 200, 50, &options).unwrap();
+      image.pixels().map(|pixel| 255 - pixel[0] as u32).sum()
+    };
+    assert!(get_ink(2.2) > get_ink(1.0));
+  }
+}
@@ -0,0 +1,231 @@
+use std::{
+  cell::RefCell,
+  collections::HashMap,
+  rc::Rc
+};
+
+use boa_engine::{
+  builtins::JsArgs,
+  object::{FunctionBuilder, ObjectInitializer},
+  property::Attribute,
+  Context, JsResult, JsValue,
+};
+use gc::{unsafe_empty_trace, Finalize, Trace};
+
+use crate::dom::{Document, Node, NodeType};
+
+/// `Node.ELEMENT_NODE`
+const ELEMENT_NODE: i32 = 1;
+
+/// 暴露给脚本的元素信息（只读快照）
+#[derive(Debug, Clone)]
+struct ElementSnapshot {
+  tag_name: String,
+  text_content: String,
+  /// 从根节点到该元素依次经过的子节点下标，用于将修改应用回DOM
+  path: Vec<usize>
+}
+
+/// 脚本通过`setAttribute`对元素属性的修改
+#[derive(Debug, Clone)]
+struct AttributeMutation {
+  path: Vec<usize>,
+  name: String,
+  value: String
+}
+
+/// 脚本执行期间记录的所有修改，脚本全部执行完之后再按顺序应用到DOM上
+type Mutations = Rc<RefCell<Vec<AttributeMutation>>>;
+
+/// 按照`id`索引的元素信息，作为`getElementById`的闭包捕获值
+#[derive(Debug, Clone)]
+struct ElementIndex {
+  elements: HashMap<String, ElementSnapshot>,
+  mutations: Mutations
+}
+
+/// 元素的`setAttribute`方法的闭包捕获值
+#[derive(Debug, Clone)]
+struct ElementRef {
+  path: Vec<usize>,
+  mutations: Mutations
+}
+
+// 快照中只有字符串，不包含任何gc指针，无需追踪
+impl Finalize for ElementIndex {}
+unsafe impl Trace for ElementIndex {
+  unsafe_empty_trace!();
+}
+
+impl Finalize for ElementRef {}
+unsafe impl Trace for ElementRef {
+  unsafe_empty_trace!();
+}
+
+impl ElementIndex {
+  /// 深度优先遍历DOM树收集带有`id`的元素，`id`重复时以文档中第一个元素为准
+  fn new(root: &Node, mutations: Mutations) -> Self {
+    let mut elements = HashMap::new();
+    collect_elements(root, &mut vec![], &mut elements);
+    Self { elements, mutations }
+  }
+}
+
+fn collect_elements(node: &Node, path: &mut Vec<usize>, elements: &mut HashMap<String, ElementSnapshot>) {
+  if let NodeType::Element(elem) = &node.node_type {
+    if let Some(id) = elem.get_attribute("id") {
+      elements.entry(id.to_string()).or_insert_with(|| ElementSnapshot {
+        tag_name: elem.tag_name.to_uppercase(),
+        text_content: node.text_content(),
+        path: path.clone()
+      });
+    }
+  }
+  for (idx, child) in node.children.iter().enumerate() {
+    path.push(idx);
+    collect_elements(child, path, elements);
+    path.pop();
+  }
+}
+
+/// 按顺序将脚本记录的修改应用到DOM上
+fn apply_mutations(root: &mut Node, mutations: &[AttributeMutation]) {
+  for mutation in mutations {
+    let node = mutation.path
+      .iter()
+      .try_fold(&mut *root, |node, &idx| node.children.get_mut(idx));
+    if let Some(Node { node_type: NodeType::Element(elem), .. }) = node {
+      elem.set_attribute(&mutation.name, &mutation.value);
+    }
+  }
+}
+
+/// 按文档顺序收集所有`script`的源码
+fn collect_scripts<'a>(node: &'a Node, scripts: &mut Vec<&'a str>) {
+  if let NodeType::Script(source) = &node.node_type {
+    scripts.push(source);
+  }
+  for child in &node.children {
+    collect_scripts(child, scripts);
+  }
+}
+
+/// `element.setAttribute(name, value)`，修改在脚本全部执行完之后才会反映到DOM上
+fn set_attribute(_this: &JsValue, args: &[JsValue], element: &mut ElementRef, context: &mut Context) -> JsResult<JsValue> {
+  let name = args.get_or_undefined(0).to_string(context)?;
+  let value = args.get_or_undefined(1).to_string(context)?;
+  element.mutations.borrow_mut().push(AttributeMutation {
+    path: element.path.clone(),
+    name: name.to_lowercase(),
+    value: value.to_string()
+  });
+  Ok(JsValue::undefined())
+}
+
+/// `document.getElementById(id)`，找不到时返回`null`
+fn get_element_by_id(_this: &JsValue, args: &[JsValue], index: &mut ElementIndex, context: &mut Context) -> JsResult<JsValue> {
+  let id = args.get_or_undefined(0).to_string(context)?;
+  let element = match index.elements.get(id.as_str()) {
+    Some(element) => element,
+    None => return Ok(JsValue::null())
+  };
+  let element_ref = ElementRef { path: element.path.clone(), mutations: index.mutations.clone() };
+  let set_attribute = FunctionBuilder::closure_with_captures(context, set_attribute, element_ref)
+    .name("setAttribute")
+    .length(2)
+    .build();
+  let obj = ObjectInitializer::new(context)
+    .property("setAttribute", set_attribute, Attribute::READONLY)
+    .property("nodeType", ELEMENT_NODE, Attribute::READONLY | Attribute::ENUMERABLE)
+    .property("tagName", element.tag_name.clone(), Attribute::READONLY | Attribute::ENUMERABLE)
+    .property("textContent", element.text_content.clone(), Attribute::READONLY | Attribute::ENUMERABLE)
+    .build();
+  Ok(obj.into())
+}
+
+/// 创建脚本执行环境，注册全局的`document`对象
+///
+/// 只能通过`getElementById`查询元素的节点类型及文本，以及通过`setAttribute`修改元素属性
+fn create_context(document: &Document, mutations: Mutations) -> Context {
+  let mut context = Context::default();
+  let get_element_by_id = FunctionBuilder::closure_with_captures(&mut context, get_element_by_id, ElementIndex::new(&document.root, mutations))
+    .name("getElementById")
+    .length(1)
+    .build();
+  let document_obj = ObjectInitializer::new(&mut context)
+    .property("getElementById", get_element_by_id, Attribute::READONLY)
+    .property("title", document.title.clone().unwrap_or_default(), Attribute::READONLY | Attribute::ENUMERABLE)
+    .build();
+  context.register_global_property("document", document_obj, Attribute::READONLY);
+  context
+}
+
+/// 按文档顺序执行所有`script`，返回每个脚本的执行结果；某个脚本出错不影响后续脚本的执行
+///
+/// 脚本对元素属性的修改在所有脚本执行完之后应用到`document`上
+pub fn run_scripts(document: &mut Document) -> Vec<JsResult<JsValue>> {
+  let mut scripts = vec![];
+  collect_scripts(&document.root, &mut scripts);
+  if scripts.is_empty() {
+    return vec![];
+  }
+  let mutations = Mutations::default();
+  let mut context = create_context(document, mutations.clone());
+  let results = scripts
+    .into_iter()
+    .map(|source| {
+      let res = context.eval(source);
+      if let Err(err) = &res {
+        log::warn!("脚本执行出错：{}", err.display());
+      }
+      res
+    })
+    .collect();
+  apply_mutations(&mut document.root, &mutations.borrow());
+  results
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::html;
+
+  /// 解析`html`并执行其中的脚本，返回执行之后的`document`及每个脚本的结果
+  fn run(source: &str) -> (Document, Vec<JsResult<JsValue>>) {
+    let mut document = html::parse(source.to_string()).unwrap();
+    let results = run_scripts(&mut document);
+    (document, results)
+  }
+
+  #[test]
+  fn get_element_by_id_exposes_element() {
+    let (_, results) = run(r#"<div><p id="intro">hello <b>world</b></p><script>
+      var el = document.getElementById("intro");
+      [el.tagName, el.textContent, el.nodeType].join("|");
+    </script></div>"#);
+    assert_eq!(results[0].as_ref().unwrap().as_string().unwrap().as_str(), "P|hello world|1");
+  }
+
+  #[test]
+  fn get_element_by_id_returns_null_for_missing_id() {
+    let (_, results) = run(r#"<div><script>document.getElementById("missing")</script></div>"#);
+    assert!(results[0].as_ref().unwrap().is_null());
+  }
+
+  #[test]
+  fn error_does_not_stop_later_scripts() {
+    let (_, results) = run(r#"<div><script>throw new Error("boom");</script><script>1 + 1</script></div>"#);
+    assert_eq!(results.len(), 2);
+    assert!(results[0].is_err());
+    assert_eq!(results[1].as_ref().unwrap().as_number(), Some(2.0));
+  }
+
+  #[test]
+  fn set_attribute_applies_to_dom() {
+    let (document, _) = run(r#"<div><p id="target">text</p><script>document.getElementById("target").setAttribute("Class", "active")</script></div>"#);
+    let NodeType::Element(elem) = &document.root.children[0].node_type else {
+      panic!("{:?}", document.root.children[0].node_type);
+    };
+    assert_eq!(elem.get_attribute("class"), Some("active"));
+  }
+}
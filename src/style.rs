@@ -6,17 +6,39 @@ use crate::dom::{
 };
 use crate::css::{
   CSSValue,
+  CSSColor,
+  CSSUnit,
+  Filter,
+  TrackSize,
+  GridLine,
+  DEFAULT_FONT_SIZE,
   CSSSimpleSelector,
+  PseudoClass,
   Specificity,
   CSSRule,
+  CSSPropValue,
   Stylesheet,
   parse_inline_style,
+  parse_selectors,
+  parse_filters,
+  parse_track_list,
+  parse_grid_placement,
+  get_box_longhands,
 };
+use crate::fetch::History;
 use std::collections::HashMap;
 use std::sync::{ Arc, Weak, Mutex };
+use url::Url;
 
 type NodeStyle = HashMap<String, CSSValue>;
 
+/// 视窗尺寸，用于计算`vw`、`vh`等视窗单位
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Viewport {
+  pub width: f32,
+  pub height: f32
+}
+
 /// `style-tree`节点
 #[derive(Debug)]
 pub struct StyledNode<'a> {
@@ -24,56 +46,593 @@ pub struct StyledNode<'a> {
   pub children: Mutex<Vec<Arc<StyledNode<'a>>>>, // RefCell允许引用值可变：https://course.rs/advance/smart-pointer/cell-refcell.html
   /// 该节点命中的样式信息
   pub style: NodeStyle,
+  /// 该节点的计算值（目前只有`font-size`，为像素长度），`get_val`优先取这里的值
+  pub computed_style: NodeStyle,
+  /// 该节点声明的自定义属性（以`--`开头），总是可以继承
+  pub custom_props: NodeStyle,
+  /// 视窗尺寸
+  pub viewport: Viewport,
   /// 父级样式节点，用于继承
   pub parent: Option<Weak<StyledNode<'a>>> // 使用week可以有效避免Rc指针的循环引用（https://course.rs/advance/circle-self-ref/circle-reference.html#%E4%BD%BF%E7%94%A8-weak-%E8%A7%A3%E5%86%B3%E5%BE%AA%E7%8E%AF%E5%BC%95%E7%94%A8）
 }
 
 pub struct StyleTree {
   pub document: Document,
+  /// 浏览记录，用于匹配`:visited`
+  pub history: History,
+  /// 获得焦点的元素在所有可聚焦元素中的序号（见`Node::focusable_elements`），用于匹配`:focus`
+  pub focused: Option<usize>,
 }
 
 #[derive(Debug)]
 pub enum Display {
   Inline,
   Block,
+  InlineBlock,
+  /// 单行的弹性布局容器
+  Flex,
+  /// 网格布局容器
+  Grid,
   None
 }
 
+/// 浮动类型
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Float {
+  Left,
+  Right,
+  None
+}
+
+/// 定位方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Position {
+  /// 按照文档流布局
+  Static,
+  /// 脱离文档流，相对于视窗定位，不随页面滚动
+  Fixed,
+  /// 按照文档流布局，滚动越过`top`/`bottom`阈值后固定在视窗中，但不会移出包含块
+  Sticky
+}
+
+/// 清除浮动的方向
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Clear {
+  Left,
+  Right,
+  Both,
+  None
+}
+
+/// 行内内容的水平对齐方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextAlign {
+  Left,
+  Right,
+  Center,
+  Justify
+}
+
+/// 弹性布局中子项在主轴上的对齐方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JustifyContent {
+  FlexStart,
+  FlexEnd,
+  Center,
+  /// 首尾子项贴着容器边缘，子项之间的间距相等
+  SpaceBetween,
+  /// 每个子项两侧的间距相等，因此首尾的间距是子项之间的一半
+  SpaceAround,
+  /// 首尾及子项之间的间距都相等
+  SpaceEvenly
+}
+
+/// 弹性布局中子项在交叉轴上的对齐方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlignItems {
+  FlexStart,
+  FlexEnd,
+  Center,
+  /// 子项第一行文本的基线对齐，只对水平排列有效，竖直排列时等同于`flex-start`
+  Baseline,
+  /// 交叉轴上为`auto`尺寸的子项填满容器
+  Stretch
+}
+
+/// 弹性布局中多行之间在交叉轴上的分配方式，只对多行（`flex-wrap: wrap`）容器有效
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlignContent {
+  FlexStart,
+  FlexEnd,
+  Center,
+  SpaceBetween,
+  SpaceAround,
+  SpaceEvenly,
+  /// 剩余空间平分给每一行
+  Stretch
+}
+
+/// 弹性布局的子项是否可以换行
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlexWrap {
+  /// 所有子项排在同一行，放不下时溢出
+  NoWrap,
+  /// 放不下的子项排到新的一行
+  Wrap
+}
+
+/// 弹性布局的主轴方向
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlexDirection {
+  /// 子项水平排列
+  Row,
+  /// 子项竖直排列
+  Column
+}
+
+/// 行内级盒子在line box中的竖直对齐方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VerticalAlign {
+  Baseline,
+  Middle,
+  Top,
+  Bottom,
+  Sub,
+  Super,
+  TextTop,
+  TextBottom
+}
+
+/// 内容溢出盒子时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+  Visible,
+  /// 溢出`padding-box`的部分不绘制，`clip`、`scroll`及`auto`同样按此处理（暂不支持滚动条）
+  Hidden
+}
+
+/// 空白字符及换行的处理方式（`white-space`）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WhiteSpace {
+  /// 连续的空白合并为一个空格，在单词间自动换行
+  Normal,
+  /// 连续的空白合并为一个空格，但不自动换行，放不下时溢出
+  Nowrap,
+  /// 保留所有空白，只在换行符处换行
+  Pre
+}
+
+/// 文本装饰线（`text-decoration`），可以同时有多条
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TextDecoration {
+  pub underline: bool,
+  pub line_through: bool
+}
+
+/// `font-size`绝对尺寸关键字对应的字号相对于`medium`（即默认字号）的比例
+const FONT_SIZE_KEYWORDS: [(&str, f32); 8] = [
+  ("xx-small", 3.0 / 5.0),
+  ("x-small", 3.0 / 4.0),
+  ("small", 8.0 / 9.0),
+  ("medium", 1.0),
+  ("large", 6.0 / 5.0),
+  ("x-large", 3.0 / 2.0),
+  ("xx-large", 2.0),
+  ("xxx-large", 3.0)
+];
+
+/// `font-size: larger`/`smaller`相对于父级字号的缩放比例
+const FONT_SIZE_RELATIVE_SCALE: f32 = 1.2;
+
 /// 默认为可继承的样式属性
-static INHERIT_ATTRS: [&str; 1] = ["color"];
+///
+/// 节点没有设置这些属性时，`get_val`会沿着`parent`向上查找；不在列表中的属性（如`margin`）只取节点自身的值
+static INHERIT_ATTRS: [&str; 16] = [
+  "color",
+  "font-size",
+  "font-family",
+  "font-weight",
+  "font-style",
+  "font-variant",
+  "letter-spacing",
+  "word-spacing",
+  "line-height",
+  "text-align",
+  "text-transform",
+  "white-space",
+  "text-indent",
+  "cursor",
+  "visibility",
+  "list-style"
+];
+
+/// 获取样式属性的初始值（`initial`），未收录的属性返回`None`
+fn get_initial_val(name: &str) -> Option<CSSValue> {
+  let keyword = |val: &str| Some(CSSValue::Keyword(String::from(val)));
+  let zero = Some(CSSValue::Length(0.0, CSSUnit::Px));
+  match name {
+    "color" => Some(CSSValue::Color(CSSColor { r: 0, g: 0, b: 0, a: 255 })),
+    "background-color" => Some(CSSValue::Color(CSSColor { r: 0, g: 0, b: 0, a: 0 })),
+    "font-size" => Some(CSSValue::Length(DEFAULT_FONT_SIZE, CSSUnit::Px)),
+    "display" => keyword("inline"),
+    "float" | "clear" => keyword("none"),
+    "text-align" => keyword("left"),
+    "vertical-align" => keyword("baseline"),
+    "line-height" | "white-space" => keyword("normal"),
+    "width" | "height" => keyword("auto"),
+    "margin" | "margin-top" | "margin-right" | "margin-bottom" | "margin-left" => zero,
+    "padding" | "padding-top" | "padding-right" | "padding-bottom" | "padding-left" => zero,
+    "border-width" | "border-top-width" | "border-right-width" | "border-bottom-width" | "border-left-width" => zero,
+    "border-radius" => zero,
+    _ => None
+  }
+}
 
 impl<'a> StyledNode<'a> {
   /// 获取样式节点的某个样式属性值
+  ///
+  /// `inherit`取父级的值，`initial`取属性的初始值，`unset`对可继承的属性等同于`inherit`，否则等同于`initial`；
+  /// 可继承的属性没有设置时同样取父级的值
   pub fn get_val(&self, name: &str) -> Option<CSSValue> {
-    if INHERIT_ATTRS.contains(&name) {
-      return self.get_inherit_val(name);
+    if let Some(val) = self.computed_style.get(name) {
+      return Some(val.clone());
+    }
+    // 无法解析的`var()`视为没有设置该属性
+    let val = match self.style.get(name) {
+      Some(CSSValue::Var(var_name, fallback)) => self.resolve_var(var_name, fallback),
+      val => val.cloned()
+    };
+    match val {
+      Some(CSSValue::Inherit) => self.get_parent_val(name),
+      Some(CSSValue::Initial) => get_initial_val(name),
+      Some(CSSValue::Unset) => {
+        if INHERIT_ATTRS.contains(&name) {
+          self.get_parent_val(name)
+        } else {
+          get_initial_val(name)
+        }
+      },
+      Some(CSSValue::Length(length, CSSUnit::Vw)) => Some(CSSValue::Length(length / 100.0 * self.viewport.width, CSSUnit::Px)),
+      Some(CSSValue::Length(length, CSSUnit::Vh)) => Some(CSSValue::Length(length / 100.0 * self.viewport.height, CSSUnit::Px)),
+      Some(val) => Some(val),
+      None if INHERIT_ATTRS.contains(&name) => self.get_parent_val(name),
+      None => None
     }
-    self.style.get(name).map(|val| val.clone())
   }
 
-  /// 从style tree向上查找可继承的属性值
-  fn get_inherit_val(&self, name: &str) -> Option<CSSValue> {
-    let self_val = self.style.get(name);
-    if let None = self_val {
-      self.parent.as_ref()?.upgrade()?.get_inherit_val(name)
-    } else {
-      self_val.map(|val| val.clone())
+  /// 获取计算后的字号（像素）
+  pub fn get_font_size(&self) -> f32 {
+    match self.computed_style.get("font-size") {
+      Some(size) => size.to_px(),
+      None => DEFAULT_FONT_SIZE
+    }
+  }
+
+  /// 计算字号：`em`及百分比相对于父级计算后的字号，`rem`相对于根节点的字号；没有声明时继承父级的字号
+  ///
+  /// 绝对尺寸关键字（如`large`）按比例换算自默认字号，`larger`/`smaller`则相对于父级的字号
+  fn compute_font_size(&self) -> f32 {
+    let parent = self.parent.as_ref().and_then(|parent| parent.upgrade());
+    let parent_size = parent.as_ref().map(|parent| parent.get_font_size()).unwrap_or(DEFAULT_FONT_SIZE);
+    let val = match self.style.get("font-size") {
+      Some(CSSValue::Var(var_name, fallback)) => self.resolve_var(var_name, fallback),
+      val => val.cloned()
+    };
+    match val {
+      Some(CSSValue::Length(length, CSSUnit::Em)) => length * parent_size,
+      Some(CSSValue::Length(length, CSSUnit::Percent)) => length / 100.0 * parent_size,
+      Some(CSSValue::Length(length, CSSUnit::Rem)) => length * get_root_font_size(parent),
+      Some(CSSValue::Length(length, CSSUnit::Vw)) => length / 100.0 * self.viewport.width,
+      Some(CSSValue::Length(length, CSSUnit::Vh)) => length / 100.0 * self.viewport.height,
+      Some(CSSValue::Length(length, CSSUnit::Px)) => length,
+      Some(CSSValue::Initial) => DEFAULT_FONT_SIZE,
+      Some(CSSValue::Keyword(keyword)) => match &*keyword {
+        "larger" => parent_size * FONT_SIZE_RELATIVE_SCALE,
+        "smaller" => parent_size / FONT_SIZE_RELATIVE_SCALE,
+        keyword => FONT_SIZE_KEYWORDS
+          .iter()
+          .find(|(name, _)| *name == keyword)
+          .map_or(parent_size, |(_, scale)| scale * DEFAULT_FONT_SIZE)
+      },
+      _ => parent_size
+    }.max(0.0)
+  }
+
+  /// 从自身及祖先节点查找自定义属性
+  fn get_custom_prop(&self, name: &str) -> Option<CSSValue> {
+    match self.custom_props.get(name) {
+      Some(val) => Some(val.clone()),
+      None => self.parent.as_ref()?.upgrade()?.get_custom_prop(name)
     }
   }
 
+  /// 解析`var()`引用，自定义属性不存在时使用后备值
+  ///
+  /// 自定义属性之间循环引用（如`--a: var(--b); --b: var(--a)`）时无法解析，返回`CSSValue::Initial`，即取属性的初始值
+  fn resolve_var(&self, name: &str, fallback: &Option<Box<CSSValue>>) -> Option<CSSValue> {
+    self.resolve_var_chain(name, fallback, &mut Vec::new())
+  }
+
+  /// 沿着`var()`引用链解析，`visited`记录链上已经引用过的自定义属性
+  fn resolve_var_chain(&self, name: &str, fallback: &Option<Box<CSSValue>>, visited: &mut Vec<String>) -> Option<CSSValue> {
+    if visited.iter().any(|visited_name| visited_name == name) {
+      log::warn!("自定义属性循环引用：{} -> {}", visited.join(" -> "), name);
+      return Some(CSSValue::Initial);
+    }
+    visited.push(name.to_string());
+    let val = self.get_custom_prop(name).or_else(|| fallback.as_deref().cloned())?;
+    match val {
+      CSSValue::Var(name, fallback) => self.resolve_var_chain(&name, &fallback, visited),
+      val => Some(val)
+    }
+  }
+
+  /// 获取父级样式节点的属性值
+  fn get_parent_val(&self, name: &str) -> Option<CSSValue> {
+    self.parent.as_ref()?.upgrade()?.get_val(name)
+  }
+
   /// 获取样式节点的`display`类型
   pub fn get_display(&self) -> Display {
-    if let Some(CSSValue::Keyword(val)) = self.get_val("display") {
+    // 注释、`style`及`script`内容、文档类型声明永远不参与渲染，无论样式表怎么设置
+    if let NodeType::Comment(_) | NodeType::Style(_) | NodeType::Script(_) | NodeType::Doctype(_) = self.node.node_type {
+      return Display::None;
+    }
+    let display = if let Some(CSSValue::Keyword(val)) = self.get_val("display") {
       match &*val {
         "block" => Display::Block,
         "none" => Display::None,
+        "inline-block" => Display::InlineBlock,
+        "flex" => Display::Flex,
+        "grid" => Display::Grid,
         _ => Display::Inline
       }
     } else {
       Display::Inline
+    };
+    // 浮动元素、固定定位元素及弹性布局、网格布局容器的子元素（flex item、grid item）会被块级化；文本节点则由匿名块级box包裹
+    let is_element = matches!(self.node.node_type, NodeType::Element(_));
+    let is_flex_item = is_element && self.parent
+      .as_ref()
+      .and_then(|parent| parent.upgrade())
+      .is_some_and(|parent| matches!(parent.get_val("display"), Some(CSSValue::Keyword(val)) if val == "flex" || val == "grid"));
+    match (display, self.get_float()) {
+      (Display::Inline | Display::InlineBlock, _) if is_flex_item || self.get_position() == Position::Fixed => Display::Block,
+      (Display::Inline | Display::InlineBlock, Float::Left | Float::Right) => Display::Block,
+      (display, _) => display
+    }
+  }
+
+  /// 获取样式节点的`float`类型，固定定位的元素不会浮动
+  pub fn get_float(&self) -> Float {
+    if self.get_position() == Position::Fixed {
+      return Float::None;
+    }
+    if let Some(CSSValue::Keyword(val)) = self.get_val("float") {
+      match &*val {
+        "left" => Float::Left,
+        "right" => Float::Right,
+        _ => Float::None
+      }
+    } else {
+      Float::None
+    }
+  }
+
+  /// 获取样式节点的`position`类型
+  pub fn get_position(&self) -> Position {
+    match self.get_val("position") {
+      Some(CSSValue::Keyword(val)) if val == "fixed" => Position::Fixed,
+      Some(CSSValue::Keyword(val)) if val == "sticky" => Position::Sticky,
+      _ => Position::Static
+    }
+  }
+
+  /// 获取样式节点的`clear`类型
+  pub fn get_clear(&self) -> Clear {
+    if let Some(CSSValue::Keyword(val)) = self.get_val("clear") {
+      match &*val {
+        "left" => Clear::Left,
+        "right" => Clear::Right,
+        "both" => Clear::Both,
+        _ => Clear::None
+      }
+    } else {
+      Clear::None
+    }
+  }
+
+  /// 获取样式节点的`text-align`类型
+  pub fn get_text_align(&self) -> TextAlign {
+    if let Some(CSSValue::Keyword(val)) = self.get_val("text-align") {
+      match &*val {
+        "right" => TextAlign::Right,
+        "center" => TextAlign::Center,
+        "justify" => TextAlign::Justify,
+        _ => TextAlign::Left
+      }
+    } else {
+      TextAlign::Left
+    }
+  }
+
+  /// 获取样式节点的`justify-content`类型
+  pub fn get_justify_content(&self) -> JustifyContent {
+    if let Some(CSSValue::Keyword(val)) = self.get_val("justify-content") {
+      match &*val {
+        "flex-end" => JustifyContent::FlexEnd,
+        "center" => JustifyContent::Center,
+        "space-between" => JustifyContent::SpaceBetween,
+        "space-around" => JustifyContent::SpaceAround,
+        "space-evenly" => JustifyContent::SpaceEvenly,
+        _ => JustifyContent::FlexStart
+      }
+    } else {
+      JustifyContent::FlexStart
+    }
+  }
+
+  /// 是否可见；`visibility: hidden`（以及`collapse`）的元素仍然占据布局空间，只是不绘制
+  pub fn is_visible(&self) -> bool {
+    !matches!(self.get_val("visibility"), Some(CSSValue::Keyword(val)) if val == "hidden" || val == "collapse")
+  }
+
+  /// 获取`filter`中的滤镜列表，`none`或无法识别时为空
+  pub fn get_filters(&self) -> Vec<Filter> {
+    match self.get_val("filter") {
+      Some(CSSValue::Unknown(val)) => parse_filters(&val),
+      _ => Vec::new()
+    }
+  }
+
+  /// 获取作用于样式节点的`text-decoration`，如`underline line-through`
+  ///
+  /// 装饰线虽然不可继承，但祖先元素的装饰线会作用到其内部的所有文本，因此需要合并祖先节点的值
+  pub fn get_text_decoration(&self) -> TextDecoration {
+    let parent = self.parent
+      .as_ref()
+      .and_then(|parent| parent.upgrade())
+      .map(|parent| parent.get_text_decoration())
+      .unwrap_or_default();
+    let lines = match self.get_val("text-decoration") {
+      Some(CSSValue::Keyword(val) | CSSValue::Unknown(val)) => val,
+      _ => return parent
+    };
+    let lines: Vec<&str> = lines.split_whitespace().collect();
+    TextDecoration {
+      underline: parent.underline || lines.contains(&"underline"),
+      line_through: parent.line_through || lines.contains(&"line-through")
+    }
+  }
+
+  /// 获取样式节点的`overflow`
+  pub fn get_overflow(&self) -> Overflow {
+    match self.get_val("overflow") {
+      Some(CSSValue::Keyword(val)) if matches!(&*val, "hidden" | "clip" | "scroll" | "auto") => Overflow::Hidden,
+      _ => Overflow::Visible
+    }
+  }
+
+  /// 获取`white-space`
+  pub fn get_white_space(&self) -> WhiteSpace {
+    match self.get_val("white-space") {
+      Some(CSSValue::Keyword(val)) if val == "nowrap" => WhiteSpace::Nowrap,
+      Some(CSSValue::Keyword(val)) if val == "pre" => WhiteSpace::Pre,
+      _ => WhiteSpace::Normal
+    }
+  }
+
+  /// 获取`z-index`，`auto`返回`None`；只对定位元素生效，非定位元素同样返回`None`
+  pub fn get_z_index(&self) -> Option<i32> {
+    if self.get_position() == Position::Static {
+      return None;
+    }
+    match self.get_val("z-index") {
+      Some(CSSValue::Number(z_index)) => Some(z_index as i32),
+      _ => None
+    }
+  }
+
+  /// 获取`align-items`，默认为`stretch`
+  pub fn get_align_items(&self) -> AlignItems {
+    match self.get_val("align-items") {
+      Some(CSSValue::Keyword(val)) => match &*val {
+        "flex-start" => AlignItems::FlexStart,
+        "flex-end" => AlignItems::FlexEnd,
+        "center" => AlignItems::Center,
+        "baseline" => AlignItems::Baseline,
+        _ => AlignItems::Stretch
+      },
+      _ => AlignItems::Stretch
+    }
+  }
+
+  /// 获取`align-content`，默认为`stretch`
+  pub fn get_align_content(&self) -> AlignContent {
+    match self.get_val("align-content") {
+      Some(CSSValue::Keyword(val)) => match &*val {
+        "flex-start" => AlignContent::FlexStart,
+        "flex-end" => AlignContent::FlexEnd,
+        "center" => AlignContent::Center,
+        "space-between" => AlignContent::SpaceBetween,
+        "space-around" => AlignContent::SpaceAround,
+        "space-evenly" => AlignContent::SpaceEvenly,
+        _ => AlignContent::Stretch
+      },
+      _ => AlignContent::Stretch
     }
   }
 
+  /// 获取`flex-wrap`，`wrap-reverse`按`wrap`处理
+  pub fn get_flex_wrap(&self) -> FlexWrap {
+    match self.get_val("flex-wrap") {
+      Some(CSSValue::Keyword(val)) if val == "wrap" || val == "wrap-reverse" => FlexWrap::Wrap,
+      _ => FlexWrap::NoWrap
+    }
+  }
+
+  /// 获取`flex-direction`
+  pub fn get_flex_direction(&self) -> FlexDirection {
+    match self.get_val("flex-direction") {
+      Some(CSSValue::Keyword(val)) if val == "column" => FlexDirection::Column,
+      _ => FlexDirection::Row
+    }
+  }
+
+  /// 获取`flex-grow`，默认为0即不放大
+  pub fn get_flex_grow(&self) -> f32 {
+    match self.get_val("flex-grow") {
+      Some(CSSValue::Number(grow)) => grow.max(0.0),
+      _ => 0.0
+    }
+  }
+
+  /// 获取`grid-template-columns`的列轨道列表，`none`或未设置时为空
+  pub fn get_grid_template_columns(&self) -> Vec<TrackSize> {
+    match self.get_val("grid-template-columns") {
+      Some(CSSValue::Keyword(val) | CSSValue::Unknown(val)) if val != "none" => parse_track_list(&val),
+      _ => vec![]
+    }
+  }
+
+  /// 获取grid item在列方向上的起止网格线（`grid-column`）
+  pub fn get_grid_column(&self) -> (GridLine, GridLine) {
+    match self.get_val("grid-column") {
+      Some(CSSValue::Keyword(val) | CSSValue::Unknown(val)) => parse_grid_placement(&val),
+      _ => (GridLine::Auto, GridLine::Auto)
+    }
+  }
+
+  /// 获取grid item在行方向上的起止网格线（`grid-row`）
+  pub fn get_grid_row(&self) -> (GridLine, GridLine) {
+    match self.get_val("grid-row") {
+      Some(CSSValue::Keyword(val) | CSSValue::Unknown(val)) => parse_grid_placement(&val),
+      _ => (GridLine::Auto, GridLine::Auto)
+    }
+  }
+
+  /// 获取样式节点的`vertical-align`类型
+  pub fn get_vertical_align(&self) -> VerticalAlign {
+    if let Some(CSSValue::Keyword(val)) = self.get_val("vertical-align") {
+      match &*val {
+        "middle" => VerticalAlign::Middle,
+        "top" => VerticalAlign::Top,
+        "bottom" => VerticalAlign::Bottom,
+        "sub" => VerticalAlign::Sub,
+        "super" => VerticalAlign::Super,
+        "text-top" => VerticalAlign::TextTop,
+        "text-bottom" => VerticalAlign::TextBottom,
+        _ => VerticalAlign::Baseline
+      }
+    } else {
+      VerticalAlign::Baseline
+    }
+  }
+
+  /// 获取某个方向的长属性（如`margin-left`）的值，没有时取简写属性`init_key`（如`margin`）的值，都没有时取`init_val`
+  ///
+  /// 简写属性在解析时已经展开为长属性，只有包含`var()`的简写属性会原样保留（见`css::Parser::parse_prop_value`）；
+  /// 层叠时这样的简写属性会覆盖之前的长属性，而自定义属性只有单个值，因此其解析后的值作用于所有方向
   pub fn look_up(&self, key: &str, init_key: &str, init_val: &CSSValue) -> CSSValue {
     self
       .get_val(key)
@@ -86,9 +645,81 @@ impl<'a> StyledNode<'a> {
 
 type MatchedRule<'a> = (Specificity, &'a CSSRule);
 
+/// 生成`style tree`所需的页面信息
+struct StyleContext<'a> {
+  /// 页面地址，链接的相对地址基于此解析
+  base_url: Option<&'a Url>,
+  history: &'a History,
+  viewport: Viewport,
+  /// 获得焦点的元素
+  focused: Option<&'a Node>
+}
+
+impl StyleContext<'_> {
+  /// 判断链接是否访问过，无法解析的地址视为未访问
+  fn is_visited(&self, href: &str) -> bool {
+    let url = match self.base_url {
+      Some(base_url) => base_url.join(href),
+      None => Url::parse(href)
+    };
+    url.is_ok_and(|url| self.history.is_visited(&url))
+  }
+}
+
+/// 元素匹配伪类所需的状态：在父级的元素子节点中的位置（用于`:first-child`等结构伪类）、链接及焦点状态
+#[derive(Debug, Clone, Copy, Default)]
+struct ElementState {
+  is_first: bool,
+  is_last: bool,
+  /// 是否为带有`href`的`<a>`
+  is_link: bool,
+  is_visited: bool,
+  is_focused: bool
+}
+
+impl ElementState {
+  /// 计算`node`在`parent`的元素子节点（不包括文本、注释等节点）中的位置；根节点不命中任何结构伪类
+  fn new(node: &Node, parent: Option<&Node>, context: &StyleContext) -> Self {
+    let href = match &node.node_type {
+      NodeType::Element(element) if element.tag_name == "a" => element.attrs.get("href"),
+      _ => None
+    };
+    let is_link = href.is_some();
+    let is_visited = href.is_some_and(|href| context.is_visited(href));
+    let is_focused = context.focused.is_some_and(|focused| std::ptr::eq(focused, node));
+    let siblings: Vec<&Node> = match parent {
+      Some(parent) => parent.children
+        .iter()
+        .filter(|child| matches!(child.node_type, NodeType::Element(_)))
+        .collect(),
+      None => return Self { is_link, is_visited, is_focused, ..Self::default() }
+    };
+    Self {
+      is_first: siblings.first().is_some_and(|first| std::ptr::eq(*first, node)),
+      is_last: siblings.last().is_some_and(|last| std::ptr::eq(*last, node)),
+      is_link,
+      is_visited,
+      is_focused
+    }
+  }
+
+  /// 判断是否命中伪类
+  fn matches(&self, pseudo_class: &PseudoClass) -> bool {
+    match pseudo_class {
+      PseudoClass::FirstChild => self.is_first,
+      PseudoClass::LastChild => self.is_last,
+      PseudoClass::Link => self.is_link && !self.is_visited,
+      PseudoClass::Visited => self.is_link && self.is_visited,
+      PseudoClass::Focus => self.is_focused,
+      PseudoClass::Unsupported(_) => false
+    }
+  }
+}
+
 /// 判断简单选择器`selector`是否命中`element`节点
-fn match_selector(element: &ElementData, selector: &CSSSimpleSelector) -> bool {
-  if selector.tag.iter().any(|name| element.tag_name != *name) {
+fn match_selector(element: &ElementData, state: ElementState, selector: &CSSSimpleSelector) -> bool {
+  // 通配选择器命中任意标签
+  if !selector.universal && selector.tag.iter().any(|name| element.tag_name != *name) {
     return false;
   }
 
@@ -105,62 +736,144 @@ fn match_selector(element: &ElementData, selector: &CSSSimpleSelector) -> bool {
     return false;
   }
 
+  let is_attr_matched = selector.attribute_selectors
+    .iter()
+    .all(|attr| element.attrs.get(&attr.name).is_some_and(|val| attr.matches(val)));
+  if !is_attr_matched {
+    return false;
+  }
+
+  if selector.pseudo_classes.iter().any(|pseudo_class| !state.matches(pseudo_class)) {
+    return false;
+  }
+
   true
 }
 
-/// 从单个规则中匹配节点样式
-fn match_rule<'a>(element: &ElementData, rule: &'a CSSRule) -> Option<MatchedRule<'a>> {
+/// 找到单个规则中命中`element`节点的选择器
+fn find_matched_selector<'a>(element: &ElementData, state: ElementState, rule: &'a CSSRule) -> Option<&'a CSSSimpleSelector> {
   rule.selectors
     .iter()
-    .find(|selector| match_selector(element, &selector)) // 规则中只要有一个选择器命中就算命中了
+    .find(|selector| match_selector(element, state, selector)) // 规则中只要有一个选择器命中就算命中了
+}
+
+/// 从单个规则中匹配节点样式
+fn match_rule<'a>(element: &ElementData, state: ElementState, rule: &'a CSSRule) -> Option<MatchedRule<'a>> {
+  find_matched_selector(element, state, rule)
     .map(|selector| (selector.get_specificity(), rule))
 }
 
 /// 从多个规则中匹配节点样式
-fn match_rules<'a>(element: &ElementData, stylesheet: &'a Stylesheet) -> Vec<MatchedRule<'a>> {
+fn match_rules<'a>(element: &ElementData, state: ElementState, stylesheet: &'a Stylesheet) -> Vec<MatchedRule<'a>> {
   stylesheet.rules
     .iter()
-    .filter_map(|rule| match_rule(element, rule))
+    .filter_map(|rule| match_rule(element, state, rule))
     .collect()
 }
 
+/// 将逻辑属性映射为对应的物理属性，其它属性原样返回
+///
+/// 目前只考虑水平书写模式（`horizontal-tb`）及从左到右的方向，即`inline`方向为水平方向
+fn get_physical_props(prop: &str) -> Vec<&str> {
+  match prop {
+    "inline-size" => vec!["width"],
+    "block-size" => vec!["height"],
+    "min-inline-size" => vec!["min-width"],
+    "max-inline-size" => vec!["max-width"],
+    "min-block-size" => vec!["min-height"],
+    "max-block-size" => vec!["max-height"],
+    "margin-inline" => vec!["margin-left", "margin-right"],
+    "margin-block" => vec!["margin-top", "margin-bottom"],
+    "margin-inline-start" => vec!["margin-left"],
+    "margin-inline-end" => vec!["margin-right"],
+    "margin-block-start" => vec!["margin-top"],
+    "margin-block-end" => vec!["margin-bottom"],
+    "padding-inline" => vec!["padding-left", "padding-right"],
+    "padding-block" => vec!["padding-top", "padding-bottom"],
+    "padding-inline-start" => vec!["padding-left"],
+    "padding-inline-end" => vec!["padding-right"],
+    "padding-block-start" => vec!["padding-top"],
+    "padding-block-end" => vec!["padding-bottom"],
+    _ => vec![prop]
+  }
+}
+
 /// 从多个样式表中匹配节点样式
-fn specified_values(element: &ElementData, stylesheets: &Vec<Stylesheet>) -> NodeStyle {
+fn specified_values(element: &ElementData, state: ElementState, stylesheets: &Vec<Arc<Stylesheet>>) -> NodeStyle {
   let mut style = HashMap::new();
+  // 逻辑属性在层叠时就转为物理属性，这样才能与物理属性按照声明顺序互相覆盖
+  let mut insert = |prop_value: &CSSPropValue| {
+    // 保留下来的简写属性（包含`var()`）需要覆盖之前声明的长属性
+    for longhand in get_box_longhands(&prop_value.prop).into_iter().flatten() {
+      style.remove(&longhand);
+    }
+    for prop in get_physical_props(&prop_value.prop) {
+      style.insert(prop.to_string(), prop_value.value.clone());
+    }
+  };
   let mut rules = vec!();
   for stylesheet in stylesheets {
-    let mut res = match_rules(element, stylesheet);
+    let mut res = match_rules(element, state, stylesheet);
     rules.append(&mut res);
   }
   rules.sort_by(|&(a, _), &(b, _)| a.cmp(&b)); // 对命中的规则按照优先级从低到高进行排序（这样便于优先级高的进行覆盖）
-  for (_, rule) in rules {
-    for prop_value in &rule.prop_value_set {
-      style.insert(prop_value.prop.clone(), prop_value.value.clone());
-    }
-  }
-  if element.attrs.contains_key("style") { // 最后解析内联样式（优先级最高，目前不考虑!important）
-    let empty_str = String::from("");
-    let style_content = element.attrs.get("style").unwrap_or(&empty_str);
-    let prop_value_set = parse_inline_style(style_content.clone());
-    for prop_value in &prop_value_set {
-      style.insert(prop_value.prop.clone(), prop_value.value.clone());
+  // 最后解析内联样式（优先级最高）
+  let inline_style = element.attrs
+    .get("style")
+    .map(|style_content| parse_inline_style(style_content.clone()))
+    .unwrap_or_default();
+  let declarations: Vec<&CSSPropValue> = rules
+    .iter()
+    .flat_map(|(_, rule)| rule.prop_value_set.iter())
+    .chain(inline_style.iter())
+    .collect();
+  // `!important`声明的优先级高于所有普通声明，因此在普通声明之后按照同样的顺序再覆盖一遍
+  for important in [false, true] {
+    for prop_value in declarations.iter().filter(|prop_value| prop_value.important == important) {
+      insert(prop_value);
     }
   }
   style
 }
 
+/// 获取根节点计算后的字号（`rem`的参照），`node`为`None`时即为默认字号
+fn get_root_font_size(node: Option<Arc<StyledNode>>) -> f32 {
+  match node {
+    Some(node) => match node.parent.as_ref().and_then(|parent| parent.upgrade()) {
+      Some(parent) => get_root_font_size(Some(parent)),
+      None => node.get_font_size()
+    },
+    None => DEFAULT_FONT_SIZE
+  }
+}
+
 /// 递归方法，从`DOM tree`根节点进行样式匹配，生成对应的`style tree`
-fn style_tree<'a>(root: &'a Node, stylesheets: &'a Vec<Stylesheet>, parent: Option<Weak<StyledNode<'a>>>) -> Arc<StyledNode<'a>> {
-  let styled_node = Arc::new(StyledNode {
-    node: root,
-    style: match root.node_type {
-      NodeType::Element(ref element) => specified_values(element, stylesheets),
-      NodeType::Text(_) => HashMap::new(),
-      _ => HashMap::new()
+fn style_tree<'a>(root: &'a Node, stylesheets: &'a Vec<Arc<Stylesheet>>, context: &StyleContext, parent: Option<Weak<StyledNode<'a>>>) -> Arc<StyledNode<'a>> {
+  let values = match root.node_type {
+    NodeType::Element(ref element) => {
+      let parent_node = parent.as_ref().and_then(|parent| parent.upgrade());
+      let state = ElementState::new(root, parent_node.as_ref().map(|parent| parent.node), context);
+      specified_values(element, state, stylesheets)
     },
+    NodeType::Text(_) => HashMap::new(),
+    _ => HashMap::new()
+  };
+  // 自定义属性单独存放
+  let (custom_props, style): (NodeStyle, NodeStyle) = values
+    .into_iter()
+    .partition(|(name, _)| name.starts_with("--"));
+  let mut styled_node = StyledNode {
+    node: root,
+    style,
+    computed_style: HashMap::new(),
+    custom_props,
+    viewport: context.viewport,
     children: Mutex::new(vec![]),
     parent
-  });
+  };
+  let font_size = styled_node.compute_font_size();
+  styled_node.computed_style.insert(String::from("font-size"), CSSValue::Length(font_size, CSSUnit::Px));
+  let styled_node = Arc::new(styled_node);
 
   let mut children = styled_node.children.lock().unwrap(); // 获取互斥锁
 
@@ -168,12 +881,12 @@ fn style_tree<'a>(root: &'a Node, stylesheets: &'a Vec<Stylesheet>, parent: Opti
     .iter()
     .filter_map(|child| if let NodeType::Element(elem) = &child.node_type {
       if elem.tag_name == "head" {
-        None // 跳过head的解析
+        None // head不参与渲染，其中的title等信息在解析html时已经提取到`Document`中
       } else {
-        Some(style_tree(child, stylesheets, Some(Arc::downgrade(&styled_node)))) // 弱引用
+        Some(style_tree(child, stylesheets, context, Some(Arc::downgrade(&styled_node)))) // 弱引用
       }
     } else {
-      Some(style_tree(child, stylesheets, Some(Arc::downgrade(&styled_node))))
+      Some(style_tree(child, stylesheets, context, Some(Arc::downgrade(&styled_node))))
     })
     .collect();
 
@@ -182,10 +895,156 @@ fn style_tree<'a>(root: &'a Node, stylesheets: &'a Vec<Stylesheet>, parent: Opti
   styled_node
 }
 
+/// 深度优先查找第一个命中任一选择器的元素，同时返回其在兄弟节点中的位置
+fn find_element<'a>(root: &'a Node, parent: Option<&Node>, selectors: &Vec<CSSSimpleSelector>, context: &StyleContext) -> Option<(&'a ElementData, ElementState)> {
+  if let NodeType::Element(element) = &root.node_type {
+    let state = ElementState::new(root, parent, context);
+    if selectors.iter().any(|selector| match_selector(element, state, selector)) {
+      return Some((element, state));
+    }
+  }
+  root.children
+    .iter()
+    .find_map(|child| find_element(child, Some(root), selectors, context))
+}
+
+/// 深度优先查找第一个命中任一选择器的样式节点
+fn find_styled_node<'a>(styled_node: &Arc<StyledNode<'a>>, parent: Option<&Node>, selectors: &Vec<CSSSimpleSelector>, context: &StyleContext) -> Option<Arc<StyledNode<'a>>> {
+  if let NodeType::Element(element) = &styled_node.node.node_type {
+    let state = ElementState::new(styled_node.node, parent, context);
+    if selectors.iter().any(|selector| match_selector(element, state, selector)) {
+      return Some(styled_node.clone());
+    }
+  }
+  styled_node.children
+    .lock()
+    .unwrap()
+    .iter()
+    .find_map(|child| find_styled_node(child, Some(styled_node.node), selectors, context))
+}
+
 impl StyleTree {
-  /// 根据文档对象生成对应的`style tree`
-  pub fn get_style_tree<'a>(&'a self) -> Arc<StyledNode<'a>> {
+  fn context(&self, viewport: Viewport) -> StyleContext<'_> {
+    StyleContext {
+      base_url: self.document.url.as_ref(),
+      history: &self.history,
+      viewport,
+      focused: self.focused.and_then(|idx| self.document.root.focusable_elements().get(idx).copied())
+    }
+  }
+
+  /// 根据文档对象生成对应的`style tree`，视窗单位基于`viewport`计算
+  pub fn get_style_tree<'a>(&'a self, viewport: Viewport) -> Arc<StyledNode<'a>> {
     // 这里数据的所有权怎么处理？ -> 将引用数据转为内部数据
-    style_tree(&self.document.root, &self.document.stylesheets, None)
+    style_tree(&self.document.root, &self.document.stylesheets, &self.context(viewport), None)
+  }
+
+  /// 在`style_tree`（由`get_style_tree`生成）中查找第一个命中`selector`的元素的样式节点
+  ///
+  /// 样式节点的继承依赖祖先节点，调用方需要在使用期间一直持有`style_tree`
+  pub fn query_styled_node<'a>(&'a self, style_tree: &Arc<StyledNode<'a>>, selector: &str) -> Option<Arc<StyledNode<'a>>> {
+    let selectors = parse_selectors(selector.to_string());
+    find_styled_node(style_tree, None, &selectors, &self.context(style_tree.viewport))
+  }
+
+  /// 查询第一个命中`selector`的元素所命中的所有规则（类似devtools的样式面板）
+  ///
+  /// 返回值按照层叠顺序（优先级从低到高）排列，每一项为命中规则的选择器优先级及选择器文本
+  pub fn matched_rules(&self, selector: &str) -> Vec<(Specificity, String)> {
+    let selectors = parse_selectors(selector.to_string());
+    let (element, state) = match find_element(&self.document.root, None, &selectors, &self.context(Viewport::default())) {
+      Some(res) => res,
+      None => return vec![]
+    };
+    let mut res: Vec<(Specificity, String)> = self.document.stylesheets
+      .iter()
+      .flat_map(|stylesheet| stylesheet.rules.iter())
+      .filter_map(|rule| find_matched_selector(element, state, rule))
+      .map(|selector| (selector.get_specificity(), selector.to_string()))
+      .collect();
+    res.sort_by_key(|(specificity, _)| *specificity);
+    res
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::html;
+
+  fn get_style_tree(source: &str) -> StyleTree {
+    StyleTree { document: html::parse(source.to_string()).unwrap(), history: Default::default(), focused: None }
+  }
+
+  /// 查找第一个命中`selector`的元素的样式节点交给`f`检查
+  fn with_styled_node(source: &str, selector: &str, f: impl FnOnce(&StyledNode)) {
+    let tree = get_style_tree(source);
+    let root = tree.get_style_tree(Viewport { width: 800.0, height: 600.0 });
+    let node = tree.query_styled_node(&root, selector).unwrap();
+    f(&node);
+  }
+
+  #[test]
+  fn matched_rules_report_specificity() {
+    let tree = get_style_tree(r#"<div><style>p { color: red; } .note { color: blue; } #other { color: green; }</style><p class="note">text</p></div>"#);
+    // 内置样式表中命中的规则排在前面
+    assert!(tree.matched_rules("p").ends_with(&[
+      ((0, 0, 1), String::from("p")),
+      ((0, 1, 0), String::from(".note"))
+    ]));
+  }
+
+  #[test]
+  fn cyclic_var_resolves_to_initial() {
+    let source = r#"<div style="color: #ff0000"><style>p { --a: var(--b); --b: var(--a); color: var(--a); }</style><p>text</p></div>"#;
+    with_styled_node(source, "p", |node| {
+      assert_eq!(node.get_val("color"), get_initial_val("color"));
+    });
+  }
+
+  #[test]
+  fn universal_with_class_matches() {
+    let source = r#"<div><style>*.active { color: #0000ff; } p { color: #ff0000; }</style><p class="active">text</p></div>"#;
+    with_styled_node(source, "p", |node| {
+      assert_eq!(node.get_val("color"), Some(CSSValue::Color(CSSColor { r: 0, g: 0, b: 255, a: 255 })));
+    });
+  }
+
+  #[test]
+  fn text_align_inherits_to_nested_block() {
+    with_styled_node(r#"<body style="text-align: center"><div><p>text</p></div></body>"#, "p", |node| {
+      assert_eq!(node.get_text_align(), TextAlign::Center);
+    });
+  }
+
+  #[test]
+  fn inherit_overrides_matching_rule() {
+    let source = r#"<div style="color: #00ff00"><style>p { color: #ff0000; }</style><p style="color: inherit">text</p></div>"#;
+    with_styled_node(source, "p", |node| {
+      assert_eq!(node.get_val("color"), Some(CSSValue::Color(CSSColor { r: 0, g: 255, b: 0, a: 255 })));
+    });
+  }
+
+  #[test]
+  fn link_matches_only_anchors_with_href() {
+    let source = r#"<div><style>a:link { color: #ff0000; }</style><a id="with" href="/page">link</a><a id="without">anchor</a></div>"#;
+    let red = Some(CSSValue::Color(CSSColor { r: 255, g: 0, b: 0, a: 255 }));
+    with_styled_node(source, "#with", |node| assert_eq!(node.get_val("color"), red));
+    with_styled_node(source, "#without", |node| assert_ne!(node.get_val("color"), red));
+  }
+
+  #[test]
+  fn look_up_per_side_values() {
+    let zero = CSSValue::Length(0.0, CSSUnit::Px);
+    let source = r#"<div><p id="short" style="margin: 1px 2px 3px 4px">a</p><p id="long" style="margin-top: 1px; margin-right: 2px; margin-bottom: 3px; margin-left: 4px">b</p></div>"#;
+    for selector in ["#short", "#long"] {
+      with_styled_node(source, selector, |node| {
+        let sides: Vec<CSSValue> = ["margin-top", "margin-right", "margin-bottom", "margin-left"]
+          .iter()
+          .map(|key| node.look_up(key, "margin", &zero))
+          .collect();
+        assert_eq!(sides, [1.0, 2.0, 3.0, 4.0].map(|px| CSSValue::Length(px, CSSUnit::Px)));
+      });
+    }
   }
 }
@@ -3,11 +3,31 @@ use std::sync::{Arc, Mutex};
 use std::sync::mpsc::{self, Sender};
 use std::thread::{self, JoinHandle};
 
+use url::Url;
+
 use crate::dom::{Document};
-use crate::{html, style, layout, raster};
+use crate::fetch::History;
+use crate::font::TextLayout;
+use crate::{html, style, layout, raster, script};
 use crate::layout::{LayoutTree};
 use crate::style::{StyleTree};
 
+/// 流水线的输入：`html`源码、焦点及窗口尺寸
+type PipelineInput = (String, Option<usize>, (f32, f32));
+
+/// 输入与上一次相比是否有变化，有变化时记录为新的`last_input`
+///
+/// 直接比较源码本身而不是哈希值，不会因为哈希碰撞漏掉更新；源码不同时通常在开头就能比较出来
+fn is_input_changed(last_input: &mut Option<PipelineInput>, source: &str, focus: Option<usize>, size: (f32, f32)) -> bool {
+  if let Some((last_source, last_focus, last_size)) = last_input {
+    if last_source == source && *last_focus == focus && *last_size == size {
+      return false;
+    }
+  }
+  *last_input = Some((source.to_string(), focus, size));
+  true
+}
+
 pub struct PageThread {
   pub html_sender: Sender<String>,
   // style_sender: Sender<Document>,
@@ -17,7 +37,9 @@ pub struct PageThread {
   style_thread: JoinHandle<()>,
   layout_thread: JoinHandle<()>,
   raster_thread: JoinHandle<()>,
-  pub raster_window: Arc<Mutex<raster::RasterWindow>>
+  pub raster_window: Arc<Mutex<raster::RasterWindow>>,
+  /// 浏览记录，在所有页面间共享
+  pub history: Arc<Mutex<History>>
 }
 
 // impl<T> ThreadInfo<T> {
@@ -28,7 +50,8 @@ pub struct PageThread {
 // }
 
 impl PageThread {
-  pub fn new(viewport: layout::Box, id: String) -> Self {
+  /// `base_url`为页面地址，用于解析页面内外部资源的相对地址
+  pub fn new(viewport: layout::Box, id: String, base_url: Option<Url>) -> Self {
     let (html_sender, html_recevier) = mpsc::channel::<String>();
     let (style_sender, style_recevier) = mpsc::channel::<Document>();
     let (layout_sender, layout_recevier) = mpsc::channel::<StyleTree>();
@@ -37,12 +60,35 @@ impl PageThread {
     // let raster_local_sender = raster_sender.clone();
     let document_store: Arc<Mutex<Option<Document>>> = Arc::new(Mutex::new(None));
     let document_data = document_store.clone();
-    let raster_window = Arc::new(Mutex::new(raster::RasterWindow::new(id)));
-    let raster_window_store = raster_window.clone();
+    let history = Arc::new(Mutex::new(History::default()));
+    let history_data = history.clone();
+    // 文字布局模块由布局和光栅化共享，通过互斥锁保证串行访问
+    let text_layout = Arc::new(Mutex::new(TextLayout::default()));
+    let window = raster::RasterWindow::new(id, text_layout.clone());
+    *window.size.lock().unwrap() = (viewport.content.width, viewport.content.height);
+    let raster_window = Arc::new(Mutex::new(window));
+    let window_handle = raster_window.lock().unwrap().handle();
+    let focus = raster_window.lock().unwrap().focus.clone();
+    let focus_data = focus.clone();
+    let size = raster_window.lock().unwrap().size.clone();
+    let size_data = size.clone();
 
     let html_thread = thread::spawn(move || {
+      // 上一次解析的源码、焦点及窗口尺寸，都没有变化时跳过后续整个流水线
+      let mut last_input = None;
       for msg in html_recevier {
-        let document = html::parse(msg);
+        if !is_input_changed(&mut last_input, &msg, *focus.lock().unwrap(), *size.lock().unwrap()) {
+          continue;
+        }
+        // 无法解析的源码不会更新页面，保留上一次的渲染结果
+        let mut document = match html::parse_with_base(msg, base_url.clone()) {
+          Ok(document) => document,
+          Err(err) => {
+            log::error!("{err}");
+            continue;
+          }
+        };
+        script::run_scripts(&mut document);
         style_sender.send(document).unwrap();
       }
     });
@@ -57,7 +103,9 @@ impl PageThread {
         if document_ref.is_some() {
           let document = document_ref.take().unwrap(); // Option的take方法可以直接拿走Some数据：https://stackoverflow.com/questions/30573188/cannot-move-data-out-of-a-mutex
           let style_tree = style::StyleTree {
-            document
+            document,
+            history: history_data.lock().unwrap().clone(),
+            focused: *focus_data.lock().unwrap()
           };
           layout_sender.send(style_tree).unwrap();
         }
@@ -74,10 +122,24 @@ impl PageThread {
     });
 
     let raster_thread = thread::spawn(move || {
+      let mut last_title: Option<String> = None;
       for layout_tree in raster_recevier {
-        let mut raster_window_ref = raster_window_store.lock().unwrap();
-        raster_window_ref.raster(&layout_tree.get_layout_tree(viewport));
-        drop(raster_window_ref);
+        // 页面标题变化时才更新窗口标题
+        if let Some(title) = &layout_tree.style_tree.document.title {
+          if last_title.as_ref() != Some(title) {
+            last_title = Some(title.clone());
+            if window_handle.set_title(title.clone()).is_err() {
+              break;
+            }
+          }
+        }
+        // 视窗尺寸随窗口缩放变化
+        let mut viewport = viewport;
+        (viewport.content.width, viewport.content.height) = *size_data.lock().unwrap();
+        // 窗口关闭后不再需要光栅化
+        if window_handle.raster(&layout_tree.get_layout_tree(viewport, &text_layout)).is_err() {
+          break;
+        }
       }
     });
 
@@ -87,7 +149,8 @@ impl PageThread {
       style_thread,
       layout_thread,
       raster_thread,
-      raster_window
+      raster_window,
+      history
     }
   }
 
@@ -166,4 +229,20 @@ impl PageThread {
     self.layout_thread.join()?;
     self.raster_thread.join()
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn skip_unchanged_input() {
+    let mut last_input = None;
+    assert!(is_input_changed(&mut last_input, "<p>1</p>", None, (800.0, 600.0)));
+    assert!(!is_input_changed(&mut last_input, "<p>1</p>", None, (800.0, 600.0)));
+    assert!(is_input_changed(&mut last_input, "<p>2</p>", None, (800.0, 600.0)));
+    assert!(is_input_changed(&mut last_input, "<p>2</p>", Some(0), (800.0, 600.0)));
+    assert!(is_input_changed(&mut last_input, "<p>2</p>", Some(0), (1024.0, 600.0)));
+    assert!(!is_input_changed(&mut last_input, "<p>2</p>", Some(0), (1024.0, 600.0)));
+  }
+}